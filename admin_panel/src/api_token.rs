@@ -0,0 +1,168 @@
+use std::{
+    path::PathBuf,
+    sync::{Mutex, PoisonError},
+};
+
+use axum::http::Method;
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::ApiTokenError,
+    models::auth::{Role, Scope},
+    password::{constant_time_eq, random_string, sha256_hex},
+    store,
+};
+
+/// Makes the tokens easy to recognize, e.g. by secret scanners.
+static TOKEN_PREFIX: &str = "mcp_";
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ApiToken {
+    pub id: String,
+    pub name: String,
+    /// The token can't do more than the user who created it.
+    pub owner: String,
+    pub scopes: Vec<Scope>,
+    pub created: i64,
+    pub expires: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredToken {
+    #[serde(flatten)]
+    token: ApiToken,
+    /// SHA-256 of the token, hex encoded.
+    hash: String,
+}
+
+/// Long-lived tokens for scripts and bots, persisted in a JSON file. Only hashes of the tokens are stored.
+pub struct ApiTokens {
+    path: PathBuf,
+    tokens: Mutex<Vec<StoredToken>>,
+}
+
+impl ApiTokens {
+    pub fn load(path: PathBuf) -> Result<Self, ApiTokenError> {
+        let tokens = store::load(&path)?;
+        Ok(Self {
+            path,
+            tokens: Mutex::new(tokens),
+        })
+    }
+
+    /// Returns the token itself, which is never shown again.
+    pub fn create(
+        &self,
+        owner: &str,
+        role: Role,
+        name: String,
+        scopes: Vec<Scope>,
+        expires: Option<i64>,
+    ) -> Result<(ApiToken, String), ApiTokenError> {
+        if let Some(scope) = scopes.iter().find(|scope| scope.role() > role) {
+            return Err(ApiTokenError::ScopeNotAllowed(*scope));
+        }
+        let secret = format!("{}{}", TOKEN_PREFIX, random_string(40));
+        let token = ApiToken {
+            id: random_string(12),
+            name,
+            owner: owner.to_string(),
+            scopes,
+            created: time::OffsetDateTime::now_utc().unix_timestamp(),
+            expires,
+        };
+        let mut tokens = self.tokens.lock().unwrap_or_else(PoisonError::into_inner);
+        tokens.push(StoredToken {
+            token: token.clone(),
+            hash: sha256_hex(&secret),
+        });
+        self.save(&tokens)?;
+        info!("<{}> created API token <{}>", owner, &token.name);
+        Ok((token, secret))
+    }
+
+    /// Tokens of the user, or all of them, if `owner` is `None`.
+    pub fn list(&self, owner: Option<&str>) -> Vec<ApiToken> {
+        let tokens = self.tokens.lock().unwrap_or_else(PoisonError::into_inner);
+        tokens
+            .iter()
+            .map(|stored| &stored.token)
+            .filter(|token| owner.map_or(true, |owner| token.owner == owner))
+            .cloned()
+            .collect()
+    }
+
+    /// Only the owner can revoke the token, unless `owner` is `None`.
+    pub fn revoke(&self, id: &str, owner: Option<&str>) -> Result<(), ApiTokenError> {
+        let mut tokens = self.tokens.lock().unwrap_or_else(PoisonError::into_inner);
+        let position = tokens
+            .iter()
+            .position(|stored| {
+                stored.token.id == id && owner.map_or(true, |owner| stored.token.owner == owner)
+            })
+            .ok_or_else(|| ApiTokenError::NotFound(id.to_string()))?;
+        let token = tokens.remove(position).token;
+        self.save(&tokens)?;
+        info!(
+            "API token <{}> of <{}> was revoked",
+            &token.name, &token.owner
+        );
+        Ok(())
+    }
+
+    /// Returns the token, if it exists and hasn't expired.
+    pub fn authenticate(&self, secret: &str) -> Option<ApiToken> {
+        if !secret.starts_with(TOKEN_PREFIX) {
+            return None;
+        }
+        let hash = sha256_hex(secret);
+        let now = time::OffsetDateTime::now_utc().unix_timestamp();
+        let tokens = self.tokens.lock().unwrap_or_else(PoisonError::into_inner);
+        tokens
+            .iter()
+            .find(|stored| constant_time_eq(stored.hash.as_bytes(), hash.as_bytes()))
+            .map(|stored| &stored.token)
+            .filter(|token| token.expires.map_or(true, |expires| expires > now))
+            .cloned()
+    }
+
+    fn save(&self, tokens: &[StoredToken]) -> Result<(), ApiTokenError> {
+        Ok(store::save(&self.path, tokens)?)
+    }
+}
+
+/// Scope a token needs to call the route, `None` if tokens can't call it at all.
+pub fn required_scope(method: &Method, path: &str) -> Option<Scope> {
+    let scope = match (method.as_str(), path) {
+        ("GET", "/ping" | "/ban" | "/ban-ip" | "/whitelist" | "/op" | "/backups" | "/restore") => {
+            Scope::Read
+        }
+        ("POST", "/kick") => Scope::Kick,
+        ("POST" | "DELETE", "/ban") => Scope::Ban,
+        ("POST" | "DELETE", "/whitelist") => Scope::Whitelist,
+        ("POST" | "DELETE", "/op") => Scope::Op,
+        ("POST", "/backup" | "/restore") => Scope::Backup,
+        ("GET", "/logs" | "/logs/stream") => Scope::Logs,
+        _ => return None,
+    };
+    Some(scope)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_routes_to_scopes() {
+        assert_eq!(required_scope(&Method::GET, "/ping"), Some(Scope::Read));
+        assert_eq!(required_scope(&Method::POST, "/kick"), Some(Scope::Kick));
+        assert_eq!(
+            required_scope(&Method::DELETE, "/whitelist"),
+            Some(Scope::Whitelist)
+        );
+        assert_eq!(required_scope(&Method::GET, "/console"), None);
+        assert_eq!(required_scope(&Method::POST, "/tokens"), None);
+        assert_eq!(required_scope(&Method::POST, "/totp"), None);
+    }
+}
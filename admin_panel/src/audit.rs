@@ -0,0 +1,227 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+    net::IpAddr,
+    path::PathBuf,
+};
+
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{error::AuditError, models::protected::AuditQuery};
+
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 500;
+
+#[derive(Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// Unix timestamp.
+    pub time: i64,
+    pub user: String,
+    /// Name of the API token, if the user didn't use the panel itself.
+    pub token: Option<String>,
+    pub ip: Option<IpAddr>,
+    /// Name of the `ProxyMessage`.
+    pub action: String,
+    pub params: Value,
+    pub response: Option<Value>,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct AuditPage {
+    /// Newest entries come first.
+    pub entries: Vec<AuditEntry>,
+    /// Number of the entries that match the filters.
+    pub total: usize,
+    pub page: usize,
+    pub per_page: usize,
+}
+
+/// Administrative actions as JSON Lines. The file is only ever appended to.
+pub struct AuditLog {
+    path: PathBuf,
+}
+
+impl AuditLog {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// The action has already happened, so a failure to record it is only logged.
+    pub fn record(&self, entry: &AuditEntry) {
+        let result = serde_json::to_string(entry)
+            .map_err(AuditError::from)
+            .and_then(|mut line| {
+                line.push('\n');
+                // The line is written at once, so concurrent writes don't interleave
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)
+                    .and_then(|mut file| file.write_all(line.as_bytes()))
+                    .map_err(AuditError::Write)
+            });
+        if let Err(e) = result {
+            error!(
+                "Couldn't record <{}> by <{}>: {}",
+                &entry.action, &entry.user, &e
+            );
+        }
+    }
+
+    /// This function reads the whole file, so it must not be called on the async runtime.
+    pub fn query(&self, query: &AuditQuery) -> Result<AuditPage, AuditError> {
+        let content = match fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(AuditError::Read(e)),
+        };
+        Ok(page(&content, query))
+    }
+}
+
+fn page(content: &str, query: &AuditQuery) -> AuditPage {
+    let per_page = query
+        .per_page
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let page = query.page.unwrap_or(1).max(1);
+    let mut matching: Vec<AuditEntry> = content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| match serde_json::from_str::<AuditEntry>(line) {
+            Ok(entry) => Some(entry),
+            Err(e) => {
+                // E.g. the last line, if the container was killed while it was written
+                warn!("Skipping invalid audit log line: {}", &e);
+                None
+            }
+        })
+        .filter(|entry| matches(entry, query))
+        .collect();
+    let total = matching.len();
+    matching.reverse();
+    let entries = matching
+        .into_iter()
+        .skip((page - 1).saturating_mul(per_page))
+        .take(per_page)
+        .collect();
+    AuditPage {
+        entries,
+        total,
+        page,
+        per_page,
+    }
+}
+
+fn matches(entry: &AuditEntry, query: &AuditQuery) -> bool {
+    let user = query
+        .user
+        .as_ref()
+        .map_or(true, |user| entry.user.eq_ignore_ascii_case(user));
+    let action = query
+        .action
+        .as_ref()
+        .map_or(true, |action| entry.action.eq_ignore_ascii_case(action));
+    let ip = query.ip.map_or(true, |ip| entry.ip == Some(ip));
+    let since = query.since.map_or(true, |since| entry.time >= since);
+    let until = query.until.map_or(true, |until| entry.time < until);
+    user && action && ip && since && until
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn log() -> String {
+        let entry = |time: i64, user: &str, action: &str| {
+            serde_json::to_string(&AuditEntry {
+                time,
+                user: user.to_string(),
+                token: None,
+                ip: Some([127, 0, 0, 1].into()),
+                action: action.to_string(),
+                params: json!({ "nickname": "Steve" }),
+                response: None,
+                error: None,
+            })
+            .unwrap()
+        };
+        [
+            entry(100, "admin", "Ban"),
+            entry(200, "alice", "Kick"),
+            entry(300, "admin", "Kick"),
+            "{\"time\": 400, \"us".to_string(),
+            entry(500, "Alice", "Pardon"),
+        ]
+        .join("\n")
+    }
+
+    fn query() -> AuditQuery {
+        AuditQuery {
+            page: None,
+            per_page: None,
+            user: None,
+            action: None,
+            ip: None,
+            since: None,
+            until: None,
+        }
+    }
+
+    fn times(page: &AuditPage) -> Vec<i64> {
+        page.entries.iter().map(|entry| entry.time).collect()
+    }
+
+    #[test]
+    fn returns_newest_first_and_skips_broken_lines() {
+        let page = page(&log(), &query());
+        assert_eq!(times(&page), vec![500, 300, 200, 100]);
+        assert_eq!(page.total, 4);
+    }
+
+    #[test]
+    fn paginates() {
+        let second = AuditQuery {
+            page: Some(2),
+            per_page: Some(3),
+            ..query()
+        };
+        let page = page(&log(), &second);
+        assert_eq!(times(&page), vec![100]);
+        assert_eq!(page.total, 4);
+
+        let beyond = AuditQuery {
+            page: Some(10),
+            ..query()
+        };
+        assert!(super::page(&log(), &beyond).entries.is_empty());
+    }
+
+    #[test]
+    fn filters() {
+        let alice = AuditQuery {
+            user: Some("alice".to_string()),
+            ..query()
+        };
+        assert_eq!(times(&page(&log(), &alice)), vec![500, 200]);
+
+        let kicks = AuditQuery {
+            action: Some("kick".to_string()),
+            since: Some(250),
+            ..query()
+        };
+        assert_eq!(times(&page(&log(), &kicks)), vec![300]);
+
+        let until = AuditQuery {
+            until: Some(200),
+            ip: Some([127, 0, 0, 1].into()),
+            ..query()
+        };
+        assert_eq!(times(&page(&log(), &until)), vec![100]);
+    }
+}
@@ -0,0 +1,175 @@
+use std::{
+    cmp::Reverse,
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+};
+
+use flate2::{write::GzEncoder, Compression};
+use log::{info, warn};
+use serde::Serialize;
+use time::{Date, Month, OffsetDateTime, PrimitiveDateTime, Time};
+
+static PREFIX: &str = "world-";
+static EXTENSION: &str = ".tar.gz";
+
+#[derive(Serialize)]
+pub struct Backup {
+    pub name: String,
+    pub size: u64,
+    #[serde(skip)]
+    pub path: PathBuf,
+    #[serde(skip)]
+    pub created: PrimitiveDateTime,
+}
+
+/// Archives the world into `world-YYYY-MM-DD_HH-MM-SS.tar.gz` inside `directory`.
+/// This function is blocking, and can take a while for big worlds.
+pub fn create_archive(world: &Path, directory: &Path) -> Result<PathBuf, io::Error> {
+    fs::create_dir_all(directory)?;
+    let now = OffsetDateTime::now_utc();
+    let name = format!(
+        "{}{:04}-{:02}-{:02}_{:02}-{:02}-{:02}{}",
+        PREFIX,
+        now.year(),
+        now.month() as u8,
+        now.day(),
+        now.hour(),
+        now.minute(),
+        now.second(),
+        EXTENSION
+    );
+    archive_into(world, &directory.join(name))
+}
+
+fn archive_into(world: &Path, path: &Path) -> Result<PathBuf, io::Error> {
+    // Names only go down to the second, so a backup made in the same second would be replaced
+    if path.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("<{}> already exists", path.to_string_lossy()),
+        ));
+    }
+    // Write into a temporary file first, so that an interrupted backup is never listed
+    let partial_path = path.with_extension("gz.partial");
+    info!(
+        "Archiving <{}> into <{}>...",
+        world.to_string_lossy(),
+        path.to_string_lossy()
+    );
+
+    let file = File::options()
+        .write(true)
+        .create_new(true)
+        .open(&partial_path)?;
+    let result = write_archive(world, file).and_then(|_r| fs::rename(&partial_path, path));
+    if let Err(e) = result {
+        if let Err(e) = fs::remove_file(&partial_path) {
+            warn!(
+                "Couldn't delete <{}>: {}",
+                partial_path.to_string_lossy(),
+                &e
+            );
+        }
+        return Err(e);
+    }
+    Ok(path.to_path_buf())
+}
+
+fn write_archive(world: &Path, file: File) -> Result<(), io::Error> {
+    let mut archive = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+    archive.append_dir_all("world", world)?;
+    archive.into_inner()?.finish()?.sync_all()
+}
+
+/// Lists all backups in `directory`, newest first.
+pub fn list_backups(directory: &Path) -> Result<Vec<Backup>, io::Error> {
+    let mut backups = Vec::new();
+    let entries = match fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(backups),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let created = match parse_backup_name(&name) {
+            Some(created) => created,
+            None => continue,
+        };
+        backups.push(Backup {
+            name,
+            size: entry.metadata()?.len(),
+            path: entry.path(),
+            created,
+        });
+    }
+    backups.sort_by_key(|backup| Reverse(backup.created));
+    Ok(backups)
+}
+
+fn parse_backup_name(name: &str) -> Option<PrimitiveDateTime> {
+    let timestamp = name.strip_prefix(PREFIX)?.strip_suffix(EXTENSION)?;
+    let (date, time) = timestamp.split_once('_')?;
+    let date: Vec<&str> = date.split('-').collect();
+    let time: Vec<&str> = time.split('-').collect();
+    if date.len() != 3 || time.len() != 3 {
+        return None;
+    }
+
+    let month = Month::try_from(date[1].parse::<u8>().ok()?).ok()?;
+    let date =
+        Date::from_calendar_date(date[0].parse().ok()?, month, date[2].parse().ok()?).ok()?;
+    let time = Time::from_hms(
+        time[0].parse().ok()?,
+        time[1].parse().ok()?,
+        time[2].parse().ok()?,
+    )
+    .ok()?;
+    Some(PrimitiveDateTime::new(date, time))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn directory(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("archive-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    #[test]
+    fn refuses_to_replace_backups() {
+        let directory = directory("replace");
+        let world = directory.join("world");
+        fs::create_dir_all(&world).unwrap();
+        fs::write(world.join("level.dat"), b"level").unwrap();
+        let path = directory.join("world-2022-10-17_12-00-00.tar.gz");
+
+        assert_eq!(archive_into(&world, &path).unwrap(), path);
+        let size = fs::metadata(&path).unwrap().len();
+        let error = archive_into(&world, &path).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::metadata(&path).unwrap().len(), size);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn removes_partial_archive_on_error() {
+        let directory = directory("partial");
+        let path = directory.join("world-2022-10-17_12-00-00.tar.gz");
+        assert!(archive_into(&directory.join("missing"), &path).is_err());
+        assert_eq!(fs::read_dir(&directory).unwrap().count(), 0);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn parses_backup_names() {
+        let created = parse_backup_name("world-2022-10-17_08-05-09.tar.gz").unwrap();
+        assert_eq!(created.to_string(), "2022-10-17 8:05:09.0");
+        assert!(parse_backup_name("world-2022-10-17_08-05-09.tar.gz.partial").is_none());
+        assert!(parse_backup_name("world-2022-13-17_08-05-09.tar.gz").is_none());
+    }
+}
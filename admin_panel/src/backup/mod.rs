@@ -0,0 +1,16 @@
+use std::path::PathBuf;
+
+use self::{restore::RestoreProgress, retention::RetentionPolicy, schedule::CronSchedule};
+
+pub mod archive;
+pub mod restore;
+pub mod retention;
+pub mod schedule;
+
+pub struct BackupSettings {
+    pub schedule: Option<CronSchedule>,
+    pub retention: RetentionPolicy,
+    pub world: PathBuf,
+    pub directory: PathBuf,
+    pub restore_progress: RestoreProgress,
+}
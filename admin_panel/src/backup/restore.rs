@@ -0,0 +1,100 @@
+use std::{
+    fs::{self, File},
+    path::Path,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use flate2::read::GzDecoder;
+use log::info;
+use serde::Serialize;
+
+use crate::error::BackupError;
+
+#[derive(Clone, Debug, Serialize)]
+pub enum RestoreStage {
+    Idle,
+    Stopping { name: String },
+    Unpacking { name: String },
+    Starting { name: String },
+    Done { name: String },
+    Failed { name: String, error: String },
+}
+
+impl RestoreStage {
+    pub fn in_progress(&self) -> bool {
+        matches!(
+            self,
+            RestoreStage::Stopping { .. }
+                | RestoreStage::Unpacking { .. }
+                | RestoreStage::Starting { .. }
+        )
+    }
+}
+
+/// Shared between the web layer, which reports it to the client, and the supervisor, which updates it.
+#[derive(Clone)]
+pub struct RestoreProgress(Arc<Mutex<RestoreStage>>);
+
+impl RestoreProgress {
+    pub fn new() -> Self {
+        Self(Arc::new(Mutex::new(RestoreStage::Idle)))
+    }
+
+    pub fn get(&self) -> RestoreStage {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    pub fn set(&self, stage: RestoreStage) {
+        info!("Restore progress: {:?}", &stage);
+        *self.0.lock().unwrap_or_else(PoisonError::into_inner) = stage;
+    }
+}
+
+/// Replaces `world` with the one from `archive`. The archive is unpacked and verified
+/// before the current world is touched, and the current world is kept as `world.previous`.
+/// This function is blocking, and must be called only when the server is stopped.
+pub fn restore_archive(archive: &Path, world: &Path) -> Result<(), BackupError> {
+    let parent = match world.parent() {
+        Some(parent) => parent,
+        None => return Err(BackupError::NoLevelDat),
+    };
+    let staging = parent.join(".restore");
+    if staging.exists() {
+        fs::remove_dir_all(&staging)?;
+    }
+    fs::create_dir_all(&staging)?;
+
+    info!(
+        "Unpacking <{}> into <{}>...",
+        archive.to_string_lossy(),
+        staging.to_string_lossy()
+    );
+    let file = File::open(archive)?;
+    tar::Archive::new(GzDecoder::new(file)).unpack(&staging)?;
+
+    let restored = staging.join("world");
+    if !restored.join("level.dat").is_file() {
+        fs::remove_dir_all(&staging)?;
+        return Err(BackupError::NoLevelDat);
+    }
+
+    let previous = parent.join("world.previous");
+    if previous.exists() {
+        fs::remove_dir_all(&previous)?;
+    }
+    if world.exists() {
+        fs::rename(world, &previous)?;
+    }
+    if let Err(e) = fs::rename(&restored, world) {
+        // Put the current world back, so that the server can at least start
+        if previous.exists() {
+            fs::rename(&previous, world)?;
+        }
+        return Err(e.into());
+    }
+    fs::remove_dir_all(&staging)?;
+    Ok(())
+}
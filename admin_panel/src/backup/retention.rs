@@ -0,0 +1,135 @@
+use std::{collections::HashSet, fs, io, path::Path};
+
+use log::{info, warn};
+
+use super::archive::{list_backups, Backup};
+
+/// Backups to keep: the last `keep_last` of them, plus the newest backup
+/// for each of the last `keep_daily` days and `keep_weekly` weeks.
+#[derive(Clone)]
+pub struct RetentionPolicy {
+    pub keep_last: u16,
+    pub keep_daily: u16,
+    pub keep_weekly: u16,
+}
+
+impl RetentionPolicy {
+    /// Deletes backups that don't fall under the policy. The newest backup is always kept.
+    pub fn prune(&self, directory: &Path) -> Result<(), io::Error> {
+        let backups = list_backups(directory)?;
+        for backup in self.expired(&backups) {
+            info!("Deleting old backup <{}>...", &backup.name);
+            match fs::remove_file(&backup.path) {
+                Ok(_r) => {}
+                Err(e) => warn!("Couldn't delete backup <{}>: {}", &backup.name, &e),
+            }
+        }
+        Ok(())
+    }
+
+    /// `backups` must be sorted newest first.
+    fn expired<'a>(&self, backups: &'a [Backup]) -> Vec<&'a Backup> {
+        let mut keep = HashSet::new();
+        keep.insert(0);
+        for index in 0..backups.len().min(self.keep_last as usize) {
+            keep.insert(index);
+        }
+
+        let mut days = HashSet::new();
+        let mut weeks = HashSet::new();
+        for (index, backup) in backups.iter().enumerate() {
+            let day = backup.created.date();
+            if days.len() < self.keep_daily as usize && days.insert(day) {
+                keep.insert(index);
+            }
+            let (year, week, _) = day.to_iso_week_date();
+            if weeks.len() < self.keep_weekly as usize && weeks.insert((year, week)) {
+                keep.insert(index);
+            }
+        }
+
+        backups
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| !keep.contains(index))
+            .map(|(_, backup)| backup)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::{Date, Month};
+
+    use super::*;
+
+    fn backup(day: u8, hour: u8) -> Backup {
+        let created = Date::from_calendar_date(2022, Month::October, day)
+            .unwrap()
+            .with_hms(hour, 0, 0)
+            .unwrap();
+        Backup {
+            name: format!("{}-{}", day, hour),
+            size: 0,
+            path: Default::default(),
+            created,
+        }
+    }
+
+    fn expired(policy: RetentionPolicy, backups: &[Backup]) -> Vec<&str> {
+        policy
+            .expired(backups)
+            .into_iter()
+            .map(|backup| backup.name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn keeps_buckets() {
+        // Newest first; October 17th is a Monday, so the 16th ends the previous week
+        let backups = [
+            backup(17, 12),
+            backup(17, 6),
+            backup(16, 12),
+            backup(16, 6),
+            backup(15, 12),
+            backup(14, 12),
+            backup(9, 12),
+            backup(2, 12),
+        ];
+        let policy = |keep_last, keep_daily, keep_weekly| RetentionPolicy {
+            keep_last,
+            keep_daily,
+            keep_weekly,
+        };
+
+        assert_eq!(
+            expired(policy(3, 0, 0), &backups),
+            ["16-6", "15-12", "14-12", "9-12", "2-12"]
+        );
+        assert_eq!(
+            expired(policy(0, 3, 0), &backups),
+            ["17-6", "16-6", "14-12", "9-12", "2-12"]
+        );
+        assert_eq!(
+            expired(policy(0, 0, 3), &backups),
+            ["17-6", "16-6", "15-12", "14-12", "2-12"]
+        );
+        assert_eq!(
+            expired(policy(2, 3, 2), &backups),
+            ["16-6", "14-12", "9-12", "2-12"]
+        );
+    }
+
+    #[test]
+    fn keeps_newest() {
+        let backups = [backup(17, 12), backup(16, 12)];
+        let policy = RetentionPolicy {
+            keep_last: 0,
+            keep_daily: 0,
+            keep_weekly: 0,
+        };
+        assert_eq!(expired(policy.clone(), &backups), ["16-12"]);
+        assert!(expired(policy, &[]).is_empty());
+    }
+}
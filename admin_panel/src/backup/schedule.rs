@@ -0,0 +1,182 @@
+use std::str::FromStr;
+
+use time::OffsetDateTime;
+
+use crate::error::CronParserError;
+
+/// Subset of cron syntax: `minute hour day-of-month month day-of-week`.
+/// Each field supports `*`, lists `1,15`, ranges `1-5` and steps `*/15`.
+/// All times are in UTC.
+pub struct CronSchedule {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl CronSchedule {
+    pub fn matches(&self, time: OffsetDateTime) -> bool {
+        let day_matches = bit_set(self.days, time.day() as u32);
+        let weekday_matches = bit_set(
+            self.weekdays,
+            time.weekday().number_days_from_sunday() as u32,
+        );
+        // Same as in cron: if both day fields are restricted, either of them can match
+        let day_matches = match (self.any_day, self.any_weekday) {
+            (false, false) => day_matches || weekday_matches,
+            _ => day_matches && weekday_matches,
+        };
+        bit_set(self.minutes, time.minute() as u32)
+            && bit_set(self.hours, time.hour() as u32)
+            && bit_set(self.months, time.month() as u32)
+            && day_matches
+    }
+}
+
+impl FromStr for CronSchedule {
+    type Err = CronParserError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(CronParserError::Parse(s.to_string()));
+        }
+
+        let mut weekdays = parse_field(fields[4], 0, 7)?;
+        // Both 0 and 7 mean Sunday
+        if bit_set(weekdays, 7) {
+            weekdays |= 1;
+        }
+        Ok(Self {
+            minutes: parse_field(fields[0], 0, 59)?,
+            hours: parse_field(fields[1], 0, 23)?,
+            days: parse_field(fields[2], 1, 31)?,
+            months: parse_field(fields[3], 1, 12)?,
+            weekdays,
+            any_day: fields[2] == "*",
+            any_weekday: fields[4] == "*",
+        })
+    }
+}
+
+fn bit_set(mask: u64, value: u32) -> bool {
+    mask & (1 << value) != 0
+}
+
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, CronParserError> {
+    let error = || CronParserError::Parse(field.to_string());
+    let number = |value: &str| match value.parse::<u32>() {
+        Ok(number) if (min..=max).contains(&number) => Ok(number),
+        _ => Err(error()),
+    };
+
+    let mut mask = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => match step.parse::<u32>() {
+                Ok(step) if step > 0 => (range, Some(step)),
+                _ => return Err(error()),
+            },
+            None => (part, None),
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (number(start)?, number(end)?)
+        } else if step.is_some() {
+            (number(range)?, max)
+        } else {
+            let value = number(range)?;
+            (value, value)
+        };
+        if start > end {
+            return Err(error());
+        }
+        for value in (start..=end).step_by(step.unwrap_or(1) as usize) {
+            mask |= 1 << value;
+        }
+    }
+    Ok(mask)
+}
+
+#[cfg(test)]
+mod tests {
+    use time::{Date, Month};
+
+    use super::*;
+
+    fn at(day: u8, hour: u8, minute: u8) -> OffsetDateTime {
+        // October 2022 starts on a Saturday
+        Date::from_calendar_date(2022, Month::October, day)
+            .unwrap()
+            .with_hms(hour, minute, 0)
+            .unwrap()
+            .assume_utc()
+    }
+
+    #[test]
+    fn matches_steps() {
+        let schedule: CronSchedule = "*/15 */6 * * *".parse().unwrap();
+        assert!(schedule.matches(at(3, 0, 0)));
+        assert!(schedule.matches(at(3, 18, 45)));
+        assert!(!schedule.matches(at(3, 18, 50)));
+        assert!(!schedule.matches(at(3, 7, 15)));
+
+        let schedule: CronSchedule = "10/20 0 * * *".parse().unwrap();
+        assert!(schedule.matches(at(3, 0, 10)));
+        assert!(schedule.matches(at(3, 0, 50)));
+        assert!(!schedule.matches(at(3, 0, 0)));
+    }
+
+    #[test]
+    fn matches_lists_and_ranges() {
+        let schedule: CronSchedule = "0 3,15 1-10 * *".parse().unwrap();
+        assert!(schedule.matches(at(1, 3, 0)));
+        assert!(schedule.matches(at(10, 15, 0)));
+        assert!(!schedule.matches(at(11, 3, 0)));
+        assert!(!schedule.matches(at(5, 4, 0)));
+    }
+
+    #[test]
+    fn matches_weekdays() {
+        let working_days: CronSchedule = "30 4 * * 1-5".parse().unwrap();
+        assert!(working_days.matches(at(3, 4, 30)));
+        assert!(working_days.matches(at(7, 4, 30)));
+        assert!(!working_days.matches(at(8, 4, 30)));
+
+        // Sunday is both 0 and 7
+        for field in ["0", "7"] {
+            let sunday: CronSchedule = format!("0 0 * * {}", field).parse().unwrap();
+            assert!(sunday.matches(at(9, 0, 0)));
+            assert!(!sunday.matches(at(10, 0, 0)));
+        }
+
+        // With both day fields restricted, either one matches
+        let first_or_monday: CronSchedule = "0 0 1 * 1".parse().unwrap();
+        assert!(first_or_monday.matches(at(1, 0, 0)));
+        assert!(first_or_monday.matches(at(17, 0, 0)));
+        assert!(!first_or_monday.matches(at(18, 0, 0)));
+    }
+
+    #[test]
+    fn rejects_invalid_schedules() {
+        for schedule in [
+            "",
+            "* * * *",
+            "* * * * * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "* * * 13 *",
+            "* * * * 8",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "a * * * *",
+        ] {
+            assert!(schedule.parse::<CronSchedule>().is_err(), "{}", schedule);
+        }
+    }
+}
@@ -1,7 +1,11 @@
-use std::sync::Arc;
+use std::{net::SocketAddr, sync::Arc};
 
 use axum::{
-    response::{Html, Redirect},
+    extract::{ConnectInfo, Query},
+    headers::{self, HeaderMapExt},
+    http::{header::SET_COOKIE, HeaderValue, Request},
+    middleware::Next,
+    response::{Html, Redirect, Response},
     Extension, Form,
 };
 use axum_extra::extract::{cookie::Cookie, CookieJar};
@@ -9,53 +13,318 @@ use cookie::{
     time::{Duration, OffsetDateTime},
     SameSite,
 };
-use jsonwebtoken::{encode, Header};
+use jsonwebtoken::{decode, encode, Header, Validation};
+use log::{info, warn};
+use tokio::task::spawn_blocking;
 
 use crate::{
-    error::LoginError,
-    models::{self, auth::Claims},
+    error::{LoginError, OidcError},
+    models::{
+        self,
+        auth::{Claims, OidcCallback, OidcLogin, PendingLogin, Role},
+    },
+    oidc::OidcClient,
+    password::{constant_time_eq, random_string, verify_dummy},
     Context,
 };
 
 static LOGIN_FORM: &str = include_str!("../../static/login.html");
+static TOTP_FORM: &str = include_str!("../../static/totp.html");
+static OIDC_BUTTON: &str = r#"<p><a href="/login/oidc">Sign in with single sign-on</a></p>"#;
+/// A redirect would still be a part of the navigation from the identity provider,
+/// so browsers wouldn't send the strict session cookie with it.
+static OIDC_REDIRECT: &str = r#"<!doctype html><meta http-equiv="refresh" content="0; url=/home">"#;
 
-pub async fn login() -> Html<String> {
-    Html::from(LOGIN_FORM.to_owned())
+const SESSION_LIFETIME: Duration = Duration::minutes(10);
+/// Tokens that expire sooner than that are re-issued on the next request.
+const REFRESH_BEFORE: Duration = Duration::minutes(5);
+const OIDC_LOGIN_LIFETIME: Duration = Duration::minutes(10);
+const SESSION_ID_LENGTH: usize = 32;
+
+pub async fn login(Extension(context): Extension<Arc<Context>>) -> Html<String> {
+    let button = match context.oidc {
+        Some(_) => OIDC_BUTTON,
+        None => "",
+    };
+    Html::from(LOGIN_FORM.replace("<!-- oidc -->", button))
+}
+
+pub async fn totp() -> Html<String> {
+    Html::from(TOTP_FORM.to_owned())
 }
 
 pub async fn login_post(
     Form(credntials): Form<models::auth::LoginData>,
     Extension(context): Extension<Arc<Context>>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
     jar: CookieJar,
 ) -> Result<(CookieJar, Redirect), LoginError> {
+    let ip = address.ip();
+    if let Err(retry_after) = context.login_guard.check(ip) {
+        warn!(
+            "Rejected login attempt as <{}> from {}: too many attempts",
+            &credntials.username, &ip
+        );
+        return Err(LoginError::TooManyAttempts(retry_after.as_secs().max(1)));
+    }
+
     let in_username = credntials.username.to_lowercase();
-    let ref_username = context.username.to_lowercase();
-    if in_username != ref_username {
-        return Err(LoginError::WrongCredentials);
+    let user = context
+        .users
+        .iter()
+        .find(|user| user.username.to_lowercase() == in_username)
+        .cloned();
+
+    // Password is checked even if there is no such user, so that both cases take the same time
+    let password = credntials.password;
+    let checked_user = user.clone();
+    let dummy_hash = context.dummy_hash.clone();
+    let verified = spawn_blocking(move || match checked_user {
+        Some(user) => user.verify_password(&password, &dummy_hash),
+        None => verify_dummy(&dummy_hash, &password),
+    })
+    .await
+    .unwrap_or(false);
+    let user = match user {
+        Some(user) if verified => user,
+        _ => {
+            warn!(
+                "Failed login attempt as <{}> from {}",
+                &credntials.username, &ip
+            );
+            if let Some(lockout) = context.login_guard.record_failure(ip) {
+                warn!("{} is locked out for {} seconds", &ip, lockout.as_secs());
+            }
+            return Err(LoginError::WrongCredentials);
+        }
+    };
+
+    // The lockout is lifted only after the second factor, otherwise the password would reset it
+    if context.totp.is_enabled(&user.username) {
+        let max_age = Duration::minutes(5);
+        let pending = PendingLogin {
+            pending: true,
+            exp: (OffsetDateTime::now_utc() + max_age).unix_timestamp(),
+            sub: user.username,
+            role: user.role,
+        };
+        let token = encode(&Header::default(), &pending, &context.keys.encoding)
+            .map_err(|_| LoginError::TokenCreation)?;
+        let cookie_jar = jar.add(cookie("mfa", token, max_age));
+        return Ok((cookie_jar, Redirect::to("/login/totp")));
     }
+    context.login_guard.record_success(ip);
+
+    let cookie = session_cookie(
+        &context,
+        user.username,
+        user.role,
+        random_string(SESSION_ID_LENGTH),
+    )?;
+    Ok((jar.add(cookie), Redirect::to("/home")))
+}
 
-    if credntials.password != context.password {
-        return Err(LoginError::WrongCredentials);
+pub async fn totp_post(
+    Form(totp): Form<models::auth::TotpCode>,
+    Extension(context): Extension<Arc<Context>>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    jar: CookieJar,
+) -> Result<(CookieJar, Redirect), LoginError> {
+    let ip = address.ip();
+    if let Err(retry_after) = context.login_guard.check(ip) {
+        warn!("Rejected two-factor code from {}: too many attempts", &ip);
+        return Err(LoginError::TooManyAttempts(retry_after.as_secs().max(1)));
+    }
+
+    let token = jar.get("mfa").ok_or(LoginError::InvalidToken)?;
+    let pending = decode::<PendingLogin>(
+        token.value(),
+        &context.keys.decoding,
+        &Validation::default(),
+    )
+    .map_err(|_| LoginError::InvalidToken)?
+    .claims;
+
+    if !context.totp.verify(&pending.sub, &totp.code)? {
+        warn!("Wrong two-factor code for <{}> from {}", &pending.sub, &ip);
+        if let Some(lockout) = context.login_guard.record_failure(ip) {
+            warn!("{} is locked out for {} seconds", &ip, lockout.as_secs());
+        }
+        return Err(LoginError::WrongCode);
     }
+    context.login_guard.record_success(ip);
+    info!(
+        "<{}> logged in with two-factor authentication",
+        &pending.sub
+    );
+
+    let cookie = session_cookie(
+        &context,
+        pending.sub,
+        pending.role,
+        random_string(SESSION_ID_LENGTH),
+    )?;
+    let cookie_jar = jar.remove(removal("mfa")).add(cookie);
+    Ok((cookie_jar, Redirect::to("/home")))
+}
+
+/// Redirects to the identity provider, which redirects back to `oidc_callback`.
+pub async fn oidc_login(
+    Extension(context): Extension<Arc<Context>>,
+    jar: CookieJar,
+) -> Result<(CookieJar, Redirect), OidcError> {
+    let oidc = context.oidc.clone().ok_or(OidcError::Disabled)?;
+    let login =
+        OidcClient::new_login((OffsetDateTime::now_utc() + OIDC_LOGIN_LIFETIME).unix_timestamp());
+    let token = encode(&Header::default(), &login, &context.keys.encoding)
+        .map_err(|_| LoginError::TokenCreation)?;
+    let url = spawn_blocking(move || oidc.authorization_url(&login)).await??;
 
-    let max_age = Duration::minutes(10);
-    let expiration = OffsetDateTime::now_utc() + max_age;
+    let mut cookie = cookie("oidc", token, OIDC_LOGIN_LIFETIME);
+    // Strict cookies aren't sent when the identity provider redirects back
+    cookie.set_same_site(SameSite::Lax);
+    Ok((jar.add(cookie), Redirect::to(&url)))
+}
+
+pub async fn oidc_callback(
+    Query(callback): Query<OidcCallback>,
+    Extension(context): Extension<Arc<Context>>,
+    jar: CookieJar,
+) -> Result<(CookieJar, Html<&'static str>), OidcError> {
+    let oidc = context.oidc.clone().ok_or(OidcError::Disabled)?;
+    if let Some(error) = callback.error {
+        return Err(OidcError::Denied(
+            callback.error_description.unwrap_or(error),
+        ));
+    }
+    let login = jar
+        .get("oidc")
+        .and_then(|cookie| {
+            decode::<OidcLogin>(
+                cookie.value(),
+                &context.keys.decoding,
+                &Validation::default(),
+            )
+            .ok()
+        })
+        .ok_or(OidcError::InvalidState)?
+        .claims;
+    let state = callback.state.unwrap_or_default();
+    if !constant_time_eq(state.as_bytes(), login.state.as_bytes()) {
+        return Err(OidcError::InvalidState);
+    }
+    let code = callback
+        .code
+        .ok_or_else(|| OidcError::Denied("there is no authorization code".to_string()))?;
+
+    let identity = match spawn_blocking(move || oidc.finish(&code, &login)).await? {
+        Ok(identity) => identity,
+        Err(e) => {
+            warn!("Failed single sign-on: {}", &e);
+            return Err(e);
+        }
+    };
+    // Local users and users of the identity provider can't be mixed up
+    let username = format!("oidc:{}", identity.username);
+    info!(
+        "<{}> logged in with single sign-on as {:?}",
+        &username, identity.role
+    );
+    let cookie = session_cookie(
+        &context,
+        username,
+        identity.role,
+        random_string(SESSION_ID_LENGTH),
+    )?;
+    let cookie_jar = jar.remove(removal("oidc")).add(cookie);
+    Ok((cookie_jar, Html(OIDC_REDIRECT)))
+}
+
+/// Tokens of the session stop working right away, even though they haven't expired yet.
+pub async fn logout(
+    Extension(context): Extension<Arc<Context>>,
+    claims: Option<Claims>,
+    jar: CookieJar,
+) -> (CookieJar, Redirect) {
+    if let Some(claims) = claims {
+        info!("<{}> logged out", &claims.sub);
+        // Refreshed tokens of the session can't live longer than that
+        let until = OffsetDateTime::now_utc() + SESSION_LIFETIME;
+        context
+            .revoked_sessions
+            .revoke(claims.jti, until.unix_timestamp());
+    }
+    (jar.remove(removal("jwt")), Redirect::to("/"))
+}
+
+/// Re-issues the session cookie when it's about to expire, so that active users aren't logged out.
+pub async fn refresh_session<B>(request: Request<B>, next: Next<B>) -> Response {
+    let refreshed = refreshed_cookie(&request);
+    let mut response = next.run(request).await;
+    // Login and logout set the cookie themselves
+    if response.headers().contains_key(SET_COOKIE) {
+        return response;
+    }
+    if let Some(cookie) = refreshed {
+        if let Ok(value) = HeaderValue::from_str(&cookie.to_string()) {
+            response.headers_mut().append(SET_COOKIE, value);
+        }
+    }
+    response
+}
+
+fn refreshed_cookie<B>(request: &Request<B>) -> Option<Cookie<'static>> {
+    let context = request.extensions().get::<Arc<Context>>()?;
+    let cookies = request.headers().typed_get::<headers::Cookie>()?;
+    let token = cookies.get("jwt")?;
+    let claims = decode::<Claims>(token, &context.keys.decoding, &Validation::default())
+        .ok()?
+        .claims;
+    let remaining = claims.exp - OffsetDateTime::now_utc().unix_timestamp();
+    if remaining > REFRESH_BEFORE.whole_seconds()
+        || context.revoked_sessions.is_revoked(&claims.jti)
+    {
+        return None;
+    }
+    session_cookie(context, claims.sub, claims.role, claims.jti).ok()
+}
+
+fn session_cookie(
+    context: &Context,
+    username: String,
+    role: Role,
+    jti: String,
+) -> Result<Cookie<'static>, LoginError> {
+    let expiration = OffsetDateTime::now_utc() + SESSION_LIFETIME;
 
     let claims = Claims {
         loged_in: true,
         exp: expiration.unix_timestamp(),
+        sub: username,
+        role,
+        jti,
+        api_token: None,
+        ip: None,
     };
 
     let token = encode(&Header::default(), &claims, &context.keys.encoding)
         .map_err(|_| LoginError::TokenCreation)?;
+    Ok(cookie("jwt", token, SESSION_LIFETIME))
+}
 
-    let mut jwt = Cookie::new("jwt", token);
-    jwt.set_http_only(true);
-    // Enable back when implement getting cert from some provider
-    // jwt.set_secure(true);
-    jwt.set_max_age(max_age);
-    jwt.set_same_site(SameSite::Strict);
-    let cookie_jar = jar.add(jwt);
+/// The path is set explicitly, otherwise it would depend on the URL that set the cookie.
+fn cookie(name: &'static str, value: String, max_age: Duration) -> Cookie<'static> {
+    let mut cookie = Cookie::new(name, value);
+    cookie.set_path("/");
+    cookie.set_http_only(true);
+    cookie.set_secure(true);
+    cookie.set_max_age(max_age);
+    cookie.set_same_site(SameSite::Strict);
+    cookie
+}
 
-    Ok((cookie_jar, Redirect::to("/home")))
+fn removal(name: &'static str) -> Cookie<'static> {
+    let mut cookie = Cookie::named(name);
+    cookie.set_path("/");
+    cookie
 }
@@ -0,0 +1,30 @@
+use std::sync::Arc;
+
+use axum::{
+    headers::{authorization::Bearer, Authorization},
+    http::header::CONTENT_TYPE,
+    response::IntoResponse,
+    Extension, TypedHeader,
+};
+
+use crate::{error::MetricsError, password::constant_time_eq, Context};
+
+/// Prometheus scrapers can't log in, so metrics are protected with a separate token, if it's set.
+pub async fn metrics(
+    Extension(context): Extension<Arc<Context>>,
+    authorization: Option<TypedHeader<Authorization<Bearer>>>,
+) -> Result<impl IntoResponse, MetricsError> {
+    if let Some(expected) = &context.metrics_token {
+        let token = match &authorization {
+            Some(TypedHeader(authorization)) => authorization.token(),
+            None => return Err(MetricsError::InvalidToken),
+        };
+        if !constant_time_eq(token.as_bytes(), expected.as_bytes()) {
+            return Err(MetricsError::InvalidToken);
+        }
+    }
+
+    let process = context.process.get();
+    let body = context.metrics.render(&process);
+    Ok(([(CONTENT_TYPE, "text/plain; version=0.0.4")], body))
+}
@@ -1,2 +1,6 @@
 pub mod auth;
+pub mod metrics;
 pub mod protected;
+pub mod tls;
+pub mod tokens;
+pub mod totp;
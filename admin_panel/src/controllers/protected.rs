@@ -1,117 +1,371 @@
-use std::sync::Arc;
+use std::{convert::Infallible, io::ErrorKind, sync::Arc};
 
-use axum::{response::Html, Extension, Json};
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Query,
+    },
+    http::header::CONTENT_TYPE,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        Html, IntoResponse, Response,
+    },
+    Extension, Json,
+};
+use log::{debug, info};
+use serde::{de::DeserializeOwned, Serialize};
 use serde_json::{json, Value};
-use tokio::sync::oneshot::channel;
+use tokio::{sync::oneshot::channel, task::spawn_blocking};
+use tokio_stream::{
+    wrappers::{errors::BroadcastStreamRecvError, BroadcastStream},
+    Stream, StreamExt,
+};
 
 use crate::{
-    error::ProxyMessageError,
-    models::{self, auth::Claims},
-    server::proxy_service::ProxyMessage,
+    audit::AuditEntry,
+    backup::archive::list_backups,
+    error::{AuditError, BackupError, ProxyMessageError, RosterError},
+    models::{
+        self,
+        auth::{Admin, Claims, Moderator},
+    },
+    server::{
+        proxy_service::{ProxyMessage, ProxyResponse},
+        temp_bans::BanDuration,
+    },
     Context,
 };
 
 static HOME_PAGE: &str = include_str!("../../static/home.html");
+static HOME_SCRIPT: &str = include_str!("../../static/home.js");
 
 pub async fn home(_claims: Claims) -> Html<String> {
     Html::from(HOME_PAGE.to_owned())
 }
 
+/// Kept out of the page, so that the content security policy doesn't have to allow inline scripts.
+pub async fn home_script() -> impl IntoResponse {
+    ([(CONTENT_TYPE, "text/javascript")], HOME_SCRIPT)
+}
+
 pub async fn ban_user(
     Json(ban): Json<models::protected::Ban>,
     Extension(context): Extension<Arc<Context>>,
-    _claims: Claims,
+    Moderator(claims): Moderator,
 ) -> Result<Json<Value>, ProxyMessageError> {
+    let duration = match ban.duration.as_deref().map(str::trim) {
+        Some(duration) if !duration.is_empty() => Some(duration.parse::<BanDuration>()?.seconds()),
+        _ => None,
+    };
     let message = ProxyMessage::Ban {
         nickname: ban.nickname,
         reason: ban.reason,
+        duration,
     };
-    send_message(context, message).await
+    send_message(context, &claims, message).await
 }
 
 pub async fn pardon(
     Json(pardon): Json<models::protected::Pardon>,
     Extension(context): Extension<Arc<Context>>,
-    _claims: Claims,
+    Moderator(claims): Moderator,
 ) -> Result<Json<Value>, ProxyMessageError> {
     let message = ProxyMessage::Pardon {
         nickname: pardon.nickname,
     };
-    send_message(context, message).await
+    send_message(context, &claims, message).await
 }
 
 pub async fn kick_user(
     Json(kick): Json<models::protected::Kick>,
     Extension(context): Extension<Arc<Context>>,
-    _claims: Claims,
+    Moderator(claims): Moderator,
 ) -> Result<Json<Value>, ProxyMessageError> {
     let message = ProxyMessage::Kick {
         nickname: kick.nickname,
         reason: kick.reason,
     };
-    send_message(context, message).await
+    send_message(context, &claims, message).await
 }
 
 pub async fn whitelist_add(
     Json(whitelist_add): Json<models::protected::WhitelistAdd>,
     Extension(context): Extension<Arc<Context>>,
-    _claims: Claims,
+    Moderator(claims): Moderator,
 ) -> Result<Json<Value>, ProxyMessageError> {
     let message = ProxyMessage::WhitelistAdd {
         nickname: whitelist_add.nickname,
     };
-    send_message(context, message).await
+    send_message(context, &claims, message).await
 }
 
 pub async fn whitelist_remove(
     Json(whitelist_remove): Json<models::protected::WhitelistRemove>,
     Extension(context): Extension<Arc<Context>>,
-    _claims: Claims,
+    Moderator(claims): Moderator,
 ) -> Result<Json<Value>, ProxyMessageError> {
     let message = ProxyMessage::WhitelistRemove {
         nickname: whitelist_remove.nickname,
     };
-    send_message(context, message).await
+    send_message(context, &claims, message).await
 }
 
 pub async fn op_add(
     Json(op_add): Json<models::protected::OpAdd>,
     Extension(context): Extension<Arc<Context>>,
-    _claims: Claims,
+    Admin(claims): Admin,
 ) -> Result<Json<Value>, ProxyMessageError> {
     let message = ProxyMessage::OpAdd {
         nickname: op_add.nickname,
     };
-    send_message(context, message).await
+    send_message(context, &claims, message).await
 }
 
 pub async fn de_op(
     Json(de_op): Json<models::protected::DeOp>,
     Extension(context): Extension<Arc<Context>>,
-    _claims: Claims,
+    Admin(claims): Admin,
 ) -> Result<Json<Value>, ProxyMessageError> {
     let message = ProxyMessage::DeOp {
         nickname: de_op.nickname,
     };
-    send_message(context, message).await
+    send_message(context, &claims, message).await
 }
 
 pub async fn server_status(
     Extension(context): Extension<Arc<Context>>,
-    _claims: Claims,
+    claims: Claims,
 ) -> Result<Json<Value>, ProxyMessageError> {
     let message = ProxyMessage::Ping;
-    send_message(context, message).await
+    send_message(context, &claims, message).await
+}
+
+pub async fn logs(
+    Query(logs): Query<models::protected::Logs>,
+    Extension(context): Extension<Arc<Context>>,
+    _claims: Moderator,
+) -> Json<Value> {
+    let lines = context.logs.tail(logs.lines.unwrap_or(100));
+    Json(json!({ "success": true, "response": lines }))
+}
+
+pub async fn logs_stream(
+    Extension(context): Extension<Arc<Context>>,
+    _claims: Moderator,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let stream = BroadcastStream::new(context.logs.subscribe()).map(|line| match line {
+        Ok(line) => Ok(Event::default().data(line)),
+        // The client is too slow, let it know that some lines were skipped
+        Err(BroadcastStreamRecvError::Lagged(skipped)) => {
+            Ok(Event::default().event("lagged").data(skipped.to_string()))
+        }
+    });
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+pub async fn backup(
+    Extension(context): Extension<Arc<Context>>,
+    Admin(claims): Admin,
+) -> Result<Json<Value>, ProxyMessageError> {
+    let message = ProxyMessage::Backup;
+    send_message(context, &claims, message).await
+}
+
+pub async fn backup_list(
+    Extension(context): Extension<Arc<Context>>,
+    _claims: Claims,
+) -> Result<Json<Value>, BackupError> {
+    let directory = context.backup_directory.clone();
+    let backups = spawn_blocking(move || list_backups(&directory)).await??;
+    Ok(Json(json!({ "success": true, "response": backups })))
+}
+
+pub async fn restore(
+    Json(restore): Json<models::protected::Restore>,
+    Extension(context): Extension<Arc<Context>>,
+    Admin(claims): Admin,
+) -> Result<Json<Value>, ProxyMessageError> {
+    // Only names from the list are accepted, so that the client can't point to arbitrary files
+    let directory = context.backup_directory.clone();
+    let backups = spawn_blocking(move || list_backups(&directory))
+        .await
+        .map_err(BackupError::Task)?
+        .map_err(BackupError::IO)?;
+    if !backups.iter().any(|backup| backup.name == restore.name) {
+        return Err(ProxyMessageError::BackupNotFound(restore.name));
+    }
+    let message = ProxyMessage::Restore { name: restore.name };
+    send_message(context, &claims, message).await
+}
+
+pub async fn restore_status(
+    Extension(context): Extension<Arc<Context>>,
+    _claims: Claims,
+) -> Json<Value> {
+    let stage = context.restore_progress.get();
+    Json(json!({ "success": true, "response": stage }))
+}
+
+/// Entries are filtered by `user`, `action`, `ip`, `since` and `until`, and split into pages.
+pub async fn audit_log(
+    Query(query): Query<models::protected::AuditQuery>,
+    Extension(context): Extension<Arc<Context>>,
+    _claims: Admin,
+) -> Result<Json<Value>, AuditError> {
+    let page = spawn_blocking(move || context.audit.query(&query)).await??;
+    Ok(Json(json!({ "success": true, "response": page })))
+}
+
+pub async fn whitelist_list(_claims: Claims) -> Result<Json<Value>, RosterError> {
+    read_roster::<models::protected::WhitelistEntry>("whitelist.json").await
+}
+
+pub async fn op_list(_claims: Claims) -> Result<Json<Value>, RosterError> {
+    read_roster::<models::protected::OpEntry>("ops.json").await
+}
+
+/// Temporary bans come with the time that's left until they run out.
+pub async fn ban_list(
+    Extension(context): Extension<Arc<Context>>,
+    _claims: Claims,
+) -> Result<Json<Value>, RosterError> {
+    let now = time::OffsetDateTime::now_utc().unix_timestamp();
+    let mut entries =
+        read_entries::<models::protected::BannedPlayer>("banned-players.json").await?;
+    for entry in entries.iter_mut() {
+        entry.remaining = context
+            .temp_bans
+            .expires(&entry.name)
+            .map(|expires| (expires - now).max(0));
+    }
+    Ok(Json(json!({ "success": true, "response": entries })))
+}
+
+pub async fn ban_ip_list(_claims: Claims) -> Result<Json<Value>, RosterError> {
+    read_roster::<models::protected::BannedIp>("banned-ips.json").await
+}
+
+pub async fn console(
+    ws: WebSocketUpgrade,
+    Extension(context): Extension<Arc<Context>>,
+    Admin(claims): Admin,
+) -> Response {
+    ws.on_upgrade(|socket| console_session(socket, context, claims))
+}
+
+async fn console_session(mut socket: WebSocket, context: Arc<Context>, claims: Claims) {
+    info!("Console session opened by <{}>", &claims.sub);
+    while let Some(message) = socket.recv().await {
+        let command = match message {
+            Ok(Message::Text(command)) => command,
+            Ok(Message::Close(_)) => break,
+            Ok(_) => continue,
+            Err(e) => {
+                debug!("Console session error: {}", &e);
+                break;
+            }
+        };
+
+        let response = match console_command(context.clone(), &claims, command).await {
+            Ok(Json(response)) => response,
+            Err(e) => json!({ "success": false, "error": e.to_string() }),
+        };
+        if socket
+            .send(Message::Text(response.to_string()))
+            .await
+            .is_err()
+        {
+            break;
+        }
+    }
+    info!("Console session closed");
+}
+
+async fn console_command(
+    context: Arc<Context>,
+    claims: &Claims,
+    command: String,
+) -> Result<Json<Value>, ProxyMessageError> {
+    let command = command.trim().to_string();
+    let allowed = context.console_allow.is_empty() || context.console_allow.matches_all(&command);
+    if !allowed || context.console_deny.matches_any(&command) {
+        let message = ProxyMessage::Raw {
+            command: command.clone(),
+        };
+        let error = ProxyMessageError::CommandNotAllowed(command);
+        let serialized = serde_json::to_value(&message).unwrap_or_default();
+        audit(&context, claims, serialized, Err(&error));
+        return Err(error);
+    }
+    let message = ProxyMessage::Raw { command };
+    send_message(context, claims, message).await
+}
+
+/// The server creates these files only when they are needed for the first time,
+/// so a missing file is the same as an empty one.
+async fn read_roster<T: DeserializeOwned + Serialize>(
+    file_name: &str,
+) -> Result<Json<Value>, RosterError> {
+    let entries = read_entries::<T>(file_name).await?;
+    Ok(Json(json!({ "success": true, "response": entries })))
+}
+
+async fn read_entries<T: DeserializeOwned>(file_name: &str) -> Result<Vec<T>, RosterError> {
+    let content = match tokio::fs::read_to_string(file_name).await {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => "[]".to_string(),
+        Err(e) => return Err(RosterError::Read(file_name.to_string(), e)),
+    };
+    serde_json::from_str(&content).map_err(|e| RosterError::Parse(file_name.to_string(), e))
 }
 
 async fn send_message(
     context: Arc<Context>,
+    claims: &Claims,
     message: ProxyMessage,
 ) -> Result<Json<Value>, ProxyMessageError> {
-    let (rx, tx) = channel();
+    // Status is polled every few seconds, it's not an administrative action
+    if matches!(&message, ProxyMessage::Ping) {
+        let (rx, tx) = channel();
+        context.tx.send((message, rx)).await?;
+        let response = tx.await?;
+        return Ok(Json(json!({ "success": true, "response": response })));
+    }
 
-    context.tx.send((message, rx)).await?;
+    info!("<{}> requested {:?}", claims.actor(), &message);
+    // The message is moved into the channel, so it's serialized beforehand
+    let serialized = serde_json::to_value(&message).unwrap_or_default();
+    let (rx, tx) = channel();
+    let result = match context.tx.send((message, rx)).await {
+        Ok(_r) => tx.await.map_err(ProxyMessageError::from),
+        Err(e) => Err(ProxyMessageError::from(e)),
+    };
+    audit(&context, claims, serialized, result.as_ref());
 
-    let response = tx.await?;
+    let response = result?;
     Ok(Json(json!({ "success": true, "response": response })))
 }
+
+/// `message` is a serialized `ProxyMessage`.
+fn audit(
+    context: &Context,
+    claims: &Claims,
+    mut message: Value,
+    result: Result<&ProxyResponse, &ProxyMessageError>,
+) {
+    let (response, error) = match result {
+        Ok(response) => (serde_json::to_value(response).ok(), None),
+        Err(e) => (None, Some(e.to_string())),
+    };
+    let entry = AuditEntry {
+        time: time::OffsetDateTime::now_utc().unix_timestamp(),
+        user: claims.sub.clone(),
+        token: claims.api_token.clone(),
+        ip: claims.ip,
+        action: message["action"].as_str().unwrap_or_default().to_string(),
+        params: message["params"].take(),
+        response,
+        error,
+    };
+    context.audit.record(&entry);
+}
@@ -0,0 +1,33 @@
+use axum::{
+    extract::Path,
+    headers::Host,
+    http::{StatusCode, Uri},
+    response::{IntoResponse, Redirect, Response},
+    Extension, TypedHeader,
+};
+
+use crate::tls::Challenges;
+
+/// Answers HTTP-01 challenges of the ACME server.
+pub async fn challenge(
+    Path(token): Path<String>,
+    Extension(challenges): Extension<Challenges>,
+) -> Response {
+    match challenges.get(&token) {
+        Some(key_authorization) => key_authorization.into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// Everything else on the plain HTTP port is moved to HTTPS.
+pub async fn redirect_to_https(host: Option<TypedHeader<Host>>, uri: Uri) -> Response {
+    let host = match host {
+        Some(TypedHeader(host)) => host.hostname().to_owned(),
+        None => return StatusCode::BAD_REQUEST.into_response(),
+    };
+    let path = uri
+        .path_and_query()
+        .map(|path| path.as_str())
+        .unwrap_or("/");
+    Redirect::permanent(&format!("https://{}{}", host, path)).into_response()
+}
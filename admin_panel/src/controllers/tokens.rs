@@ -0,0 +1,65 @@
+use std::sync::Arc;
+
+use axum::{Extension, Json};
+use serde_json::{json, Value};
+use time::{Duration, OffsetDateTime};
+
+use crate::{
+    error::ApiTokenError,
+    models::auth::{ApiTokenId, Claims, NewApiToken, Role},
+    Context,
+};
+
+/// Admins see the tokens of all users.
+pub async fn list(Extension(context): Extension<Arc<Context>>, claims: Claims) -> Json<Value> {
+    let tokens = context.api_tokens.list(owner_filter(&claims));
+    Json(json!({ "success": true, "response": tokens }))
+}
+
+/// Returns the token, it's shown only once.
+pub async fn create(
+    Json(new_token): Json<NewApiToken>,
+    Extension(context): Extension<Arc<Context>>,
+    claims: Claims,
+) -> Result<Json<Value>, ApiTokenError> {
+    let name = new_token.name.trim().to_string();
+    if name.is_empty() {
+        return Err(ApiTokenError::EmptyName);
+    }
+    if new_token.scopes.is_empty() {
+        return Err(ApiTokenError::NoScopes);
+    }
+    // Tokens check the role of their owner, which is known only for local users
+    if !context.users.iter().any(|user| user.username == claims.sub) {
+        return Err(ApiTokenError::LocalUsersOnly);
+    }
+    let expires = new_token
+        .expires_in_days
+        .map(|days| (OffsetDateTime::now_utc() + Duration::days(days as i64)).unix_timestamp());
+    let (token, secret) =
+        context
+            .api_tokens
+            .create(&claims.sub, claims.role, name, new_token.scopes, expires)?;
+    Ok(Json(
+        json!({ "success": true, "response": { "token": token, "secret": secret } }),
+    ))
+}
+
+/// Admins can revoke the tokens of other users.
+pub async fn revoke(
+    Json(token): Json<ApiTokenId>,
+    Extension(context): Extension<Arc<Context>>,
+    claims: Claims,
+) -> Result<Json<Value>, ApiTokenError> {
+    context
+        .api_tokens
+        .revoke(&token.id, owner_filter(&claims))?;
+    Ok(Json(json!({ "success": true })))
+}
+
+fn owner_filter(claims: &Claims) -> Option<&str> {
+    match claims.role {
+        Role::Admin => None,
+        _ => Some(&claims.sub),
+    }
+}
@@ -0,0 +1,45 @@
+use std::sync::Arc;
+
+use axum::{Extension, Json};
+use serde_json::{json, Value};
+
+use crate::{
+    error::TotpError,
+    models::auth::{Claims, TotpCode},
+    Context,
+};
+
+pub async fn status(Extension(context): Extension<Arc<Context>>, claims: Claims) -> Json<Value> {
+    let enabled = context.totp.is_enabled(&claims.sub);
+    Json(json!({ "success": true, "response": { "enabled": enabled } }))
+}
+
+/// Starts the setup, the secret isn't used until it's confirmed with a code.
+pub async fn enroll(
+    Extension(context): Extension<Arc<Context>>,
+    claims: Claims,
+) -> Result<Json<Value>, TotpError> {
+    let enrollment = context.totp.start_enrollment(&claims.sub)?;
+    Ok(Json(json!({ "success": true, "response": enrollment })))
+}
+
+/// Returns the recovery codes, they are shown only once.
+pub async fn confirm(
+    Json(totp): Json<TotpCode>,
+    Extension(context): Extension<Arc<Context>>,
+    claims: Claims,
+) -> Result<Json<Value>, TotpError> {
+    let recovery_codes = context.totp.finish_enrollment(&claims.sub, &totp.code)?;
+    Ok(Json(
+        json!({ "success": true, "response": { "recovery_codes": recovery_codes } }),
+    ))
+}
+
+pub async fn disable(
+    Json(totp): Json<TotpCode>,
+    Extension(context): Extension<Arc<Context>>,
+    claims: Claims,
+) -> Result<Json<Value>, TotpError> {
+    context.totp.disable(&claims.sub, &totp.code)?;
+    Ok(Json(json!({ "success": true })))
+}
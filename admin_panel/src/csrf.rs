@@ -0,0 +1,105 @@
+use std::sync::Arc;
+
+use axum::{
+    http::{
+        header::{HOST, ORIGIN, REFERER, UPGRADE},
+        Method, Request,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use log::warn;
+
+use crate::{error::CsrfError, Context};
+
+/// Rejects state-changing requests and WebSocket handshakes that come from other sites.
+/// Browsers always send `Origin` with them, so requests without it, or `Referer`,
+/// come from scripts, which can't use the session of a user anyway.
+pub async fn verify_origin<B>(request: Request<B>, next: Next<B>) -> Response {
+    let websocket = request.headers().contains_key(UPGRADE);
+    let safe = matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    );
+    if safe && !websocket {
+        return next.run(request).await;
+    }
+
+    let headers = request.headers();
+    let origin = match (headers.get(ORIGIN), headers.get(REFERER)) {
+        (Some(origin), _) => origin.to_str().ok().map(|origin| origin.to_string()),
+        (None, Some(referer)) => referer.to_str().ok().map(origin_of),
+        (None, None) => return next.run(request).await,
+    };
+    let host = headers.get(HOST).and_then(|host| host.to_str().ok());
+    let allowed = match (&origin, request.extensions().get::<Arc<Context>>()) {
+        (Some(origin), Some(context)) => {
+            is_same_origin(origin, host) || context.allowed_origins.contains(origin)
+        }
+        _ => false,
+    };
+    if !allowed {
+        let origin = origin.unwrap_or_default();
+        warn!(
+            "Rejected {} {} from <{}>",
+            request.method(),
+            request.uri().path(),
+            &origin
+        );
+        return CsrfError::OriginNotAllowed(origin).into_response();
+    }
+    next.run(request).await
+}
+
+/// `https://example.com:8443/home` becomes `https://example.com:8443`.
+fn origin_of(url: &str) -> String {
+    let (scheme, rest) = match url.split_once("://") {
+        Some(parts) => parts,
+        None => return String::new(),
+    };
+    let authority = rest.split(&['/', '?', '#'][..]).next().unwrap_or_default();
+    format!("{}://{}", scheme, authority)
+}
+
+fn is_same_origin(origin: &str, host: Option<&str>) -> bool {
+    let host = match host {
+        Some(host) => host,
+        None => return false,
+    };
+    let authority = match origin.split_once("://") {
+        Some((_scheme, authority)) => authority,
+        None => return false,
+    };
+    authority.eq_ignore_ascii_case(host)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extracts_origin_from_referer() {
+        assert_eq!(
+            origin_of("https://example.com:8443/home?tab=ban#list"),
+            "https://example.com:8443"
+        );
+        assert_eq!(origin_of("https://example.com"), "https://example.com");
+        assert_eq!(origin_of("/home"), "");
+    }
+
+    #[test]
+    fn compares_origin_with_host() {
+        assert!(is_same_origin("https://Example.com", Some("example.com")));
+        assert!(is_same_origin(
+            "https://example.com:8443",
+            Some("example.com:8443")
+        ));
+        assert!(!is_same_origin(
+            "https://example.com",
+            Some("example.com:8443")
+        ));
+        assert!(!is_same_origin("https://evil.com", Some("example.com")));
+        assert!(!is_same_origin("null", Some("example.com")));
+        assert!(!is_same_origin("https://example.com", None));
+    }
+}
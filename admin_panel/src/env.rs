@@ -1,13 +1,21 @@
 use log::{error, info, warn};
 use std::io::Write;
 use std::{
+    convert::Infallible,
     fmt::Display,
     num::{NonZeroU16, NonZeroU8},
-    path::Path,
+    path::{Path, PathBuf},
     str::FromStr,
+    time::Duration,
 };
 
-use crate::error::DifficultyParserError;
+use crate::{
+    backup::{retention::RetentionPolicy, schedule::CronSchedule},
+    error::{DifficultyParserError, StatusProbeParserError},
+    oidc::{OidcSettings, RoleMapping},
+    server::tick_health::TickSettings,
+    tls::{AcmeSettings, TlsSettings},
+};
 
 static SERVER_PROPERTIES: &str = include_str!("../static/server.properties");
 
@@ -45,6 +53,137 @@ impl Display for Difficulty {
     }
 }
 
+/// Protocol used to check whether the server is alive.
+pub enum StatusProbe {
+    Query,
+    Ping,
+}
+
+impl FromStr for StatusProbe {
+    type Err = StatusProbeParserError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let probe = match s.to_ascii_lowercase().as_str() {
+            "query" => StatusProbe::Query,
+            "ping" => StatusProbe::Ping,
+            _ => return Err(StatusProbeParserError::Parse(s.to_string())),
+        };
+
+        Ok(probe)
+    }
+}
+
+impl Display for StatusProbe {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            StatusProbe::Query => write!(f, "query"),
+            StatusProbe::Ping => write!(f, "ping"),
+        }
+    }
+}
+
+/// Comma separated list of console commands, e.g. `time set, gamerule`.
+#[derive(Clone, Default)]
+pub struct CommandPrefixes(Vec<String>);
+
+impl CommandPrefixes {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Checks whether the command, or any command it runs with `execute ... run`, matches one of the prefixes.
+    pub fn matches_any(&self, command: &str) -> bool {
+        commands(command)
+            .iter()
+            .any(|command| self.matches(command))
+    }
+
+    /// Checks whether the command, and every command it runs with `execute ... run`, match the prefixes.
+    pub fn matches_all(&self, command: &str) -> bool {
+        commands(command)
+            .iter()
+            .all(|command| self.matches(command))
+    }
+
+    /// A prefix matches only whole words, so `op` matches `op Steve`, but not `openinv`.
+    fn matches(&self, command: &str) -> bool {
+        self.0.iter().any(|prefix| {
+            command == prefix
+                || (command.starts_with(prefix.as_str())
+                    && command[prefix.len()..].starts_with(' '))
+        })
+    }
+}
+
+/// The command itself and everything that follows `run` in `execute`, without the `minecraft:` namespace.
+/// A `run` that is just an argument, e.g. a player's name, only makes the checks stricter.
+pub fn commands(command: &str) -> Vec<String> {
+    let command = command.trim().trim_start_matches('/').to_ascii_lowercase();
+    let words: Vec<&str> = command.split_whitespace().collect();
+    let mut commands = vec![without_namespace(&words)];
+    if words
+        .first()
+        .map(|word| word.trim_start_matches("minecraft:"))
+        == Some("execute")
+    {
+        for (index, word) in words.iter().enumerate() {
+            if *word == "run" {
+                commands.push(without_namespace(&words[index + 1..]));
+            }
+        }
+    }
+    commands
+}
+
+fn without_namespace(words: &[&str]) -> String {
+    let mut command = words.join(" ");
+    if command.starts_with("minecraft:") {
+        command.replace_range(.."minecraft:".len(), "");
+    }
+    command
+}
+
+impl FromStr for CommandPrefixes {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let prefixes = s
+            .split(',')
+            .map(|prefix| prefix.trim().trim_start_matches('/').to_ascii_lowercase())
+            .filter(|prefix| !prefix.is_empty())
+            .collect();
+        Ok(Self(prefixes))
+    }
+}
+
+/// Comma separated list of origins, e.g. `https://example.com, https://bot.example.com:8443`.
+#[derive(Clone, Default)]
+pub struct AllowedOrigins(Vec<String>);
+
+impl AllowedOrigins {
+    pub fn contains(&self, origin: &str) -> bool {
+        let origin = origin.trim_end_matches('/').to_ascii_lowercase();
+        self.0.contains(&origin)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &String> {
+        self.0.iter()
+    }
+}
+
+impl FromStr for AllowedOrigins {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let origins = s
+            .split(',')
+            .map(|origin| origin.trim().trim_end_matches('/').to_ascii_lowercase())
+            .filter(|origin| !origin.is_empty())
+            .collect();
+        Ok(Self(origins))
+    }
+}
+
 pub struct Environment {
     pub eula: bool,
     pub difficulty: Difficulty,
@@ -56,6 +195,21 @@ pub struct Environment {
     pub server_idle_timeout: NonZeroU8,
     pub view_distance: NonZeroU8,
     pub pvp: bool,
+    pub hibernate: bool,
+    pub loopback_firewall: bool,
+    pub sleeping_motd: String,
+    pub console_allow: CommandPrefixes,
+    pub console_deny: CommandPrefixes,
+    pub status_probe: StatusProbe,
+    pub backup_schedule: Option<CronSchedule>,
+    pub backup_retention: RetentionPolicy,
+    pub crash_max_restarts: u8,
+    pub crash_restart_delay: NonZeroU8,
+    pub tick_health: TickSettings,
+    pub tls: TlsSettings,
+    pub persistent_sessions: bool,
+    pub allowed_origins: AllowedOrigins,
+    pub oidc: Option<OidcSettings>,
 }
 
 impl Environment {
@@ -71,6 +225,44 @@ impl Environment {
         let server_idle_timeout = Self::get_env("SERVER_IDLE_TIMEOUT", NonZeroU8::new(10).unwrap());
         let view_distance = Self::get_env("VIEW_DISTANCE", NonZeroU8::new(10).unwrap());
         let pvp = Self::get_env("PVP", false);
+        let hibernate = Self::get_env("HIBERNATE", false);
+        let loopback_firewall = Self::get_env("LOOPBACK_FIREWALL", true);
+        let sleeping_motd = Self::get_raw_env("SLEEPING_MOTD")
+            .unwrap_or_else(|| "Sleeping - join to start".to_owned());
+        let console_allow = Self::get_env("CONSOLE_ALLOW", CommandPrefixes::default());
+        let console_deny = Self::get_env("CONSOLE_DENY", CommandPrefixes::default());
+        let status_probe = Self::get_env("STATUS_PROBE", StatusProbe::Query);
+        let backup_schedule = Self::get_optional_env("BACKUP_SCHEDULE");
+        let backup_retention = RetentionPolicy {
+            keep_last: Self::get_env("BACKUP_KEEP_LAST", 10),
+            keep_daily: Self::get_env("BACKUP_KEEP_DAILY", 7),
+            keep_weekly: Self::get_env("BACKUP_KEEP_WEEKLY", 4),
+        };
+        let crash_max_restarts = Self::get_env("CRASH_MAX_RESTARTS", 3);
+        let crash_restart_delay = Self::get_env("CRASH_RESTART_DELAY", NonZeroU8::new(10).unwrap());
+        let tick_command: String = Self::get_env("TICK_COMMAND", "tick query".to_owned());
+        let tick_interval = Self::get_env("TICK_INTERVAL", NonZeroU16::new(30).unwrap());
+        let tick_health = TickSettings {
+            command: Some(tick_command).filter(|command| !command.trim().is_empty()),
+            interval: Duration::from_secs(tick_interval.get() as u64),
+            threshold: Self::get_env("TPS_ALERT_THRESHOLD", 15.0),
+            alert_after: Self::get_env("TPS_ALERT_SAMPLES", NonZeroU8::new(3).unwrap()).get(),
+        };
+        let acme = Self::get_optional_env::<String>("ACME_DOMAIN").map(|domain| AcmeSettings {
+            domain,
+            email: Self::get_optional_env("ACME_EMAIL"),
+            directory: Self::get_raw_env("ACME_DIRECTORY")
+                .unwrap_or_else(|| "https://acme-v02.api.letsencrypt.org/directory".to_owned()),
+            ca_cert: Self::get_raw_env("ACME_CA_CERT").map(PathBuf::from),
+        });
+        let tls = TlsSettings {
+            cert: Self::get_raw_env("TLS_CERT").map(PathBuf::from),
+            key: Self::get_raw_env("TLS_KEY").map(PathBuf::from),
+            acme,
+        };
+        let persistent_sessions = Self::get_env("PERSISTENT_SESSIONS", false);
+        let allowed_origins = Self::get_env("ALLOWED_ORIGINS", AllowedOrigins::default());
+        let oidc = Self::load_oidc();
         Self {
             eula,
             difficulty,
@@ -82,6 +274,85 @@ impl Environment {
             server_idle_timeout,
             view_distance,
             pvp,
+            hibernate,
+            loopback_firewall,
+            sleeping_motd,
+            console_allow,
+            console_deny,
+            status_probe,
+            backup_schedule,
+            backup_retention,
+            crash_max_restarts,
+            crash_restart_delay,
+            tick_health,
+            tls,
+            persistent_sessions,
+            allowed_origins,
+            oidc,
+        }
+    }
+
+    fn load_oidc() -> Option<OidcSettings> {
+        let issuer = Self::get_raw_env("OIDC_ISSUER")?;
+        let (client_id, redirect_url) = match (
+            Self::get_raw_env("OIDC_CLIENT_ID"),
+            Self::get_raw_env("OIDC_REDIRECT_URL"),
+        ) {
+            (Some(client_id), Some(redirect_url)) => (client_id, redirect_url),
+            _ => {
+                warn!(
+                    "OIDC_CLIENT_ID and OIDC_REDIRECT_URL are required, single sign-on is disabled"
+                );
+                return None;
+            }
+        };
+        // Values of the claims are case sensitive, so `get_env` can't be used
+        let roles = match Self::get_raw_env("OIDC_ROLES").map(|roles| roles.parse::<RoleMapping>())
+        {
+            Some(Ok(roles)) => roles,
+            Some(Err(e)) => {
+                warn!("<OIDC_ROLES>: {}, ignoring it", &e);
+                RoleMapping::default()
+            }
+            None => RoleMapping::default(),
+        };
+        if roles.is_empty() {
+            warn!("OIDC_ROLES is empty, nobody can sign in with single sign-on");
+        }
+        Some(OidcSettings {
+            issuer,
+            client_id,
+            client_secret: Self::get_raw_env("OIDC_CLIENT_SECRET"),
+            redirect_url,
+            scopes: Self::get_raw_env("OIDC_SCOPES").unwrap_or_else(|| "openid profile".to_owned()),
+            username_claim: Self::get_raw_env("OIDC_USERNAME_CLAIM")
+                .unwrap_or_else(|| "preferred_username".to_owned()),
+            role_claim: Self::get_raw_env("OIDC_ROLE_CLAIM").unwrap_or_else(|| "groups".to_owned()),
+            roles,
+        })
+    }
+
+    /// Paths and URLs are case sensitive, so unlike other values they aren't lowercased.
+    fn get_raw_env(key: &str) -> Option<String> {
+        match std::env::var(key) {
+            Ok(v) if v.trim().is_empty() => None,
+            Ok(v) => Some(v.trim().to_owned()),
+            Err(_) => None,
+        }
+    }
+
+    /// Same as `get_env`, but an empty or missing value means that the feature is disabled.
+    fn get_optional_env<T: FromStr>(key: &str) -> Option<T> {
+        match std::env::var(key) {
+            Ok(v) if v.trim().is_empty() => None,
+            Ok(v) => match v.to_ascii_lowercase().parse() {
+                Ok(parsed) => Some(parsed),
+                Err(_) => {
+                    warn!("<{}>=<{}>: couldn't parse value, ignoring it", key, &v);
+                    None
+                }
+            },
+            Err(_) => None,
         }
     }
 
@@ -148,4 +419,75 @@ impl Environment {
         writeln!(file, "pvp={}", &self.pvp)?;
         Ok(())
     }
+
+    /// Replaces RCON password in server.properties, or adds it if it's missing.
+    pub fn save_rcon_password(&self, password: &str) -> Result<(), std::io::Error> {
+        info!("Updating RCON password...");
+        let properties_path = Path::new("./server.properties");
+        let properties = std::fs::read_to_string(properties_path)?;
+        let password_line = format!("rcon.password={}", password);
+        let mut replaced = false;
+        let mut lines: Vec<&str> = properties
+            .lines()
+            .map(|line| {
+                if line.trim_start().starts_with("rcon.password=") {
+                    replaced = true;
+                    password_line.as_str()
+                } else {
+                    line
+                }
+            })
+            .collect();
+        if !replaced {
+            lines.push(&password_line);
+        }
+
+        let mut file = std::fs::File::create(properties_path)?;
+        for line in lines {
+            writeln!(file, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prefixes(s: &str) -> CommandPrefixes {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn matches_whole_words() {
+        let deny = prefixes("op, /time set");
+        assert!(deny.matches_any("/op Steve"));
+        assert!(deny.matches_any("  OP"));
+        assert!(deny.matches_any("time  set day"));
+        assert!(!deny.matches_any("openinv Steve"));
+        assert!(!deny.matches_any("time query daytime"));
+    }
+
+    #[test]
+    fn strips_namespace() {
+        let deny = prefixes("op");
+        assert!(deny.matches_any("minecraft:op Steve"));
+        assert!(deny.matches_any("/minecraft:op Steve"));
+        assert!(!deny.matches_any("othermod:op Steve"));
+    }
+
+    #[test]
+    fn looks_into_execute() {
+        let deny = prefixes("op");
+        assert!(deny.matches_any("execute run op Steve"));
+        assert!(deny.matches_any("execute as @a at @s run minecraft:op Steve"));
+        assert!(deny.matches_any("minecraft:execute if entity @p run execute run op Steve"));
+        assert!(!deny.matches_any("execute as @a run say op"));
+
+        let allow = prefixes("execute, say, time");
+        assert!(allow.matches_all("execute as @a run say hi"));
+        assert!(allow.matches_all("time set day"));
+        assert!(!allow.matches_all("execute as @a run op Steve"));
+        assert!(!allow.matches_all("execute run execute run op Steve"));
+    }
 }
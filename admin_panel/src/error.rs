@@ -1,13 +1,26 @@
-use std::{error::Error, io};
+use std::io;
 
-use axum::{http::StatusCode, response::IntoResponse, Json};
+use axum::{
+    http::{header::RETRY_AFTER, HeaderValue, StatusCode},
+    response::IntoResponse,
+    Json,
+};
 use log::SetLoggerError;
 use log4rs::config::runtime::ConfigErrors;
 use serde_json::json;
 use thiserror::Error;
-use tokio::sync::{mpsc::error::SendError, oneshot};
+use tokio::{
+    sync::{mpsc::error::SendError, oneshot},
+    task::JoinError,
+};
 
-use crate::server::proxy_service::{ProxyMessage, ProxyResponse};
+use crate::{
+    models::auth::Scope,
+    server::{
+        proxy_service::{ProxyMessage, ProxyResponse},
+        rcon::RconError,
+    },
+};
 
 #[derive(Error, Debug)]
 pub enum DifficultyParserError {
@@ -15,6 +28,18 @@ pub enum DifficultyParserError {
     Parse(String),
 }
 
+#[derive(Error, Debug)]
+pub enum StatusProbeParserError {
+    #[error("Couldn't parse status probe: {0}")]
+    Parse(String),
+}
+
+#[derive(Error, Debug)]
+pub enum CronParserError {
+    #[error("Couldn't parse cron schedule: {0}")]
+    Parse(String),
+}
+
 #[derive(Error, Debug)]
 pub enum LogInitError {
     #[error("Couldn't initialize logger config: {0}")]
@@ -35,6 +60,14 @@ pub enum LoginError {
     ContextNotLoaded,
     #[error("Invalid token")]
     InvalidToken,
+    #[error("Not enough permissions")]
+    Forbidden,
+    #[error("Too many login attempts, try again in {0} seconds")]
+    TooManyAttempts(u64),
+    #[error("Incorrect two-factor code")]
+    WrongCode,
+    #[error("{0}")]
+    SecondFactor(#[from] TotpError),
 }
 
 impl IntoResponse for LoginError {
@@ -44,6 +77,281 @@ impl IntoResponse for LoginError {
             LoginError::TokenCreation => StatusCode::INTERNAL_SERVER_ERROR,
             LoginError::ContextNotLoaded => StatusCode::INTERNAL_SERVER_ERROR,
             LoginError::InvalidToken => StatusCode::UNAUTHORIZED,
+            LoginError::Forbidden => StatusCode::FORBIDDEN,
+            LoginError::TooManyAttempts(_) => StatusCode::TOO_MANY_REQUESTS,
+            LoginError::WrongCode => StatusCode::UNAUTHORIZED,
+            LoginError::SecondFactor(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        let msg = format!("{}", self);
+
+        let mut response =
+            (status, Json(json!({ "success": false, "error": msg }))).into_response();
+        if let LoginError::TooManyAttempts(retry_after) = self {
+            if let Ok(value) = HeaderValue::from_str(&retry_after.to_string()) {
+                response.headers_mut().insert(RETRY_AFTER, value);
+            }
+        }
+        response
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum UsersError {
+    #[error("Couldn't read {0}: {1}")]
+    Read(String, io::Error),
+    #[error("Couldn't parse {0}: {1}")]
+    Parse(String, serde_json::Error),
+    #[error("Password hash of <{0}> is not a valid Argon2id PHC string")]
+    InvalidHash(String),
+}
+
+#[derive(Error, Debug)]
+pub enum StoreError {
+    #[error("Couldn't read {0}: {1}")]
+    Read(String, io::Error),
+    #[error("Couldn't parse {0}: {1}")]
+    Parse(String, serde_json::Error),
+    #[error("Couldn't write {0}: {1}")]
+    Write(String, io::Error),
+}
+
+#[derive(Error, Debug)]
+pub enum TotpError {
+    #[error("{0}")]
+    Store(#[from] StoreError),
+    #[error("Two-factor secret is corrupted")]
+    CorruptedSecret,
+    #[error("Two-factor authentication is already enabled")]
+    AlreadyEnabled,
+    #[error("Two-factor authentication setup wasn't started")]
+    NotEnrolled,
+    #[error("Invalid two-factor code")]
+    InvalidCode,
+}
+
+impl IntoResponse for TotpError {
+    fn into_response(self) -> axum::response::Response {
+        let status = match self {
+            TotpError::AlreadyEnabled => StatusCode::CONFLICT,
+            TotpError::NotEnrolled => StatusCode::BAD_REQUEST,
+            TotpError::InvalidCode => StatusCode::BAD_REQUEST,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        let msg = format!("{}", self);
+
+        (status, Json(json!({ "success": false, "error": msg }))).into_response()
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum SessionError {
+    #[error("Couldn't read session key: {0}")]
+    Read(io::Error),
+    #[error("Couldn't save session key: {0}")]
+    Write(io::Error),
+}
+
+#[derive(Error, Debug)]
+pub enum ApiTokenError {
+    #[error("{0}")]
+    Store(#[from] StoreError),
+    #[error("Token name can't be empty")]
+    EmptyName,
+    #[error("Token must have at least one scope")]
+    NoScopes,
+    #[error("Your role doesn't allow the <{0}> scope")]
+    ScopeNotAllowed(Scope),
+    #[error("There is no API token <{0}>")]
+    NotFound(String),
+    #[error("Only local users can create API tokens")]
+    LocalUsersOnly,
+}
+
+impl IntoResponse for ApiTokenError {
+    fn into_response(self) -> axum::response::Response {
+        let status = match self {
+            ApiTokenError::EmptyName => StatusCode::BAD_REQUEST,
+            ApiTokenError::NoScopes => StatusCode::BAD_REQUEST,
+            ApiTokenError::ScopeNotAllowed(_) => StatusCode::FORBIDDEN,
+            ApiTokenError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiTokenError::LocalUsersOnly => StatusCode::FORBIDDEN,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        let msg = format!("{}", self);
+
+        (status, Json(json!({ "success": false, "error": msg }))).into_response()
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum TlsError {
+    #[error("Couldn't read {0}: {1}")]
+    Read(String, io::Error),
+    #[error("Couldn't write {0}: {1}")]
+    Write(String, io::Error),
+    #[error("{0} doesn't exist")]
+    NotFound(String),
+    #[error("TLS_CERT and TLS_KEY must be set together")]
+    IncompletePair,
+    #[error("Invalid certificate or key: {0}")]
+    Invalid(io::Error),
+    #[error("Couldn't generate certificate: {0}")]
+    Generate(#[from] rcgen::RcgenError),
+    #[error("ACME error: {0}")]
+    Acme(#[from] AcmeError),
+    #[error("ACME task failed: {0}")]
+    Task(#[from] JoinError),
+}
+
+#[derive(Error, Debug)]
+pub enum AcmeError {
+    #[error("Couldn't connect to the ACME server: {0}")]
+    Transport(String),
+    #[error("Couldn't read the response: {0}")]
+    Read(io::Error),
+    #[error("ACME server responded with {0}: {1}")]
+    Problem(u16, String),
+    #[error("Response doesn't have {0} header")]
+    MissingHeader(&'static str),
+    #[error("ACME server doesn't offer the HTTP-01 challenge")]
+    NoHttpChallenge,
+    #[error("Unexpected status: {0}")]
+    Status(String),
+    #[error("Key error: {0}")]
+    Key(String),
+    #[error("Couldn't create certificate request: {0}")]
+    Request(#[from] rcgen::RcgenError),
+}
+
+impl From<ureq::Error> for AcmeError {
+    fn from(e: ureq::Error) -> Self {
+        match e {
+            ureq::Error::Status(code, response) => {
+                Self::Problem(code, response.into_string().unwrap_or_default())
+            }
+            ureq::Error::Transport(transport) => Self::Transport(transport.to_string()),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum AuditError {
+    #[error("Couldn't read the audit log: {0}")]
+    Read(io::Error),
+    #[error("Couldn't serialize the audit entry: {0}")]
+    Serialize(#[from] serde_json::Error),
+    #[error("Couldn't write the audit log: {0}")]
+    Write(io::Error),
+    #[error("Audit log task failed: {0}")]
+    Task(#[from] JoinError),
+}
+
+impl IntoResponse for AuditError {
+    fn into_response(self) -> axum::response::Response {
+        let status = StatusCode::INTERNAL_SERVER_ERROR;
+        let msg = format!("{}", self);
+
+        (status, Json(json!({ "success": false, "error": msg }))).into_response()
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum BanDurationParserError {
+    #[error("Couldn't parse ban duration: {0}")]
+    Parse(String),
+}
+
+#[derive(Error, Debug)]
+pub enum RoleMappingParserError {
+    #[error("Couldn't parse role mapping: {0}")]
+    Parse(String),
+}
+
+#[derive(Error, Debug)]
+pub enum OidcError {
+    #[error("Single sign-on is not configured")]
+    Disabled,
+    #[error("Couldn't connect to the identity provider: {0}")]
+    Transport(String),
+    #[error("Couldn't read the response: {0}")]
+    Read(io::Error),
+    #[error("Identity provider responded with {0}: {1}")]
+    Provider(u16, String),
+    #[error("Identity provider has a different issuer: {0}")]
+    IssuerMismatch(String),
+    #[error("Invalid redirect URL: {0}")]
+    InvalidUrl(String),
+    #[error("Login was cancelled or rejected: {0}")]
+    Denied(String),
+    #[error("Login has expired or was started in another browser")]
+    InvalidState,
+    #[error("Invalid ID token: {0}")]
+    InvalidIdToken(String),
+    #[error("<{0}> doesn't have any role in the admin panel")]
+    NoRole(String),
+    #[error("Couldn't create session")]
+    Session(#[from] LoginError),
+    #[error("Single sign-on task failed: {0}")]
+    Task(#[from] JoinError),
+}
+
+impl From<ureq::Error> for OidcError {
+    fn from(e: ureq::Error) -> Self {
+        match e {
+            ureq::Error::Status(code, response) => {
+                Self::Provider(code, response.into_string().unwrap_or_default())
+            }
+            ureq::Error::Transport(transport) => Self::Transport(transport.to_string()),
+        }
+    }
+}
+
+impl IntoResponse for OidcError {
+    fn into_response(self) -> axum::response::Response {
+        let status = match self {
+            OidcError::Disabled => StatusCode::NOT_FOUND,
+            OidcError::Transport(_) => StatusCode::BAD_GATEWAY,
+            OidcError::Provider(_, _) => StatusCode::BAD_GATEWAY,
+            OidcError::IssuerMismatch(_) => StatusCode::BAD_GATEWAY,
+            OidcError::Denied(_) => StatusCode::UNAUTHORIZED,
+            OidcError::InvalidState => StatusCode::UNAUTHORIZED,
+            OidcError::InvalidIdToken(_) => StatusCode::UNAUTHORIZED,
+            OidcError::NoRole(_) => StatusCode::FORBIDDEN,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        let msg = format!("{}", self);
+
+        (status, Json(json!({ "success": false, "error": msg }))).into_response()
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum CsrfError {
+    #[error("Requests from <{0}> are not allowed")]
+    OriginNotAllowed(String),
+}
+
+impl IntoResponse for CsrfError {
+    fn into_response(self) -> axum::response::Response {
+        let status = match self {
+            CsrfError::OriginNotAllowed(_) => StatusCode::FORBIDDEN,
+        };
+        let msg = format!("{}", self);
+
+        (status, Json(json!({ "success": false, "error": msg }))).into_response()
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum MetricsError {
+    #[error("Invalid metrics token")]
+    InvalidToken,
+}
+
+impl IntoResponse for MetricsError {
+    fn into_response(self) -> axum::response::Response {
+        let status = match self {
+            MetricsError::InvalidToken => StatusCode::UNAUTHORIZED,
         };
         let msg = format!("{}", self);
 
@@ -51,6 +359,14 @@ impl IntoResponse for LoginError {
     }
 }
 
+#[derive(Error, Debug)]
+pub enum FirewallError {
+    #[error("Couldn't run {0}: {1}")]
+    Run(String, io::Error),
+    #[error("{0} failed: {1}")]
+    Rule(String, String),
+}
+
 #[derive(Error, Debug)]
 pub enum OnlinePollerError {
     #[error("Couldn't create the watcher: {0}")]
@@ -68,12 +384,26 @@ impl IntoResponse for OnlinePollerError {
     }
 }
 
+#[derive(Error, Debug)]
+pub enum WakeListenerError {
+    #[error("Couldn't listen on the game port: {0}")]
+    Bind(#[from] io::Error),
+}
+
 #[derive(Error, Debug)]
 pub enum ProxyMessageError {
     #[error("Couldn't send message to Minecraft server")]
     ChannelClosed,
     #[error("Couldn't receive response from Minecraft server")]
     IncomingChannelClosed,
+    #[error("Command <{0}> is not allowed")]
+    CommandNotAllowed(String),
+    #[error("Backup <{0}> doesn't exist")]
+    BackupNotFound(String),
+    #[error("{0}")]
+    InvalidDuration(#[from] BanDurationParserError),
+    #[error("Couldn't list backups: {0}")]
+    Backup(#[from] BackupError),
 }
 
 impl From<SendError<(ProxyMessage, oneshot::Sender<ProxyResponse>)>> for ProxyMessageError {
@@ -90,7 +420,12 @@ impl From<oneshot::error::RecvError> for ProxyMessageError {
 
 impl IntoResponse for ProxyMessageError {
     fn into_response(self) -> axum::response::Response {
-        let status = StatusCode::INTERNAL_SERVER_ERROR;
+        let status = match self {
+            ProxyMessageError::CommandNotAllowed(_) => StatusCode::FORBIDDEN,
+            ProxyMessageError::BackupNotFound(_) => StatusCode::NOT_FOUND,
+            ProxyMessageError::InvalidDuration(_) => StatusCode::BAD_REQUEST,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
         let msg = format!("{}", self);
 
         (status, Json(json!({ "success": false, "error": msg }))).into_response()
@@ -105,12 +440,39 @@ pub enum ProxyResponseError {
     IncomingChannelClosed,
     #[error("Sending messages to the server too often")]
     Spam,
-    #[error("Can't connect to MC server: {0}")]
-    McServerConnect(Box<dyn Error>),
-    #[error("Couldn't authenticate to MC server: {0}")]
-    McServerAuth(Box<dyn Error>),
     #[error("Couldn't send command to MC server: {0}")]
-    McServerCommand(Box<dyn Error>),
+    Rcon(#[from] RconError),
+    #[error("Couldn't wait for players: {0}")]
+    Wake(#[from] WakeListenerError),
+    #[error("Couldn't back up the world: {0}")]
+    Backup(#[from] BackupError),
+}
+
+#[derive(Error, Debug)]
+pub enum BackupError {
+    #[error("Backup is already in progress")]
+    InProgress,
+    #[error("Restore is already in progress")]
+    RestoreInProgress,
+    #[error("Archive doesn't contain world/level.dat")]
+    NoLevelDat,
+    #[error("Generic IO error: {0}")]
+    IO(#[from] io::Error),
+    #[error("Backup task failed: {0}")]
+    Task(#[from] JoinError),
+}
+
+impl IntoResponse for BackupError {
+    fn into_response(self) -> axum::response::Response {
+        let status = match self {
+            BackupError::InProgress => StatusCode::CONFLICT,
+            BackupError::RestoreInProgress => StatusCode::CONFLICT,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        let msg = format!("{}", self);
+
+        (status, Json(json!({ "success": false, "error": msg }))).into_response()
+    }
 }
 
 impl From<ProxyResponse> for ProxyResponseError {
@@ -118,3 +480,20 @@ impl From<ProxyResponse> for ProxyResponseError {
         Self::ResponseChannelClosed
     }
 }
+
+#[derive(Error, Debug)]
+pub enum RosterError {
+    #[error("Couldn't read {0}: {1}")]
+    Read(String, io::Error),
+    #[error("Couldn't parse {0}: {1}")]
+    Parse(String, serde_json::Error),
+}
+
+impl IntoResponse for RosterError {
+    fn into_response(self) -> axum::response::Response {
+        let status = StatusCode::INTERNAL_SERVER_ERROR;
+        let msg = format!("{}", self);
+
+        (status, Json(json!({ "success": false, "error": msg }))).into_response()
+    }
+}
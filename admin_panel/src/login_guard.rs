@@ -0,0 +1,155 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+/// Failed attempts allowed before the client is locked out.
+const FREE_ATTEMPTS: u32 = 5;
+/// The first lockout, every next one is twice as long.
+const INITIAL_LOCKOUT: Duration = Duration::from_secs(30);
+const MAX_LOCKOUT: Duration = Duration::from_secs(60 * 60);
+/// Clients that haven't failed for that long are forgotten.
+const FORGET_AFTER: Duration = Duration::from_secs(24 * 60 * 60);
+/// Login attempts from all clients together, refilled at `GLOBAL_REFILL` per second.
+const GLOBAL_BURST: f64 = 30.0;
+const GLOBAL_REFILL: f64 = 0.5;
+
+struct Attempts {
+    failures: u32,
+    last_failure: Instant,
+    locked_until: Option<Instant>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Slows down password guessing: each client is locked out for exponentially longer periods
+/// after several failed attempts, and all clients together can't try more than a few times per second.
+pub struct LoginGuard {
+    clients: Mutex<HashMap<IpAddr, Attempts>>,
+    bucket: Mutex<Bucket>,
+}
+
+impl LoginGuard {
+    pub fn new() -> Self {
+        Self {
+            clients: Mutex::new(HashMap::new()),
+            bucket: Mutex::new(Bucket {
+                tokens: GLOBAL_BURST,
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    /// Returns for how long the client has to wait, if it's not allowed to try now.
+    pub fn check(&self, ip: IpAddr) -> Result<(), Duration> {
+        let now = Instant::now();
+        {
+            let clients = self.clients.lock().unwrap_or_else(PoisonError::into_inner);
+            if let Some(locked_until) = clients.get(&ip).and_then(|a| a.locked_until) {
+                if locked_until > now {
+                    return Err(locked_until - now);
+                }
+            }
+        }
+
+        let mut bucket = self.bucket.lock().unwrap_or_else(PoisonError::into_inner);
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * GLOBAL_REFILL).min(GLOBAL_BURST);
+        bucket.updated = now;
+        if bucket.tokens < 1.0 {
+            let wait = (1.0 - bucket.tokens) / GLOBAL_REFILL;
+            return Err(Duration::from_secs_f64(wait));
+        }
+        bucket.tokens -= 1.0;
+        Ok(())
+    }
+
+    /// Returns the duration of the lockout, if the client is locked out after this attempt.
+    pub fn record_failure(&self, ip: IpAddr) -> Option<Duration> {
+        let now = Instant::now();
+        let mut clients = self.clients.lock().unwrap_or_else(PoisonError::into_inner);
+        clients.retain(|_ip, attempts| now.duration_since(attempts.last_failure) < FORGET_AFTER);
+
+        let attempts = clients.entry(ip).or_insert(Attempts {
+            failures: 0,
+            last_failure: now,
+            locked_until: None,
+        });
+        attempts.failures += 1;
+        attempts.last_failure = now;
+        if attempts.failures < FREE_ATTEMPTS {
+            return None;
+        }
+        let exponent = (attempts.failures - FREE_ATTEMPTS).min(16);
+        let lockout = INITIAL_LOCKOUT
+            .saturating_mul(2u32.pow(exponent))
+            .min(MAX_LOCKOUT);
+        attempts.locked_until = Some(now + lockout);
+        Some(lockout)
+    }
+
+    pub fn record_success(&self, ip: IpAddr) {
+        let mut clients = self.clients.lock().unwrap_or_else(PoisonError::into_inner);
+        clients.remove(&ip);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    const CLIENT: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+    const OTHER: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+
+    #[test]
+    fn escalates_lockouts() {
+        let guard = LoginGuard::new();
+        for _ in 1..FREE_ATTEMPTS {
+            assert_eq!(guard.record_failure(CLIENT), None);
+        }
+        assert!(guard.check(CLIENT).is_ok());
+
+        assert_eq!(guard.record_failure(CLIENT), Some(INITIAL_LOCKOUT));
+        let wait = guard.check(CLIENT).unwrap_err();
+        assert!(wait > INITIAL_LOCKOUT - Duration::from_secs(1) && wait <= INITIAL_LOCKOUT);
+        assert!(guard.check(OTHER).is_ok());
+
+        assert_eq!(guard.record_failure(CLIENT), Some(INITIAL_LOCKOUT * 2));
+        assert_eq!(guard.record_failure(CLIENT), Some(INITIAL_LOCKOUT * 4));
+        for _ in 0..20 {
+            guard.record_failure(CLIENT);
+        }
+        assert_eq!(guard.record_failure(CLIENT), Some(MAX_LOCKOUT));
+    }
+
+    #[test]
+    fn resets_on_success() {
+        let guard = LoginGuard::new();
+        for _ in 0..FREE_ATTEMPTS {
+            guard.record_failure(CLIENT);
+        }
+        assert!(guard.check(CLIENT).is_err());
+
+        guard.record_success(CLIENT);
+        assert!(guard.check(CLIENT).is_ok());
+        assert_eq!(guard.record_failure(CLIENT), None);
+    }
+
+    #[test]
+    fn limits_all_clients_together() {
+        let guard = LoginGuard::new();
+        for n in 0..GLOBAL_BURST as u8 {
+            let ip = IpAddr::V4(Ipv4Addr::new(198, 51, 100, n));
+            assert!(guard.check(ip).is_ok());
+        }
+        let wait = guard.check(CLIENT).unwrap_err();
+        assert!(wait <= Duration::from_secs_f64(1.0 / GLOBAL_REFILL));
+    }
+}
@@ -2,47 +2,138 @@ use std::collections::HashSet;
 use std::env::current_dir;
 use std::ffi::OsString;
 use std::fs::{self, read_dir};
+use std::io::ErrorKind;
 use std::io::Write;
-use std::path::Path;
-use std::process::{ExitStatus, Stdio};
-use std::{env::VarError, net::SocketAddr, sync::Arc, time::Duration};
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Output, Stdio};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
 
+use api_token::ApiTokens;
+use audit::AuditLog;
+use axum::http::header::{
+    AUTHORIZATION, CONTENT_SECURITY_POLICY, CONTENT_TYPE, REFERRER_POLICY, X_CONTENT_TYPE_OPTIONS,
+    X_FRAME_OPTIONS,
+};
+use axum::http::{HeaderValue, Method};
 use axum::routing::delete;
 use axum::{
-    routing::{get, post},
+    middleware,
+    routing::{get, post, put},
     Extension, Router,
 };
-use controllers::{auth, protected};
+use axum_server::Handle;
+use backup::restore::{restore_archive, RestoreProgress, RestoreStage};
+use backup::BackupSettings;
+use controllers::{auth, metrics, protected};
 use fs_extra::dir::CopyOptions;
 use log::{error, info, warn};
-use models::auth::Keys;
-use rand::RngCore;
+use login_guard::LoginGuard;
+use models::auth::{Keys, Role, User};
+use oidc::OidcClient;
+use password::{dummy_hash, hash_password, is_valid_hash};
+use rand::{distributions::Alphanumeric, Rng, RngCore};
+use server::firewall::{self, RCON_PORT};
+use server::list_ping::ListPingPoller;
+use server::log_buffer::LogBuffer;
+use server::metrics::Metrics;
 use server::online_poller::OnlinePoller;
-use server::proxy_service::{ProxyMessage, ProxyResponse, ProxyService};
+use server::process::{CrashBackoff, ProcessState};
+use server::proxy_service::{ProxyMessage, ProxyResponse, ProxyService, ProxySettings};
+use server::rcon::RconClient;
+use server::status::StatusProber;
+use server::temp_bans::TempBans;
+use server::wake_listener::WakeListener;
+use session::RevokedSessions;
+use tls::Challenges;
 use tokio::process::{Child, Command};
 use tokio::sync::mpsc::Sender;
 use tokio::sync::oneshot;
-use tokio::time::{timeout_at, Instant};
-use tower_http::cors::{Any, CorsLayer};
+use tokio::task::{spawn_blocking, JoinHandle};
+use tokio::time::{sleep, timeout_at, Instant};
+use totp::TotpStore;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tower_http::set_header::SetResponseHeaderLayer;
 
-use crate::env::Environment;
+use crate::env::{AllowedOrigins, CommandPrefixes, Environment, StatusProbe};
+use crate::error::{BackupError, SessionError, UsersError};
 
+mod api_token;
+mod audit;
+mod backup;
 mod controllers;
+mod csrf;
 mod env;
 mod error;
 mod logger;
+mod login_guard;
 mod models;
+mod oidc;
+mod password;
 mod server;
+mod session;
+mod store;
+mod tls;
+mod totp;
+
+static USERS_FILE: &str = "/data/users.json";
+static TOTP_FILE: &str = "/data/totp.json";
+/// Scripts and styles are loaded only from the panel itself and the CDNs used by the pages.
+static CSP: &str = "default-src 'self'; \
+    script-src 'self' https://ajax.googleapis.com https://cdnjs.cloudflare.com; \
+    style-src 'self' 'unsafe-inline' https://cdn.simplecss.org https://cdnjs.cloudflare.com; \
+    img-src 'self' data:; connect-src 'self'; object-src 'none'; base-uri 'none'; \
+    form-action 'self'; frame-ancestors 'none'";
+static SESSION_SECRET_FILE: &str = "/data/session.key";
+static REVOKED_SESSIONS_FILE: &str = "/data/revoked-sessions.json";
+static API_TOKENS_FILE: &str = "/data/api-tokens.json";
+static AUDIT_FILE: &str = "/data/audit.jsonl";
+static TEMP_BANS_FILE: &str = "/data/temp-bans.json";
+
+/// These files and dirs are accessed directly in `/data`, and aren't linked into the server dir.
+static DATA_ONLY: [&str; 11] = [
+    "world",
+    "world.previous",
+    "backups",
+    ".restore",
+    "totp.json",
+    "tls",
+    "session.key",
+    "revoked-sessions.json",
+    "api-tokens.json",
+    "audit.jsonl",
+    "temp-bans.json",
+];
 
 pub struct Context {
-    pub username: String,
-    pub password: String,
+    pub users: Vec<User>,
+    pub dummy_hash: String,
+    pub login_guard: LoginGuard,
+    pub totp: TotpStore,
     pub keys: Keys,
+    pub revoked_sessions: RevokedSessions,
+    pub api_tokens: ApiTokens,
+    pub oidc: Option<Arc<OidcClient>>,
+    pub audit: AuditLog,
+    pub temp_bans: Arc<TempBans>,
+    pub allowed_origins: AllowedOrigins,
+    pub console_allow: CommandPrefixes,
+    pub console_deny: CommandPrefixes,
+    pub backup_directory: PathBuf,
+    pub restore_progress: RestoreProgress,
+    pub logs: LogBuffer,
+    pub metrics: Metrics,
+    pub metrics_token: Option<String>,
+    pub process: ProcessState,
     pub tx: Sender<(ProxyMessage, oneshot::Sender<ProxyResponse>)>,
 }
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() {
+    if std::env::args().nth(1).as_deref() == Some("hash-password") {
+        hash_password_command();
+        return;
+    }
+
     logger::init_logger().expect("Couldn't create logger, shutting down...");
     let current_dir = match current_dir() {
         Ok(r) => r,
@@ -68,20 +159,42 @@ async fn main() {
         }
     }
 
-    let (username, password) = match load_credentials() {
-        Ok((u, p)) => (u, p),
+    let users = match load_users() {
+        Ok(users) => users,
         Err(e) => {
-            error!("Couldn't load credentials: {}", &e);
+            error!("Couldn't load users: {}", &e);
             return;
         }
     };
 
-    if username.is_empty() || password.is_empty() {
-        error!("Username and password must not be empty!");
+    if users.is_empty() {
+        error!(
+            "There are no users! Set ADMIN_USERNAME and ADMIN_PASSWORD, or add users into {}",
+            USERS_FILE
+        );
         return;
     }
+    let dummy_hash = match dummy_hash(
+        users
+            .iter()
+            .filter_map(|user| user.password_hash.as_deref()),
+    ) {
+        Ok(dummy_hash) => dummy_hash,
+        Err(e) => {
+            error!("Couldn't hash dummy password: {}", &e);
+            return;
+        }
+    };
+
+    let totp = match TotpStore::load(PathBuf::from(TOTP_FILE)) {
+        Ok(totp) => totp,
+        Err(e) => {
+            error!("Couldn't load two-factor secrets: {}", &e);
+            return;
+        }
+    };
 
-    let env = Environment::load();
+    let mut env = Environment::load();
     if !env.eula_accepted() {
         return;
     }
@@ -94,6 +207,78 @@ async fn main() {
         }
     }
 
+    // RCON password is regenerated on every launch, so it never leaks outside of this process
+    let rcon_password: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect();
+    match env.save_rcon_password(&rcon_password) {
+        Ok(_r) => {}
+        Err(e) => {
+            error!("Couldn't save RCON password: {}", &e);
+            return;
+        }
+    }
+    if env.loopback_firewall {
+        if let Err(e) = firewall::restrict_to_loopback().await {
+            error!(
+                "Couldn't restrict query and RCON to loopback: {}. Run the container with `--cap-add=NET_ADMIN`, or set LOOPBACK_FIREWALL=false",
+                &e
+            );
+            return;
+        }
+    } else {
+        warn!("LOOPBACK_FIREWALL is off, query and RCON ports are reachable over the network");
+    }
+
+    let secret = match load_session_secret(env.persistent_sessions) {
+        Ok(secret) => secret,
+        Err(e) => {
+            error!("Couldn't load session secret: {}", &e);
+            return;
+        }
+    };
+    let revoked_sessions = match RevokedSessions::load(PathBuf::from(REVOKED_SESSIONS_FILE)) {
+        Ok(revoked_sessions) => revoked_sessions,
+        Err(e) => {
+            error!("Couldn't load revoked sessions: {}", &e);
+            return;
+        }
+    };
+    let api_tokens = match ApiTokens::load(PathBuf::from(API_TOKENS_FILE)) {
+        Ok(api_tokens) => api_tokens,
+        Err(e) => {
+            error!("Couldn't load API tokens: {}", &e);
+            return;
+        }
+    };
+    let temp_bans = match TempBans::load(PathBuf::from(TEMP_BANS_FILE)) {
+        Ok(temp_bans) => Arc::new(temp_bans),
+        Err(e) => {
+            error!("Couldn't load temporary bans: {}", &e);
+            return;
+        }
+    };
+
+    info!("Loading TLS certificate...");
+    let tls_config = match tls::load(&env.tls).await {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Couldn't load TLS certificate: {}", &e);
+            return;
+        }
+    };
+    let challenges = Challenges::default();
+    // A user supplied certificate takes precedence, so ACME certificates would never be used
+    if let (None, None, Some(acme)) = (&env.tls.cert, &env.tls.key, env.tls.acme.clone()) {
+        tokio::spawn(tls::keep_renewed(
+            acme,
+            tls_config.clone(),
+            challenges.clone(),
+        ));
+    }
+
     let server = match start_server() {
         Ok(server_process) => server_process,
         Err(e) => {
@@ -104,83 +289,239 @@ async fn main() {
 
     info!("Starting proxy layer...");
     let idle_timeout = Duration::from_secs(env.server_idle_timeout.get() as u64 * 60);
-    let online_poller = match OnlinePoller::new().await {
-        Ok(online_poller) => online_poller,
-        Err(e) => {
-            error!("Couldn't create Online watcher: {}", &e);
-            return;
-        }
+    info!("Using <{}> to check server status...", &env.status_probe);
+    let status_prober: Box<dyn StatusProber> = match env.status_probe {
+        StatusProbe::Query => match OnlinePoller::new().await {
+            Ok(online_poller) => Box::new(online_poller),
+            Err(e) => {
+                error!("Couldn't create Online watcher: {}", &e);
+                return;
+            }
+        },
+        StatusProbe::Ping => Box::new(ListPingPoller::new()),
     };
 
-    let (proxy_service, tx) = ProxyService::new(online_poller, idle_timeout);
-    let proxy_task = tokio::spawn(proxy_service.run());
+    let rcon = RconClient::new(SocketAddr::from(([127, 0, 0, 1], RCON_PORT)), rcon_password);
+    let world_path = PathBuf::from("/data/world");
+    let backup_directory = PathBuf::from("/data/backups");
+    let restore_progress = RestoreProgress::new();
+    let logs = LogBuffer::new(1000);
+    let backups = BackupSettings {
+        schedule: env.backup_schedule.take(),
+        retention: env.backup_retention.clone(),
+        world: world_path.clone(),
+        directory: backup_directory.clone(),
+        restore_progress: restore_progress.clone(),
+    };
+    let process_state = ProcessState::new();
+    process_state.record_start(server.id());
+    let metrics = Metrics::new();
+    let mut crash_backoff = CrashBackoff::new(
+        env.crash_max_restarts,
+        Duration::from_secs(env.crash_restart_delay.get() as u64),
+    );
+    let settings = ProxySettings {
+        idle_timeout,
+        backups,
+        tick_health: env.tick_health.clone(),
+        temp_bans: temp_bans.clone(),
+    };
+    let (proxy_service, tx) = ProxyService::new(
+        status_prober,
+        rcon,
+        settings,
+        process_state.clone(),
+        logs.clone(),
+        metrics.clone(),
+    );
 
     info!("Starting web server...");
-    let mut secret = [0u8; 64];
-    rand::thread_rng().fill_bytes(&mut secret);
     let keys = Keys::new(&secret);
 
     let context = Arc::new(Context {
-        username,
-        password,
+        users,
+        dummy_hash,
+        login_guard: LoginGuard::new(),
+        totp,
         keys,
+        revoked_sessions,
+        api_tokens,
+        oidc: env
+            .oidc
+            .take()
+            .map(|settings| Arc::new(OidcClient::new(settings))),
+        audit: AuditLog::new(PathBuf::from(AUDIT_FILE)),
+        temp_bans,
+        allowed_origins: env.allowed_origins.clone(),
+        console_allow: env.console_allow.clone(),
+        console_deny: env.console_deny.clone(),
+        backup_directory: backup_directory.clone(),
+        restore_progress: restore_progress.clone(),
+        logs: logs.clone(),
+        metrics: metrics.clone(),
+        metrics_token: load_metrics_token(),
+        process: process_state.clone(),
         tx,
     });
 
-    /**
-    * You can test that these endpoints by running
-    * perl -e '$??s:;s:s;;$?::s;;=]=>%-{<-|}<&|`{;; y; -/:-@[-`{-};`-{/" -;;s;;$_;see'
-    * This will perform a simple load test
-    */
     let router = Router::new()
         .route("/", get(auth::login))
         .route("/login", post(auth::login_post))
+        .route("/logout", post(auth::logout))
+        .route("/login/totp", get(auth::totp))
+        .route("/login/totp", post(auth::totp_post))
+        .route("/login/oidc", get(auth::oidc_login))
+        .route("/login/oidc/callback", get(auth::oidc_callback))
         .route("/home", get(protected::home))
+        .route("/home.js", get(protected::home_script))
         .route("/ban", post(protected::ban_user))
         .route("/ban", delete(protected::pardon))
+        .route("/ban", get(protected::ban_list))
+        .route("/ban-ip", get(protected::ban_ip_list))
         .route("/kick", post(protected::kick_user))
         .route("/whitelist", post(protected::whitelist_add))
         .route("/whitelist", delete(protected::whitelist_remove))
+        .route("/whitelist", get(protected::whitelist_list))
         .route("/op", post(protected::op_add))
         .route("/op", delete(protected::de_op))
+        .route("/op", get(protected::op_list))
         .route("/ping", get(protected::server_status))
-        .layer(CorsLayer::new().allow_origin(Any))
+        .route("/console", get(protected::console))
+        .route("/logs", get(protected::logs))
+        .route("/logs/stream", get(protected::logs_stream))
+        .route("/metrics", get(metrics::metrics))
+        .route("/audit", get(protected::audit_log))
+        .route("/backup", post(protected::backup))
+        .route("/backups", get(protected::backup_list))
+        .route("/restore", post(protected::restore))
+        .route("/restore", get(protected::restore_status))
+        .route("/totp", get(controllers::totp::status))
+        .route("/totp", post(controllers::totp::enroll))
+        .route("/totp", put(controllers::totp::confirm))
+        .route("/totp", delete(controllers::totp::disable))
+        .route("/tokens", get(controllers::tokens::list))
+        .route("/tokens", post(controllers::tokens::create))
+        .route("/tokens", delete(controllers::tokens::revoke))
+        .layer(middleware::from_fn(auth::refresh_session))
+        .layer(middleware::from_fn(csrf::verify_origin))
+        .layer(cors_layer(&env.allowed_origins))
+        .layer(SetResponseHeaderLayer::overriding(
+            CONTENT_SECURITY_POLICY,
+            HeaderValue::from_static(CSP),
+        ))
+        .layer(SetResponseHeaderLayer::overriding(
+            X_FRAME_OPTIONS,
+            HeaderValue::from_static("DENY"),
+        ))
+        .layer(SetResponseHeaderLayer::overriding(
+            X_CONTENT_TYPE_OPTIONS,
+            HeaderValue::from_static("nosniff"),
+        ))
+        .layer(SetResponseHeaderLayer::overriding(
+            REFERRER_POLICY,
+            HeaderValue::from_static("same-origin"),
+        ))
         .layer(Extension(context.clone()));
 
-    let addr = SocketAddr::from(([0, 0, 0, 0], 80));
-    let result = axum::Server::bind(&addr)
-        .serve(router.into_make_service())
-        .with_graceful_shutdown(async {
-            // If the server closed normally, proxy_task will return immediately.
-            // In case MC server crashed, proxy will stop after `idle_timeout` minutes.
-            match proxy_task.await {
-                Ok(_r) => {}
-                Err(e) => error!("Error while waiting for proxy layer to shutdown: {}", &e),
+    // Plain HTTP is used only for ACME challenges and redirects to HTTPS
+    let http_router = Router::new()
+        .route(
+            "/.well-known/acme-challenge/:token",
+            get(controllers::tls::challenge),
+        )
+        .fallback(get(controllers::tls::redirect_to_https))
+        .layer(Extension(challenges));
+    tokio::spawn(async {
+        let addr = SocketAddr::from(([0, 0, 0, 0], 80));
+        let result = axum_server::bind(addr)
+            .serve(http_router.into_make_service())
+            .await;
+        if let Err(e) = result {
+            error!("Error while serving HTTP: {}", &e);
+        }
+    });
+
+    let wake_listener = WakeListener::new(env.sleeping_motd.clone(), env.max_players.get());
+    let addr = SocketAddr::from(([0, 0, 0, 0], 443));
+    let handle = Handle::new();
+    let web_server = axum_server::bind_rustls(addr, tls_config)
+        .handle(handle.clone())
+        .serve(router.into_make_service_with_connect_info::<SocketAddr>());
+    let (result, _r) = tokio::join!(web_server, async {
+        let mut server = server;
+        let mut proxy_service = proxy_service;
+        loop {
+            let started = Instant::now();
+            let (exited_tx, exited_rx) = oneshot::channel();
+            let server_task = tokio::spawn(watch_server(server, logs.clone(), exited_tx));
+            // If the server exits by itself, e.g. crashes, proxy returns immediately.
+            proxy_service = match tokio::spawn(proxy_service.run(exited_rx)).await {
+                Ok(r) => r,
+                Err(e) => {
+                    error!("Error while waiting for proxy layer to shutdown: {}", &e);
+                    break;
+                }
             };
-            // Wait for MC server to stop
-            let deadline = Instant::now() + idle_timeout;
-            match timeout_at(deadline, server.wait_with_output()).await {
-                Ok(r) => {
-                    match r {
-                        Ok(r) => save_output(&current_dir, "mc_error.log", r.status, r.stderr),
-                        Err(e) => error!(
-                            "Error while waiting for the server to shutdown: {}. Proceeding anyway",
-                            &e
-                        ),
-                    };
+            let exit_status = wait_for_server(&current_dir, server_task, idle_timeout).await;
+            let crashed = process_state.record_exit(exit_status);
+            if !proxy_service.restore_requested() {
+                if crashed {
+                    match crash_backoff.next_delay(started.elapsed()) {
+                        Some(delay) => {
+                            warn!(
+                                "MC server crashed, restarting in {} seconds...",
+                                delay.as_secs()
+                            );
+                            sleep(delay).await;
+                        }
+                        None => {
+                            error!("MC server keeps crashing, giving up");
+                            break;
+                        }
+                    }
+                } else {
+                    if !env.hibernate {
+                        break;
+                    }
+
+                    // Save server files right away, in case the container is stopped while hibernating
+                    match backup_files(&current_dir, existing_files.clone())
+                        .and_then(|_r| recreate_symlinks(&current_dir))
+                    {
+                        Ok(_r) => {}
+                        Err(e) => error!("Error while backuping server files: {}", &e),
+                    }
+                    match proxy_service.hibernate(&wake_listener).await {
+                        Ok(_r) => {}
+                        Err(e) => {
+                            error!("Error while hibernating: {}", &e);
+                            break;
+                        }
+                    }
                 }
-                Err(_) => error!("Couldn't shutdown MC server in time. Proceeding anyway"),
             }
-            // Move all files from `/server` to `/data` directory. Upon the next launch, the server will
-            // create symlinks to these dirs and files, so from that point on Fabric and Minecraft server
-            // will write to `/data` directory directly.
-            match backup_files(&current_dir, existing_files) {
-                Ok(_r) => {}
-                Err(e) => error!("Error while backuping server files: {}", &e),
+            if let Some(name) = proxy_service.take_restore() {
+                restore_world(&restore_progress, &backup_directory, &world_path, name).await;
             }
-            info!("Closing web server...");
-        })
-        .await;
+            server = match start_server() {
+                Ok(server_process) => server_process,
+                Err(e) => {
+                    error!("Couldn't start Minecraft server: {}", &e);
+                    break;
+                }
+            };
+            process_state.record_start(server.id());
+        }
+        // Move all files from `/server` to `/data` directory. Upon the next launch, the server will
+        // create symlinks to these dirs and files, so from that point on Fabric and Minecraft server
+        // will write to `/data` directory directly.
+        match backup_files(&current_dir, existing_files) {
+            Ok(_r) => {}
+            Err(e) => error!("Error while backuping server files: {}", &e),
+        }
+        info!("Closing web server...");
+        handle.graceful_shutdown(Some(Duration::from_secs(10)));
+    });
 
     match result {
         Ok(_r) => {}
@@ -188,11 +529,136 @@ async fn main() {
     };
 }
 
-fn load_credentials() -> Result<(String, String), VarError> {
-    info!("Loading credentials...");
-    let username = std::env::var("ADMIN_USERNAME")?;
-    let password = std::env::var("ADMIN_PASSWORD")?;
-    Ok((username, password))
+/// Reads the password from stdin and prints its hash, so that it can be put into `ADMIN_PASSWORD_HASH`.
+fn hash_password_command() {
+    eprint!("Password: ");
+    let mut password = String::new();
+    if let Err(e) = std::io::stdin().read_line(&mut password) {
+        eprintln!("Couldn't read the password: {}", &e);
+        std::process::exit(1);
+    }
+    let password = password.trim_end_matches(&['\r', '\n'][..]);
+    if password.is_empty() {
+        eprintln!("Password must not be empty");
+        std::process::exit(1);
+    }
+    match hash_password(password) {
+        Ok(hash) => println!("{}", hash),
+        Err(e) => {
+            eprintln!("Couldn't hash the password: {}", &e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// `ADMIN_USERNAME` and `ADMIN_PASSWORD` or `ADMIN_PASSWORD_HASH` describe an admin, other users are listed in the users file.
+fn load_users() -> Result<Vec<User>, UsersError> {
+    info!("Loading users...");
+    let mut users = Vec::new();
+    let username = std::env::var("ADMIN_USERNAME").unwrap_or_default();
+    let password = std::env::var("ADMIN_PASSWORD").unwrap_or_default();
+    let password_hash = std::env::var("ADMIN_PASSWORD_HASH").unwrap_or_default();
+    if !username.is_empty() && (!password.is_empty() || !password_hash.is_empty()) {
+        if !password.is_empty() && password_hash.is_empty() {
+            warn!("ADMIN_PASSWORD is stored in plain text, consider using ADMIN_PASSWORD_HASH instead");
+        }
+        users.push(User {
+            username,
+            password: Some(password).filter(|p| !p.is_empty()),
+            password_hash: Some(password_hash).filter(|h| !h.is_empty()),
+            role: Role::Admin,
+        });
+    }
+
+    let content = match fs::read_to_string(USERS_FILE) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(users),
+        Err(e) => return Err(UsersError::Read(USERS_FILE.to_string(), e)),
+    };
+    let listed: Vec<User> =
+        serde_json::from_str(&content).map_err(|e| UsersError::Parse(USERS_FILE.to_string(), e))?;
+    for user in listed {
+        let has_password = matches!(&user.password, Some(p) if !p.is_empty())
+            || matches!(&user.password_hash, Some(h) if !h.is_empty());
+        if user.username.is_empty() || !has_password {
+            warn!("Skipping a user with empty username or password");
+            continue;
+        }
+        if users
+            .iter()
+            .any(|u| u.username.to_lowercase() == user.username.to_lowercase())
+        {
+            warn!("Skipping duplicate user <{}>", &user.username);
+            continue;
+        }
+        users.push(user);
+    }
+    for user in users.iter() {
+        if let Some(hash) = &user.password_hash {
+            if !is_valid_hash(hash) {
+                return Err(UsersError::InvalidHash(user.username.clone()));
+            }
+        }
+    }
+    info!("Loaded {} users", users.len());
+    Ok(users)
+}
+
+/// Only the origins from `ALLOWED_ORIGINS` can call the API from other sites.
+fn cors_layer(allowed_origins: &AllowedOrigins) -> CorsLayer {
+    let origins: Vec<HeaderValue> = allowed_origins
+        .iter()
+        .filter_map(|origin| match HeaderValue::from_str(origin) {
+            Ok(value) => Some(value),
+            Err(_) => {
+                warn!("<{}> is not a valid origin, ignoring it", origin);
+                None
+            }
+        })
+        .collect();
+    if origins.is_empty() {
+        return CorsLayer::new();
+    }
+    CorsLayer::new()
+        .allow_origin(AllowOrigin::list(origins))
+        .allow_methods(vec![Method::GET, Method::POST, Method::PUT, Method::DELETE])
+        .allow_headers(vec![CONTENT_TYPE, AUTHORIZATION])
+        .allow_credentials(true)
+}
+
+/// Tokens signed with a random secret stop working after a restart, so with persistent sessions
+/// the secret is kept in `/data`.
+fn load_session_secret(persistent: bool) -> Result<Vec<u8>, SessionError> {
+    let mut secret = vec![0u8; 64];
+    if persistent {
+        match fs::read(SESSION_SECRET_FILE) {
+            Ok(stored) if stored.len() >= 32 => return Ok(stored),
+            Ok(_) => warn!("{} is too short, generating a new one", SESSION_SECRET_FILE),
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(SessionError::Read(e)),
+        }
+    }
+    rand::thread_rng().fill_bytes(&mut secret);
+    if persistent {
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        // Only the owner can read the secret
+        #[cfg(target_family = "unix")]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options
+            .open(SESSION_SECRET_FILE)
+            .map_err(SessionError::Write)?;
+        file.write_all(&secret).map_err(SessionError::Write)?;
+    }
+    Ok(secret)
+}
+
+/// Unlike other variables, the token is case sensitive, so it's not loaded through `Environment`.
+fn load_metrics_token() -> Option<String> {
+    match std::env::var("METRICS_TOKEN") {
+        Ok(token) if !token.is_empty() => Some(token),
+        _ => None,
+    }
 }
 
 fn folder_content(dir: &Path) -> Result<HashSet<OsString>, std::io::Error> {
@@ -212,7 +678,7 @@ fn recreate_symlinks(current_path: &Path) -> Result<(), std::io::Error> {
         let entry = path?;
         let file_name = entry.file_name();
         let file_name = file_name.to_string_lossy();
-        if file_name == "world" {
+        if DATA_ONLY.contains(&&*file_name) {
             continue;
         }
         let file_type = entry.file_type()?;
@@ -314,6 +780,55 @@ fn backup_files(
         .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))
 }
 
+/// Waits for the server to exit, and lets the proxy know about it right away.
+async fn watch_server(
+    mut server: Child,
+    logs: LogBuffer,
+    exited: oneshot::Sender<ExitStatus>,
+) -> Result<Output, std::io::Error> {
+    if let Some(stdout) = server.stdout.take() {
+        tokio::spawn(logs.capture(stdout));
+    }
+    let output = server.wait_with_output().await?;
+    let _ = exited.send(output.status);
+    Ok(output)
+}
+
+/// Returns the exit status of the server, if it exited in time.
+async fn wait_for_server(
+    current_path: &Path,
+    mut server_task: JoinHandle<Result<Output, std::io::Error>>,
+    idle_timeout: Duration,
+) -> Option<ExitStatus> {
+    let deadline = Instant::now() + idle_timeout;
+    match timeout_at(deadline, &mut server_task).await {
+        Ok(Ok(Ok(r))) => {
+            save_output(current_path, "mc_error.log", r.status, r.stderr);
+            Some(r.status)
+        }
+        Ok(Ok(Err(e))) => {
+            error!(
+                "Error while waiting for the server to shutdown: {}. Proceeding anyway",
+                &e
+            );
+            None
+        }
+        Ok(Err(e)) => {
+            error!(
+                "Error while waiting for the server to shutdown: {}. Proceeding anyway",
+                &e
+            );
+            None
+        }
+        Err(_) => {
+            // Dropping the process kills it
+            error!("Couldn't shutdown MC server in time. Killing it");
+            server_task.abort();
+            None
+        }
+    }
+}
+
 fn save_output(current_path: &Path, log_path: &str, exist_status: ExitStatus, output: Vec<u8>) {
     if output.is_empty() {
         return;
@@ -348,6 +863,34 @@ fn save_output(current_path: &Path, log_path: &str, exist_status: ExitStatus, ou
     };
 }
 
+async fn restore_world(
+    progress: &RestoreProgress,
+    backup_directory: &Path,
+    world_path: &Path,
+    name: String,
+) {
+    progress.set(RestoreStage::Unpacking { name: name.clone() });
+    let archive = backup_directory.join(&name);
+    let world = world_path.to_path_buf();
+    let result = match spawn_blocking(move || restore_archive(&archive, &world)).await {
+        Ok(r) => r,
+        Err(e) => Err(BackupError::from(e)),
+    };
+    match result {
+        Ok(_r) => progress.set(RestoreStage::Starting { name }),
+        Err(e) => {
+            error!(
+                "Couldn't restore <{}>, starting the current world: {}",
+                &name, &e
+            );
+            progress.set(RestoreStage::Failed {
+                name,
+                error: e.to_string(),
+            });
+        }
+    }
+}
+
 fn start_server() -> Result<Child, std::io::Error> {
     info!("Starting Minecraft server...");
     Command::new("java")
@@ -377,6 +920,9 @@ fn start_server() -> Result<Child, std::io::Error> {
         .arg("-jar")
         .arg("fabric-server-launcher.jar")
         .arg("nogui")
+        .stdout(Stdio::piped())
         .stderr(Stdio::piped())
+        // Make sure the server doesn't outlive the supervisor, e.g. if it doesn't stop in time
+        .kill_on_drop(true)
         .spawn()
 }
@@ -1,15 +1,24 @@
-use std::sync::Arc;
+use std::{
+    fmt,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
 
 use axum::{
     async_trait,
-    extract::{FromRequest, RequestParts},
-    headers::Cookie,
+    extract::{ConnectInfo, FromRequest, RequestParts},
+    headers::{authorization::Bearer, Authorization, Cookie},
     Extension, TypedHeader,
 };
 use jsonwebtoken::{decode, DecodingKey, EncodingKey, Validation};
 use serde::{Deserialize, Serialize};
 
-use crate::{error::LoginError, Context};
+use crate::{
+    api_token::required_scope,
+    error::LoginError,
+    password::{constant_time_eq, verify_dummy, verify_hash},
+    Context,
+};
 
 #[derive(Deserialize)]
 pub struct LoginData {
@@ -17,6 +26,25 @@ pub struct LoginData {
     pub password: String,
 }
 
+#[derive(Deserialize)]
+pub struct TotpCode {
+    /// Either a code from the authenticator app, or a recovery code.
+    pub code: String,
+}
+
+#[derive(Deserialize)]
+pub struct NewApiToken {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    /// The token never expires, if it's not set.
+    pub expires_in_days: Option<u32>,
+}
+
+#[derive(Deserialize)]
+pub struct ApiTokenId {
+    pub id: String,
+}
+
 pub struct Keys {
     pub encoding: EncodingKey,
     pub decoding: DecodingKey,
@@ -31,12 +59,144 @@ impl Keys {
     }
 }
 
+/// Each role can do everything the previous ones can.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Can see the status of the server, the logs and the lists of players.
+    Viewer,
+    /// Can also kick, ban and whitelist players.
+    Moderator,
+    /// Can also op players, use the console and manage backups.
+    Admin,
+}
+
+/// What an API token is allowed to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Status of the server, the lists of players and backups.
+    Read,
+    Kick,
+    Ban,
+    Whitelist,
+    Logs,
+    Op,
+    Backup,
+}
+
+impl Scope {
+    /// The least role, that can use the scope.
+    pub fn role(&self) -> Role {
+        match self {
+            Scope::Read => Role::Viewer,
+            Scope::Kick | Scope::Ban | Scope::Whitelist | Scope::Logs => Role::Moderator,
+            Scope::Op | Scope::Backup => Role::Admin,
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Scope::Read => "read",
+            Scope::Kick => "kick",
+            Scope::Ban => "ban",
+            Scope::Whitelist => "whitelist",
+            Scope::Logs => "logs",
+            Scope::Op => "op",
+            Scope::Backup => "backup",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Clone, Deserialize)]
+pub struct User {
+    pub username: String,
+    /// Plain text password, used only if there is no hash.
+    pub password: Option<String>,
+    /// Argon2id hash in PHC string format.
+    pub password_hash: Option<String>,
+    pub role: Role,
+}
+
+impl User {
+    /// This function is slow on purpose, so it must not be called on the async runtime.
+    pub fn verify_password(&self, password: &str, dummy_hash: &str) -> bool {
+        match (&self.password_hash, &self.password) {
+            (Some(hash), _) => verify_hash(hash, password),
+            (None, Some(expected)) => {
+                // Takes as long as a hashed password, so the timing doesn't reveal the username
+                verify_dummy(dummy_hash, password);
+                constant_time_eq(expected.as_bytes(), password.as_bytes())
+            }
+            (None, None) => verify_dummy(dummy_hash, password),
+        }
+    }
+}
+
 #[derive(Deserialize, Serialize)]
 pub struct Claims {
     pub loged_in: bool,
     pub exp: i64,
+    pub sub: String,
+    pub role: Role,
+    /// Identifies the session, stays the same when the token is refreshed.
+    pub jti: String,
+    /// Name of the API token, if the request was authenticated with one instead of the session cookie.
+    #[serde(skip)]
+    pub api_token: Option<String>,
+    /// Address of the client that made the request.
+    #[serde(skip)]
+    pub ip: Option<IpAddr>,
+}
+
+impl Claims {
+    /// Who made the request, for the logs.
+    pub fn actor(&self) -> String {
+        match &self.api_token {
+            Some(name) => format!("{} (token {})", &self.sub, name),
+            None => self.sub.clone(),
+        }
+    }
+}
+
+/// Issued after the password is checked, while the user still has to enter the two-factor code.
+/// It's not accepted in place of `Claims`, because it doesn't have `loged_in`.
+#[derive(Deserialize, Serialize)]
+pub struct PendingLogin {
+    pub pending: bool,
+    pub exp: i64,
+    pub sub: String,
+    pub role: Role,
+}
+
+/// Kept in a cookie between the redirect to the identity provider and the callback.
+/// It's not accepted in place of `Claims`, because it doesn't have `loged_in`.
+#[derive(Deserialize, Serialize)]
+pub struct OidcLogin {
+    pub state: String,
+    pub nonce: String,
+    /// PKCE code verifier, only its hash is sent with the authorization request.
+    pub verifier: String,
+    pub exp: i64,
+}
+
+#[derive(Deserialize)]
+pub struct OidcCallback {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+    pub error_description: Option<String>,
 }
 
+/// Claims of a user with at least the moderator role.
+pub struct Moderator(pub Claims);
+
+/// Claims of a user with the admin role.
+pub struct Admin(pub Claims);
+
 #[async_trait]
 impl<B> FromRequest<B> for Claims
 where
@@ -49,14 +209,95 @@ where
             .await
             .map_err(|_| LoginError::ContextNotLoaded)?;
 
+        let ip = req
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(address)| address.ip());
+
+        let bearer = Option::<TypedHeader<Authorization<Bearer>>>::from_request(req)
+            .await
+            .map_err(|_| LoginError::InvalidToken)?;
+        if let Some(TypedHeader(Authorization(bearer))) = bearer {
+            let claims = token_claims(&ctx, req, bearer.token())?;
+            return Ok(Self { ip, ..claims });
+        }
+
         let TypedHeader(cookies) = TypedHeader::<Cookie>::from_request(req)
             .await
             .map_err(|_| LoginError::InvalidToken)?;
 
         let token = cookies.get("jwt").ok_or(LoginError::InvalidToken)?;
 
-        let data = decode(token, &ctx.keys.decoding, &Validation::default())
+        let data = decode::<Claims>(token, &ctx.keys.decoding, &Validation::default())
             .map_err(|_| LoginError::InvalidToken)?;
-        Ok(data.claims)
+        if ctx.revoked_sessions.is_revoked(&data.claims.jti) {
+            return Err(LoginError::InvalidToken);
+        }
+        Ok(Self { ip, ..data.claims })
+    }
+}
+
+/// API tokens act on behalf of their owner, but only within their scopes and the owner's current role.
+fn token_claims<B>(
+    ctx: &Context,
+    req: &RequestParts<B>,
+    secret: &str,
+) -> Result<Claims, LoginError> {
+    let token = ctx
+        .api_tokens
+        .authenticate(secret)
+        .ok_or(LoginError::InvalidToken)?;
+    let scope = required_scope(req.method(), req.uri().path()).ok_or(LoginError::Forbidden)?;
+    let owner = ctx
+        .users
+        .iter()
+        .find(|user| user.username == token.owner)
+        .ok_or(LoginError::InvalidToken)?;
+    if !token.scopes.contains(&scope) || owner.role < scope.role() {
+        return Err(LoginError::Forbidden);
+    }
+    Ok(Claims {
+        loged_in: true,
+        exp: token.expires.unwrap_or(i64::MAX),
+        sub: token.owner,
+        role: owner.role,
+        jti: token.id,
+        api_token: Some(token.name),
+        ip: None,
+    })
+}
+
+async fn claims_with_role<B: Send>(
+    req: &mut RequestParts<B>,
+    role: Role,
+) -> Result<Claims, LoginError> {
+    let claims = Claims::from_request(req).await?;
+    if claims.role < role {
+        return Err(LoginError::Forbidden);
+    }
+    Ok(claims)
+}
+
+#[async_trait]
+impl<B> FromRequest<B> for Moderator
+where
+    B: Send,
+{
+    type Rejection = LoginError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        Ok(Self(claims_with_role(req, Role::Moderator).await?))
+    }
+}
+
+#[async_trait]
+impl<B> FromRequest<B> for Admin
+where
+    B: Send,
+{
+    type Rejection = LoginError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        Ok(Self(claims_with_role(req, Role::Admin).await?))
     }
 }
@@ -1,9 +1,13 @@
-use serde::Deserialize;
+use std::net::IpAddr;
+
+use serde::{Deserialize, Serialize};
 
 #[derive(Deserialize)]
 pub struct Ban {
     pub nickname: String,
     pub reason: Option<String>,
+    /// E.g. `30m`, `12h` or `1d12h`, the ban is permanent without it.
+    pub duration: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -36,3 +40,63 @@ pub struct OpAdd {
 pub struct DeOp {
     pub nickname: String,
 }
+
+#[derive(Deserialize)]
+pub struct Logs {
+    pub lines: Option<usize>,
+}
+
+#[derive(Deserialize)]
+pub struct AuditQuery {
+    /// Starts from 1.
+    pub page: Option<usize>,
+    pub per_page: Option<usize>,
+    pub user: Option<String>,
+    pub action: Option<String>,
+    pub ip: Option<IpAddr>,
+    /// Unix timestamps, `until` is exclusive.
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct Restore {
+    pub name: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct WhitelistEntry {
+    pub uuid: String,
+    pub name: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct OpEntry {
+    pub uuid: String,
+    pub name: String,
+    pub level: u8,
+    #[serde(rename = "bypassesPlayerLimit", default)]
+    pub bypasses_player_limit: bool,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct BannedPlayer {
+    pub uuid: String,
+    pub name: String,
+    pub created: String,
+    pub source: String,
+    pub expires: String,
+    pub reason: String,
+    /// Seconds until a temporary ban runs out, the server itself doesn't know about it.
+    #[serde(skip_deserializing)]
+    pub remaining: Option<i64>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct BannedIp {
+    pub ip: String,
+    pub created: String,
+    pub source: String,
+    pub expires: String,
+    pub reason: String,
+}
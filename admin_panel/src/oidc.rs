@@ -0,0 +1,530 @@
+use std::{
+    str::FromStr,
+    sync::{Mutex, PoisonError},
+    time::Duration,
+};
+
+use jsonwebtoken::{decode, decode_header, jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use log::info;
+use ring::digest;
+use serde::Deserialize;
+use serde_json::Value;
+use url::Url;
+
+use crate::{
+    error::{OidcError, RoleMappingParserError},
+    models::auth::{OidcLogin, Role},
+    password::random_string,
+};
+
+#[derive(Clone)]
+pub struct OidcSettings {
+    /// Issuer URL, the discovery document is expected at `/.well-known/openid-configuration` under it.
+    pub issuer: String,
+    pub client_id: String,
+    /// Not needed for public clients, PKCE is used either way.
+    pub client_secret: Option<String>,
+    /// Callback of the admin panel, e.g. `https://mc.example.com/login/oidc/callback`.
+    pub redirect_url: String,
+    pub scopes: String,
+    /// Claims can be nested, e.g. `realm_access.roles`.
+    pub username_claim: String,
+    pub role_claim: String,
+    pub roles: RoleMapping,
+}
+
+/// Comma separated `value=role` pairs, e.g. `minecraft-admins=admin, minecraft-mods=moderator`.
+#[derive(Clone, Default)]
+pub struct RoleMapping(Vec<(String, Role)>);
+
+impl RoleMapping {
+    /// The highest role of the values of the claim, which can be either a string or a list of strings.
+    pub fn role(&self, claim: Option<&Value>) -> Option<Role> {
+        let values: Vec<&str> = match claim {
+            Some(Value::String(value)) => vec![value.as_str()],
+            Some(Value::Array(values)) => values.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        self.0
+            .iter()
+            .filter(|(value, _role)| values.contains(&value.as_str()))
+            .map(|(_value, role)| *role)
+            .max()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl FromStr for RoleMapping {
+    type Err = RoleMappingParserError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(str::trim)
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (value, role) = pair
+                    .rsplit_once('=')
+                    .ok_or_else(|| RoleMappingParserError::Parse(pair.to_string()))?;
+                let role = match role.trim().to_ascii_lowercase().as_str() {
+                    "viewer" => Role::Viewer,
+                    "moderator" => Role::Moderator,
+                    "admin" => Role::Admin,
+                    _ => return Err(RoleMappingParserError::Parse(pair.to_string())),
+                };
+                Ok((value.trim().to_string(), role))
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(Self)
+    }
+}
+
+/// User as described by the identity provider.
+pub struct Identity {
+    pub username: String,
+    pub role: Role,
+}
+
+#[derive(Clone, Deserialize)]
+struct Provider {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// Relying party of the authorization code flow with PKCE. The discovery document and the keys
+/// are fetched when they're needed for the first time, so that the panel starts even if the
+/// identity provider is down. It's blocking, so it must not be called on the async runtime.
+pub struct OidcClient {
+    settings: OidcSettings,
+    agent: ureq::Agent,
+    provider: Mutex<Option<Provider>>,
+    keys: Mutex<JwkSet>,
+}
+
+impl OidcClient {
+    pub fn new(settings: OidcSettings) -> Self {
+        let agent = ureq::AgentBuilder::new()
+            .timeout(Duration::from_secs(10))
+            .build();
+        Self {
+            settings,
+            agent,
+            provider: Mutex::new(None),
+            keys: Mutex::new(JwkSet { keys: Vec::new() }),
+        }
+    }
+
+    /// Starts a new login, with fresh `state`, `nonce` and code verifier.
+    pub fn new_login(exp: i64) -> OidcLogin {
+        OidcLogin {
+            state: random_string(32),
+            nonce: random_string(32),
+            verifier: random_string(64),
+            exp,
+        }
+    }
+
+    /// URL of the login page of the identity provider.
+    pub fn authorization_url(&self, login: &OidcLogin) -> Result<String, OidcError> {
+        let provider = self.provider()?;
+        let mut url = Url::parse(&provider.authorization_endpoint)
+            .map_err(|e| OidcError::InvalidUrl(e.to_string()))?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.settings.client_id)
+            .append_pair("redirect_uri", &self.settings.redirect_url)
+            .append_pair("scope", &self.settings.scopes)
+            .append_pair("state", &login.state)
+            .append_pair("nonce", &login.nonce)
+            .append_pair("code_challenge", &code_challenge(&login.verifier))
+            .append_pair("code_challenge_method", "S256");
+        Ok(url.into())
+    }
+
+    /// Exchanges the code for an ID token, and maps its claims to the user.
+    pub fn finish(&self, code: &str, login: &OidcLogin) -> Result<Identity, OidcError> {
+        let provider = self.provider()?;
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", self.settings.redirect_url.as_str()),
+            ("client_id", self.settings.client_id.as_str()),
+            ("code_verifier", login.verifier.as_str()),
+        ];
+        if let Some(secret) = &self.settings.client_secret {
+            form.push(("client_secret", secret.as_str()));
+        }
+        let response: TokenResponse = self
+            .agent
+            .post(&provider.token_endpoint)
+            .set("Accept", "application/json")
+            .send_form(&form)?
+            .into_json()
+            .map_err(OidcError::Read)?;
+        let claims = self.verify(&response.id_token, &provider, &login.nonce)?;
+
+        let username = claim(&claims, &self.settings.username_claim)
+            .or_else(|| claims.get("sub"))
+            .and_then(Value::as_str)
+            .ok_or_else(|| OidcError::InvalidIdToken("there is no username".to_string()))?;
+        let role = self
+            .settings
+            .roles
+            .role(claim(&claims, &self.settings.role_claim))
+            .ok_or_else(|| OidcError::NoRole(username.to_string()))?;
+        Ok(Identity {
+            username: username.to_string(),
+            role,
+        })
+    }
+
+    fn provider(&self) -> Result<Provider, OidcError> {
+        let mut provider = self.provider.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(provider) = provider.as_ref() {
+            return Ok(provider.clone());
+        }
+        let issuer = self.settings.issuer.trim_end_matches('/');
+        let url = format!("{}/.well-known/openid-configuration", issuer);
+        let discovered: Provider = self
+            .agent
+            .get(&url)
+            .call()?
+            .into_json()
+            .map_err(OidcError::Read)?;
+        if discovered.issuer.trim_end_matches('/') != issuer {
+            return Err(OidcError::IssuerMismatch(discovered.issuer));
+        }
+        info!("Using identity provider {}", &discovered.issuer);
+        *provider = Some(discovered.clone());
+        Ok(discovered)
+    }
+
+    /// Keys are fetched again when the token is signed with an unknown key, e.g. after rotation.
+    fn verify(&self, token: &str, provider: &Provider, nonce: &str) -> Result<Value, OidcError> {
+        let kid = decode_header(token).ok().and_then(|header| header.kid);
+        let mut keys = self.keys.lock().unwrap_or_else(PoisonError::into_inner);
+        let known = match &kid {
+            Some(kid) => keys.find(kid).is_some(),
+            None => !keys.keys.is_empty(),
+        };
+        if !known {
+            *keys = self
+                .agent
+                .get(&provider.jwks_uri)
+                .call()?
+                .into_json()
+                .map_err(OidcError::Read)?;
+        }
+        verify_id_token(
+            token,
+            &keys,
+            &provider.issuer,
+            &self.settings.client_id,
+            nonce,
+        )
+    }
+}
+
+/// Checks the signature, the issuer, the audience, the expiration time and the nonce of the ID token.
+pub fn verify_id_token(
+    token: &str,
+    keys: &JwkSet,
+    issuer: &str,
+    client_id: &str,
+    nonce: &str,
+) -> Result<Value, OidcError> {
+    let invalid = |e: jsonwebtoken::errors::Error| OidcError::InvalidIdToken(e.to_string());
+    let header = decode_header(token).map_err(invalid)?;
+    // Otherwise the public key could be used as an HMAC secret
+    if matches!(
+        header.alg,
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+    ) {
+        return Err(OidcError::InvalidIdToken(format!(
+            "{:?} is not allowed",
+            header.alg
+        )));
+    }
+    let jwk = match &header.kid {
+        Some(kid) => keys.find(kid),
+        None if keys.keys.len() == 1 => keys.keys.first(),
+        None => None,
+    }
+    .ok_or_else(|| OidcError::InvalidIdToken("unknown signing key".to_string()))?;
+    let key = DecodingKey::from_jwk(jwk).map_err(invalid)?;
+
+    let mut validation = Validation::new(header.alg);
+    validation.set_issuer(&[issuer]);
+    validation.set_audience(&[client_id]);
+    validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+    let claims = decode::<Value>(token, &key, &validation)
+        .map_err(invalid)?
+        .claims;
+    if claims.get("nonce").and_then(Value::as_str) != Some(nonce) {
+        return Err(OidcError::InvalidIdToken("nonce doesn't match".to_string()));
+    }
+    Ok(claims)
+}
+
+/// Finds a claim by a dot separated path.
+fn claim<'a>(claims: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.')
+        .try_fold(claims, |value, key| value.get(key))
+}
+
+fn code_challenge(verifier: &str) -> String {
+    let hash = digest::digest(&digest::SHA256, verifier.as_bytes());
+    base64::encode_config(hash.as_ref(), base64::URL_SAFE_NO_PAD)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        net::TcpListener,
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{
+        http::StatusCode,
+        routing::{get, post},
+        Extension, Form, Json, Router,
+    };
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use ring::{
+        rand::SystemRandom,
+        signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING},
+    };
+    use serde_json::json;
+
+    use super::*;
+
+    /// Identity provider, that signs in everyone who asks, as long as PKCE checks out.
+    struct MockProvider {
+        issuer: String,
+        key: Vec<u8>,
+        jwk: Value,
+        /// Code challenges and nonces of the started logins.
+        logins: Mutex<HashMap<String, String>>,
+    }
+
+    impl MockProvider {
+        fn new(issuer: String) -> Self {
+            let key = EcdsaKeyPair::generate_pkcs8(
+                &ECDSA_P256_SHA256_FIXED_SIGNING,
+                &SystemRandom::new(),
+            )
+            .unwrap()
+            .as_ref()
+            .to_vec();
+            let pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &key).unwrap();
+            let public = pair.public_key().as_ref();
+            let encode = |data: &[u8]| base64::encode_config(data, base64::URL_SAFE_NO_PAD);
+            let jwk = json!({
+                "kty": "EC",
+                "crv": "P-256",
+                "kid": "mock",
+                "alg": "ES256",
+                "use": "sig",
+                "x": encode(&public[1..33]),
+                "y": encode(&public[33..65]),
+            });
+            Self {
+                issuer,
+                key,
+                jwk,
+                logins: Mutex::new(HashMap::new()),
+            }
+        }
+
+        fn id_token(&self, claims: Value) -> String {
+            let mut header = Header::new(Algorithm::ES256);
+            header.kid = Some("mock".to_string());
+            encode(&header, &claims, &EncodingKey::from_ec_der(&self.key)).unwrap()
+        }
+
+        fn keys(&self) -> JwkSet {
+            serde_json::from_value(json!({ "keys": [self.jwk.clone()] })).unwrap()
+        }
+    }
+
+    async fn discovery(Extension(mock): Extension<Arc<MockProvider>>) -> Json<Value> {
+        Json(json!({
+            "issuer": &mock.issuer,
+            "authorization_endpoint": format!("{}/authorize", &mock.issuer),
+            "token_endpoint": format!("{}/token", &mock.issuer),
+            "jwks_uri": format!("{}/jwks", &mock.issuer),
+        }))
+    }
+
+    async fn jwks(Extension(mock): Extension<Arc<MockProvider>>) -> Json<Value> {
+        Json(json!({ "keys": [mock.jwk.clone()] }))
+    }
+
+    async fn token(
+        Form(form): Form<HashMap<String, String>>,
+        Extension(mock): Extension<Arc<MockProvider>>,
+    ) -> Result<Json<Value>, StatusCode> {
+        let challenge = code_challenge(form.get("code_verifier").ok_or(StatusCode::BAD_REQUEST)?);
+        let nonce = mock
+            .logins
+            .lock()
+            .unwrap()
+            .remove(&challenge)
+            .ok_or(StatusCode::BAD_REQUEST)?;
+        let id_token = mock.id_token(json!({
+            "iss": &mock.issuer,
+            "aud": form.get("client_id"),
+            "sub": "1234",
+            "exp": time::OffsetDateTime::now_utc().unix_timestamp() + 300,
+            "nonce": nonce,
+            "preferred_username": form.get("code"),
+            "realm_access": { "roles": ["players", "minecraft-mods"] },
+        }));
+        Ok(Json(
+            json!({ "id_token": id_token, "access_token": "unused", "token_type": "Bearer" }),
+        ))
+    }
+
+    fn settings(issuer: &str) -> OidcSettings {
+        OidcSettings {
+            issuer: issuer.to_string(),
+            client_id: "admin-panel".to_string(),
+            client_secret: None,
+            redirect_url: "https://mc.example.com/login/oidc/callback".to_string(),
+            scopes: "openid profile".to_string(),
+            username_claim: "preferred_username".to_string(),
+            role_claim: "realm_access.roles".to_string(),
+            roles: "minecraft-admins=admin, minecraft-mods=moderator"
+                .parse()
+                .unwrap(),
+        }
+    }
+
+    #[test]
+    fn parses_role_mapping() {
+        let roles: RoleMapping = "Admins=admin, mods=Moderator,".parse().unwrap();
+        assert_eq!(roles.role(Some(&json!("Admins"))), Some(Role::Admin));
+        assert_eq!(
+            roles.role(Some(&json!(["mods", "Admins"]))),
+            Some(Role::Admin)
+        );
+        assert_eq!(roles.role(Some(&json!(["admins"]))), None);
+        assert_eq!(roles.role(None), None);
+        assert!("mods=owner".parse::<RoleMapping>().is_err());
+        assert!("mods".parse::<RoleMapping>().is_err());
+    }
+
+    #[test]
+    fn rejects_foreign_tokens() {
+        let mock = MockProvider::new("https://idp.example.com".to_string());
+        let now = time::OffsetDateTime::now_utc().unix_timestamp();
+        let claims = json!({
+            "iss": "https://idp.example.com",
+            "aud": "admin-panel",
+            "sub": "1234",
+            "exp": now + 300,
+            "nonce": "nonce",
+        });
+        let token = mock.id_token(claims.clone());
+        let keys = mock.keys();
+        let verify = |token: &str, issuer: &str, client_id: &str, nonce: &str| {
+            verify_id_token(token, &keys, issuer, client_id, nonce).is_ok()
+        };
+        assert!(verify(
+            &token,
+            "https://idp.example.com",
+            "admin-panel",
+            "nonce"
+        ));
+        assert!(!verify(
+            &token,
+            "https://idp.example.com",
+            "admin-panel",
+            "other"
+        ));
+        assert!(!verify(
+            &token,
+            "https://idp.example.com",
+            "other-client",
+            "nonce"
+        ));
+        assert!(!verify(
+            &token,
+            "https://evil.example.com",
+            "admin-panel",
+            "nonce"
+        ));
+
+        let mut expired = claims;
+        expired["exp"] = json!(now - 3600);
+        assert!(!verify(
+            &mock.id_token(expired),
+            "https://idp.example.com",
+            "admin-panel",
+            "nonce"
+        ));
+
+        let other = MockProvider::new("https://idp.example.com".to_string());
+        let forged = other.id_token(json!({
+            "iss": "https://idp.example.com",
+            "aud": "admin-panel",
+            "sub": "1234",
+            "exp": now + 300,
+            "nonce": "nonce",
+        }));
+        assert!(!verify(
+            &forged,
+            "https://idp.example.com",
+            "admin-panel",
+            "nonce"
+        ));
+    }
+
+    #[tokio::test]
+    async fn logs_in_with_mock_provider() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let issuer = format!("http://{}", listener.local_addr().unwrap());
+        let mock = Arc::new(MockProvider::new(issuer.clone()));
+        let router = Router::new()
+            .route("/.well-known/openid-configuration", get(discovery))
+            .route("/jwks", get(jwks))
+            .route("/token", post(token))
+            .layer(Extension(mock.clone()));
+        tokio::spawn(axum_server::from_tcp(listener).serve(router.into_make_service()));
+
+        let client = OidcClient::new(settings(&issuer));
+        let identity = tokio::task::spawn_blocking(move || {
+            let login = OidcClient::new_login(0);
+            let url = Url::parse(&client.authorization_url(&login).unwrap()).unwrap();
+            assert!(url.as_str().starts_with(&format!("{}/authorize?", &issuer)));
+            let query: HashMap<_, _> = url.query_pairs().into_owned().collect();
+            assert_eq!(query["state"], login.state);
+            assert_eq!(query["code_challenge_method"], "S256");
+            mock.logins
+                .lock()
+                .unwrap()
+                .insert(query["code_challenge"].clone(), query["nonce"].clone());
+
+            let tampered = OidcLogin {
+                verifier: random_string(64),
+                ..OidcClient::new_login(0)
+            };
+            assert!(client.finish("alice", &tampered).is_err());
+            client.finish("alice", &login).unwrap()
+        })
+        .await
+        .unwrap();
+        assert_eq!(identity.username, "alice");
+        assert_eq!(identity.role, Role::Moderator);
+    }
+}
@@ -0,0 +1,155 @@
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Algorithm, Argon2, Params, Version,
+};
+use rand::{distributions::Alphanumeric, Rng};
+use ring::digest;
+
+pub fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default().hash_password(password.as_bytes(), &salt)?;
+    Ok(hash.to_string())
+}
+
+/// Only Argon2id hashes in PHC string format are accepted.
+pub fn is_valid_hash(hash: &str) -> bool {
+    match PasswordHash::new(hash) {
+        Ok(hash) => hash.algorithm == Algorithm::Argon2id.ident() && hash.hash.is_some(),
+        Err(_) => false,
+    }
+}
+
+/// This function is slow on purpose, so it must not be called on the async runtime.
+pub fn verify_hash(hash: &str, password: &str) -> bool {
+    match PasswordHash::new(hash) {
+        Ok(hash) => Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Hash of a random password, which is checked when the user doesn't exist, so that the response
+/// time doesn't reveal whether the username is correct. It takes the most expensive parameters of
+/// the users' hashes, or the defaults, which `hash-password` uses as well.
+pub fn dummy_hash<'a>(
+    hashes: impl IntoIterator<Item = &'a str>,
+) -> Result<String, argon2::password_hash::Error> {
+    let params = hashes
+        .into_iter()
+        .filter_map(|hash| PasswordHash::new(hash).ok())
+        .filter_map(|hash| Params::try_from(&hash).ok())
+        .max_by_key(|params| {
+            params.m_cost() as u64 * params.t_cost() as u64 * params.p_cost() as u64
+        })
+        .unwrap_or_default();
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::new(Algorithm::Argon2id, Version::default(), params)
+        .hash_password(random_string(32).as_bytes(), &salt)?;
+    Ok(hash.to_string())
+}
+
+/// Takes as long as checking a real password, but never succeeds.
+pub fn verify_dummy(dummy_hash: &str, password: &str) -> bool {
+    verify_hash(dummy_hash, password);
+    false
+}
+
+/// Alphanumeric string for session ids, tokens and other secrets.
+pub fn random_string(length: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(length)
+        .map(char::from)
+        .collect()
+}
+
+/// Hex encoded SHA-256, for secrets like tokens and recovery codes. They are long and random,
+/// so unlike passwords they don't need a slow hash.
+pub fn sha256_hex(secret: &str) -> String {
+    let hash = digest::digest(&digest::SHA256, secret.as_bytes());
+    hash.as_ref().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Compares the secrets without leaking the position of the first mismatch through timing.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHEAP_HASH: &str =
+        "$argon2id$v=19$m=4096,t=3,p=1$QD1hCNFKRjXAnmzf5Kjy3Q$DgaiSdScql3DiiV3nm83qVg93Q+BtysFFg8hAoFQsSI";
+
+    fn params(hash: &str) -> (u32, u32, u32) {
+        let hash = PasswordHash::new(hash).unwrap();
+        let params = Params::try_from(&hash).unwrap();
+        (params.m_cost(), params.t_cost(), params.p_cost())
+    }
+
+    #[test]
+    fn dummy_hash_matches_users() {
+        let default = dummy_hash([]).unwrap();
+        assert!(is_valid_hash(&default));
+        assert_eq!(params(&default), params(&hash_password("").unwrap()));
+        assert!(!verify_dummy(&default, ""));
+
+        let cheap = dummy_hash(["not a hash", CHEAP_HASH]).unwrap();
+        assert!(is_valid_hash(&cheap));
+        assert_eq!(params(&cheap), (4096, 3, 1));
+        assert_ne!(cheap, CHEAP_HASH);
+
+        let expensive = dummy_hash([CHEAP_HASH, &default]).unwrap();
+        assert_eq!(params(&expensive), params(&default));
+    }
+
+    #[test]
+    fn verifies_hashes() {
+        let hash = hash_password("correct horse").unwrap();
+        assert!(is_valid_hash(&hash));
+        assert!(verify_hash(&hash, "correct horse"));
+        assert!(!verify_hash(&hash, "correct horse "));
+    }
+
+    #[test]
+    fn rejects_malformed_hashes() {
+        for hash in [
+            "",
+            "correct horse",
+            "$argon2id$v=19$m=4096,t=3,p=1",
+            "$argon2id$v=19$m=4096,t=3,p=1$QD1hCNFKRjXAnmzf5Kjy3Q$not base64!",
+            "$2b$12$R9h/cIPz0gi.URNNX3kh2OPST9/PgBkqquzi.Ss7KIUgO2t0jWMUW",
+        ] {
+            assert!(!is_valid_hash(hash), "{}", hash);
+            assert!(!verify_hash(hash, "correct horse"), "{}", hash);
+        }
+        // Other Argon2 variants aren't accepted in the settings
+        let argon2i = CHEAP_HASH.replacen("argon2id", "argon2i", 1);
+        assert!(!is_valid_hash(&argon2i));
+    }
+
+    #[test]
+    fn compares_in_constant_time() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret "));
+        assert!(constant_time_eq(b"", b""));
+    }
+
+    #[test]
+    fn hashes_secrets() {
+        assert_eq!(
+            sha256_hex("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        let secret = random_string(40);
+        assert_eq!(secret.len(), 40);
+        assert!(secret.chars().all(|c| c.is_ascii_alphanumeric()));
+        assert_ne!(secret, random_string(40));
+    }
+}
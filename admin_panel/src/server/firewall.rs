@@ -0,0 +1,65 @@
+use log::info;
+use tokio::process::Command;
+
+use crate::error::FirewallError;
+
+pub const QUERY_PORT: u16 = 25566;
+pub const RCON_PORT: u16 = 25567;
+
+/// Minecraft binds query and RCON to the same address as the game port,
+/// so packets to these ports that don't come over loopback are dropped instead.
+/// Requires the `NET_ADMIN` capability.
+pub async fn restrict_to_loopback() -> Result<(), FirewallError> {
+    let mut tools = vec!["iptables"];
+    if ipv6_enabled() {
+        tools.push("ip6tables");
+    }
+    for tool in tools {
+        for (protocol, port) in [("udp", QUERY_PORT), ("tcp", RCON_PORT)] {
+            let rule = rule(protocol, port);
+            // The rules outlive the admin panel when the container is restarted
+            if run(tool, "-C", &rule).await.is_ok() {
+                continue;
+            }
+            run(tool, "-I", &rule).await?;
+        }
+    }
+    info!(
+        "Ports {} and {} are accessible only over loopback",
+        QUERY_PORT, RCON_PORT
+    );
+    Ok(())
+}
+
+fn rule(protocol: &str, port: u16) -> Vec<String> {
+    let port = port.to_string();
+    [
+        "INPUT", "-p", protocol, "--dport", &port, "!", "-i", "lo", "-j", "DROP",
+    ]
+    .iter()
+    .map(|arg| arg.to_string())
+    .collect()
+}
+
+async fn run(tool: &str, action: &str, rule: &[String]) -> Result<(), FirewallError> {
+    let output = Command::new(tool)
+        .arg("-w")
+        .arg(action)
+        .args(rule)
+        .output()
+        .await
+        .map_err(|e| FirewallError::Run(tool.to_string(), e))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        Err(FirewallError::Rule(tool.to_string(), stderr))
+    }
+}
+
+/// Without IPv6 there is nothing to filter, and `ip6tables` would fail.
+fn ipv6_enabled() -> bool {
+    std::fs::read_to_string("/proc/net/if_inet6")
+        .map(|content| !content.trim().is_empty())
+        .unwrap_or(false)
+}
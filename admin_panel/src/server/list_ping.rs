@@ -0,0 +1,223 @@
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum::async_trait;
+use log::debug;
+use serde_json::Value;
+use thiserror::Error;
+use tokio::{
+    net::TcpStream,
+    time::{timeout, Instant},
+};
+
+use super::{
+    protocol::{read_packet, write_packet, write_string, write_varint, PacketError, PacketReader},
+    status::{ProbeError, ServerSnapshot, StatusProber},
+};
+
+#[derive(Error, Debug)]
+pub enum PingError {
+    #[error("Packet error: {0}")]
+    Packet(#[from] PacketError),
+    #[error("Incorrect packet id. Expected: <{0}>, received: <{1}>")]
+    IncorrectId(i32, i32),
+    #[error("Couldn't parse status: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Status doesn't have <{0}> field")]
+    NoField(String),
+    #[error("Read timeout")]
+    ReadTimeout,
+}
+
+/// Polls the server over Server List Ping protocol (1.7+), the same way the game client does.
+pub struct ListPingPoller {
+    address: SocketAddr,
+    read_timeout: Duration,
+}
+
+impl ListPingPoller {
+    pub fn new() -> Self {
+        Self {
+            address: SocketAddr::from((Ipv4Addr::LOCALHOST, 25565)),
+            read_timeout: Duration::from_secs(1),
+        }
+    }
+
+    async fn ping(&self) -> Result<ServerSnapshot, PingError> {
+        debug!("Connecting to {}...", &self.address);
+        let mut stream = TcpStream::connect(self.address)
+            .await
+            .map_err(PacketError::from)?;
+
+        let mut handshake = Vec::with_capacity(64);
+        // -1 means that the client doesn't know which protocol version the server uses
+        write_varint(&mut handshake, -1);
+        write_string(&mut handshake, &self.address.ip().to_string());
+        handshake.extend_from_slice(&self.address.port().to_be_bytes());
+        write_varint(&mut handshake, 1);
+        write_packet(&mut stream, 0x00, &handshake).await?;
+
+        debug!("Asking for status...");
+        write_packet(&mut stream, 0x00, &[]).await?;
+        let (id, payload) = read_packet(&mut stream).await?;
+        if id != 0x00 {
+            return Err(PingError::IncorrectId(0x00, id));
+        }
+        let status = PacketReader::new(&payload).string()?;
+        debug!("Received status: {}", &status);
+        let mut snapshot = ListPingPoller::read_status(&status)?;
+
+        let sent_at = Instant::now();
+        let payload = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_millis() as i64)
+            .unwrap_or_default();
+        write_packet(&mut stream, 0x01, &payload.to_be_bytes()).await?;
+        let (id, _payload) = read_packet(&mut stream).await?;
+        if id != 0x01 {
+            return Err(PingError::IncorrectId(0x01, id));
+        }
+        snapshot.latency_ms = Some(sent_at.elapsed().as_millis() as u32);
+
+        Ok(snapshot)
+    }
+
+    fn read_status(status: &str) -> Result<ServerSnapshot, PingError> {
+        let status: Value = serde_json::from_str(status)?;
+        let players = match status.get("players") {
+            Some(players) => players,
+            None => return Err(PingError::NoField("players".to_string())),
+        };
+        let online = match players.get("online").and_then(Value::as_u64) {
+            Some(online) => online as u32,
+            None => return Err(PingError::NoField("players.online".to_string())),
+        };
+        let max_players = match players.get("max").and_then(Value::as_u64) {
+            Some(max_players) => max_players as u32,
+            None => return Err(PingError::NoField("players.max".to_string())),
+        };
+        // The server sends only a small sample of players
+        let sample = players
+            .get("sample")
+            .and_then(Value::as_array)
+            .map(|sample| {
+                sample
+                    .iter()
+                    .filter_map(|player| player.get("name").and_then(Value::as_str))
+                    .map(|name| name.to_string())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let version = status
+            .get("version")
+            .and_then(|version| version.get("name"))
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string();
+        let motd = status
+            .get("description")
+            .map(flatten_text)
+            .unwrap_or_default();
+
+        Ok(ServerSnapshot {
+            motd,
+            version,
+            map: String::new(),
+            online,
+            max_players,
+            players: sample,
+            plugins: Vec::new(),
+            latency_ms: None,
+        })
+    }
+}
+
+#[async_trait]
+impl StatusProber for ListPingPoller {
+    async fn current_status(&self) -> Result<ServerSnapshot, ProbeError> {
+        match timeout(self.read_timeout, self.ping()).await {
+            Ok(r) => Ok(r?),
+            Err(_e) => Err(PingError::ReadTimeout.into()),
+        }
+    }
+}
+
+/// Description is either a plain string, or a chat component with nested components.
+fn flatten_text(component: &Value) -> String {
+    match component {
+        Value::String(text) => text.clone(),
+        Value::Object(object) => {
+            let mut text = object
+                .get("text")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string();
+            if let Some(Value::Array(extra)) = object.get("extra") {
+                for component in extra {
+                    text.push_str(&flatten_text(component));
+                }
+            }
+            text
+        }
+        Value::Array(components) => components.iter().map(flatten_text).collect(),
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn reads_status() {
+        let status = json!({
+            "version": { "name": "1.19.2", "protocol": 760 },
+            "players": {
+                "max": 10,
+                "online": 3,
+                "sample": [
+                    { "name": "Steve", "id": "4566e69f-c907-48ee-8d71-d7ba5aa00d20" },
+                    { "name": "Alex", "id": "ec561538-f3fd-461d-aff5-086b22154bce" },
+                    { "id": "00000000-0000-0000-0000-000000000000" }
+                ]
+            },
+            "description": "A Minecraft Server"
+        });
+        let snapshot = ListPingPoller::read_status(&status.to_string()).unwrap();
+        assert_eq!(snapshot.version, "1.19.2");
+        assert_eq!(snapshot.online, 3);
+        assert_eq!(snapshot.max_players, 10);
+        assert_eq!(snapshot.players, vec!["Steve", "Alex"]);
+        assert_eq!(snapshot.motd, "A Minecraft Server");
+    }
+
+    #[test]
+    fn rejects_status_without_players() {
+        let result = ListPingPoller::read_status(r#"{"version": {"name": "1.19.2"}}"#);
+        assert!(matches!(result, Err(PingError::NoField(field)) if field == "players"));
+        let result = ListPingPoller::read_status(r#"{"players": {"max": 10}}"#);
+        assert!(matches!(result, Err(PingError::NoField(field)) if field == "players.online"));
+        let result = ListPingPoller::read_status("{\"players\": ");
+        assert!(matches!(result, Err(PingError::Json(_))));
+    }
+
+    #[test]
+    fn flattens_nested_text() {
+        let description = json!({
+            "text": "Welcome ",
+            "bold": true,
+            "extra": [
+                { "text": "to ", "extra": [{ "text": "the " }, "best "] },
+                "server",
+                { "color": "red" }
+            ]
+        });
+        assert_eq!(flatten_text(&description), "Welcome to the best server");
+        assert_eq!(flatten_text(&json!(["a", { "text": "b" }])), "ab");
+        assert_eq!(flatten_text(&json!(42)), "");
+    }
+}
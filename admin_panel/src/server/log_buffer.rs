@@ -0,0 +1,117 @@
+use std::{
+    collections::VecDeque,
+    io::Write,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use log::error;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, BufReader},
+    sync::broadcast::{self, Receiver, Sender},
+};
+
+/// Keeps the most recent lines of the MC server output, and passes new lines to all subscribers.
+#[derive(Clone)]
+pub struct LogBuffer {
+    lines: Arc<Mutex<VecDeque<String>>>,
+    capacity: usize,
+    tx: Sender<String>,
+}
+
+impl LogBuffer {
+    pub fn new(capacity: usize) -> Self {
+        let (tx, _rx) = broadcast::channel(256);
+        Self {
+            lines: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+            tx,
+        }
+    }
+
+    pub fn push(&self, line: String) {
+        {
+            let mut lines = self.lines.lock().unwrap_or_else(PoisonError::into_inner);
+            if lines.len() == self.capacity {
+                lines.pop_front();
+            }
+            lines.push_back(line.clone());
+        }
+        // Nobody is watching the logs right now
+        let _ = self.tx.send(line);
+    }
+
+    /// Returns up to `count` most recent lines, oldest first.
+    pub fn tail(&self, count: usize) -> Vec<String> {
+        let lines = self.lines.lock().unwrap_or_else(PoisonError::into_inner);
+        let skip = lines.len().saturating_sub(count);
+        lines.iter().skip(skip).cloned().collect()
+    }
+
+    pub fn subscribe(&self) -> Receiver<String> {
+        self.tx.subscribe()
+    }
+
+    /// Reads the output until the stream is closed. Every line is also written into
+    /// the stdout of this process, so that it still shows up in container's logs.
+    pub async fn capture<R: AsyncRead + Unpin>(self, output: R) {
+        let mut reader = BufReader::new(output);
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            match reader.read_until(b'\n', &mut line).await {
+                Ok(0) => return,
+                Ok(_n) => {}
+                Err(e) => {
+                    error!("Couldn't read MC server output: {}", &e);
+                    return;
+                }
+            }
+            let line = String::from_utf8_lossy(&line).trim_end().to_string();
+            let _ = writeln!(std::io::stdout(), "{}", &line);
+            self.push(line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(range: std::ops::RangeInclusive<u32>) -> Vec<String> {
+        range.map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn wraps_around() {
+        let buffer = LogBuffer::new(3);
+        assert!(buffer.tail(10).is_empty());
+        buffer.push("1".to_string());
+        buffer.push("2".to_string());
+        assert_eq!(buffer.tail(10), lines(1..=2));
+
+        for n in 3..=7 {
+            buffer.push(n.to_string());
+        }
+        assert_eq!(buffer.tail(10), lines(5..=7));
+        assert_eq!(buffer.tail(2), lines(6..=7));
+        assert!(buffer.tail(0).is_empty());
+    }
+
+    #[tokio::test]
+    async fn captures_output() {
+        let buffer = LogBuffer::new(10);
+        let mut rx = buffer.subscribe();
+        let output: &[u8] = b"[Server thread/INFO]: Done\r\nbroken \xFF line\nno newline";
+        buffer.clone().capture(output).await;
+
+        let expected = [
+            "[Server thread/INFO]: Done",
+            "broken \u{FFFD} line",
+            "no newline",
+        ];
+        assert_eq!(buffer.tail(10), expected);
+        for line in expected {
+            assert_eq!(rx.recv().await.unwrap(), line);
+        }
+    }
+}
@@ -0,0 +1,263 @@
+/// What happened on the server, according to a single line of its output.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GameEvent {
+    PlayerJoined { player: String },
+    PlayerLeft { player: String },
+    Chat { player: String, message: String },
+    Death { player: String, message: String },
+    Advancement { player: String, advancement: String },
+    ServerStarted { seconds: f32 },
+    Warn { message: String },
+    Error { message: String },
+}
+
+/// Endings of vanilla death messages, that follow the name of the player.
+static DEATH_MESSAGES: [&str; 19] = [
+    "was ",
+    "walked into ",
+    "fell ",
+    "drowned",
+    "died",
+    "blew up",
+    "burned to death",
+    "hit the ground too hard",
+    "experienced kinetic energy",
+    "went up in flames",
+    "went off with a bang",
+    "tried to swim in lava",
+    "discovered the floor was lava",
+    "suffocated in a wall",
+    "starved to death",
+    "froze to death",
+    "withered away",
+    "didn't want to live",
+    "left the confines of this world",
+];
+
+static ADVANCEMENT_MESSAGES: [&str; 3] = [
+    " has made the advancement [",
+    " has completed the challenge [",
+    " has reached the goal [",
+];
+
+/// Parses a line in either vanilla (`[12:00:00] [Server thread/INFO]: message`)
+/// or Fabric (`[12:00:00] [Server thread/INFO] (Minecraft) message`) format.
+/// Returns `None` for lines that don't describe any event, e.g. parts of a stack trace.
+pub fn parse_line(line: &str) -> Option<GameEvent> {
+    let (level, message) = split_line(line)?;
+    match level {
+        "INFO" => parse_message(message),
+        "WARN" => Some(GameEvent::Warn {
+            message: message.to_string(),
+        }),
+        "ERROR" | "FATAL" => Some(GameEvent::Error {
+            message: message.to_string(),
+        }),
+        _ => None,
+    }
+}
+
+/// Returns the log level and the message of the line.
+fn split_line(line: &str) -> Option<(&str, &str)> {
+    // Skip the time
+    let rest = line.strip_prefix('[')?;
+    let (_time, rest) = rest.split_once("] [")?;
+    let (thread, rest) = rest.split_once(']')?;
+    let (_thread, level) = thread.rsplit_once('/')?;
+    let message = match rest.strip_prefix(": ") {
+        Some(message) => message,
+        None => {
+            // Fabric writes the name of the logger instead of a colon
+            let rest = rest.strip_prefix(" (")?;
+            let (_logger, message) = rest.split_once(") ")?;
+            message
+        }
+    };
+    Some((level, message))
+}
+
+fn parse_message(message: &str) -> Option<GameEvent> {
+    if let Some(seconds) = message.strip_prefix("Done (") {
+        let (seconds, _rest) = seconds.split_once("s)")?;
+        return Some(GameEvent::ServerStarted {
+            seconds: seconds.parse().ok()?,
+        });
+    }
+
+    // Since 1.19 messages without a signature are marked as such
+    let chat = message.strip_prefix("[Not Secure] ").unwrap_or(message);
+    if let Some(chat) = chat.strip_prefix('<') {
+        let (player, text) = chat.split_once("> ")?;
+        if !is_player_name(player) {
+            return None;
+        }
+        return Some(GameEvent::Chat {
+            player: player.to_string(),
+            message: text.to_string(),
+        });
+    }
+
+    let (player, rest) = message.split_once(' ')?;
+    if !is_player_name(player) {
+        return None;
+    }
+    let player = player.to_string();
+    match rest {
+        "joined the game" => return Some(GameEvent::PlayerJoined { player }),
+        "left the game" => return Some(GameEvent::PlayerLeft { player }),
+        _ => {}
+    }
+
+    for prefix in ADVANCEMENT_MESSAGES {
+        if let Some(advancement) = message[player.len()..].strip_prefix(prefix) {
+            let advancement = advancement.strip_suffix(']')?;
+            return Some(GameEvent::Advancement {
+                player,
+                advancement: advancement.to_string(),
+            });
+        }
+    }
+
+    if DEATH_MESSAGES.iter().any(|death| rest.starts_with(death)) {
+        return Some(GameEvent::Death {
+            player,
+            message: message.to_string(),
+        });
+    }
+    None
+}
+
+/// Player names are 3 to 16 characters long, and consist of letters, digits and underscores.
+fn is_player_name(name: &str) -> bool {
+    (3..=16).contains(&name.len()) && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_fixture(fixture: &str) -> Vec<GameEvent> {
+        fixture.lines().filter_map(parse_line).collect()
+    }
+
+    fn player(name: &str) -> String {
+        name.to_string()
+    }
+
+    #[test]
+    fn parses_vanilla_log() {
+        let events = parse_fixture(include_str!("../../tests/fixtures/vanilla.log"));
+        let expected = vec![
+            GameEvent::Warn {
+                message: "Failed to load eula.txt".to_string(),
+            },
+            GameEvent::ServerStarted { seconds: 7.512 },
+            GameEvent::PlayerJoined {
+                player: player("Steve"),
+            },
+            GameEvent::Chat {
+                player: player("Steve"),
+                message: "hello there".to_string(),
+            },
+            GameEvent::Chat {
+                player: player("Steve"),
+                message: "is anyone <here>?".to_string(),
+            },
+            GameEvent::Advancement {
+                player: player("Steve"),
+                advancement: "Stone Age".to_string(),
+            },
+            GameEvent::Warn {
+                message:
+                    "Can't keep up! Is the server overloaded? Running 2043ms or 40 ticks behind"
+                        .to_string(),
+            },
+            GameEvent::Death {
+                player: player("Steve"),
+                message: "Steve was slain by Zombie".to_string(),
+            },
+            GameEvent::PlayerJoined {
+                player: player("Alex_2"),
+            },
+            GameEvent::Death {
+                player: player("Alex_2"),
+                message: "Alex_2 fell from a high place".to_string(),
+            },
+            GameEvent::Advancement {
+                player: player("Alex_2"),
+                advancement: "Return to Sender".to_string(),
+            },
+            GameEvent::Advancement {
+                player: player("Alex_2"),
+                advancement: "Sky's the Limit".to_string(),
+            },
+            GameEvent::Death {
+                player: player("Alex_2"),
+                message: "Alex_2 drowned".to_string(),
+            },
+            GameEvent::PlayerLeft {
+                player: player("Steve"),
+            },
+            GameEvent::Error {
+                message: "Encountered an unexpected exception".to_string(),
+            },
+            GameEvent::PlayerLeft {
+                player: player("Alex_2"),
+            },
+        ];
+        assert_eq!(events, expected);
+    }
+
+    #[test]
+    fn parses_fabric_log() {
+        let events = parse_fixture(include_str!("../../tests/fixtures/fabric.log"));
+        let expected = vec![
+            GameEvent::Warn {
+                message:
+                    "Reference map 'lithium.refmap.json' for lithium.mixins.json could not be read"
+                        .to_string(),
+            },
+            GameEvent::ServerStarted { seconds: 9.873 },
+            GameEvent::PlayerJoined {
+                player: player("Notch"),
+            },
+            GameEvent::Chat {
+                player: player("Notch"),
+                message: "gg".to_string(),
+            },
+            GameEvent::Death {
+                player: player("Notch"),
+                message: "Notch tried to swim in lava".to_string(),
+            },
+            GameEvent::Advancement {
+                player: player("Notch"),
+                advancement: "Hot Stuff".to_string(),
+            },
+            GameEvent::Error {
+                message: "Couldn't save chunk; already in use by another instance of Minecraft?"
+                    .to_string(),
+            },
+            GameEvent::PlayerLeft {
+                player: player("Notch"),
+            },
+        ];
+        assert_eq!(events, expected);
+    }
+
+    #[test]
+    fn ignores_unrelated_lines() {
+        let lines = [
+            "",
+            "Starting net.minecraft.server.Main",
+            "\tat net.minecraft.server.MinecraftServer.tick(MinecraftServer.java:123)",
+            "[14:05:40] [User Authenticator #1/INFO]: UUID of player Steve is 8667ba71-b85a-4004-af54-457a9734eed7",
+            "[14:05:40] [Server thread/INFO]: Steve[/172.17.0.1:51234] logged in with entity id 312",
+            "[14:16:00] [Server thread/INFO]: [Rcon: Saved the game]",
+            "[14:16:00] [Server thread/INFO]: <St> too short name",
+            "[14:16:00] [Server thread/DEBUG]: Steve died",
+        ];
+        for line in lines {
+            assert_eq!(parse_line(line), None, "{}", line);
+        }
+    }
+}
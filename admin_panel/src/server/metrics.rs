@@ -0,0 +1,262 @@
+use std::{
+    fmt::Write,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use super::{
+    process::{resource_usage, ProcessInfo},
+    tick_health::TickSample,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ServerState {
+    Stopped,
+    Sleeping,
+    Starting,
+    Idle,
+    Busy,
+}
+
+impl ServerState {
+    const ALL: [ServerState; 5] = [
+        ServerState::Stopped,
+        ServerState::Sleeping,
+        ServerState::Starting,
+        ServerState::Idle,
+        ServerState::Busy,
+    ];
+
+    fn label(&self) -> &'static str {
+        match *self {
+            ServerState::Stopped => "stopped",
+            ServerState::Sleeping => "sleeping",
+            ServerState::Starting => "starting",
+            ServerState::Idle => "idle",
+            ServerState::Busy => "busy",
+        }
+    }
+}
+
+struct Counters {
+    state: ServerState,
+    players_online: u32,
+    max_players: u32,
+    idle_since: Option<Instant>,
+    rcon_commands: u64,
+    rcon_errors: u64,
+    rcon_seconds: f64,
+    poll_failures: u64,
+    tick: Option<TickSample>,
+}
+
+/// Updated by the proxy, and rendered in Prometheus text format by the web layer.
+#[derive(Clone)]
+pub struct Metrics {
+    counters: Arc<Mutex<Counters>>,
+    created: Instant,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let counters = Counters {
+            state: ServerState::Starting,
+            players_online: 0,
+            max_players: 0,
+            idle_since: None,
+            rcon_commands: 0,
+            rcon_errors: 0,
+            rcon_seconds: 0.0,
+            poll_failures: 0,
+            tick: None,
+        };
+        Self {
+            counters: Arc::new(Mutex::new(counters)),
+            created: Instant::now(),
+        }
+    }
+
+    pub fn set_state(
+        &self,
+        state: ServerState,
+        players_online: u32,
+        max_players: u32,
+        idle_since: Option<Instant>,
+    ) {
+        let mut counters = self.counters.lock().unwrap_or_else(PoisonError::into_inner);
+        counters.state = state;
+        counters.players_online = players_online;
+        counters.max_players = max_players;
+        counters.idle_since = idle_since;
+    }
+
+    pub fn record_rcon(&self, duration: Duration, success: bool) {
+        let mut counters = self.counters.lock().unwrap_or_else(PoisonError::into_inner);
+        counters.rcon_commands += 1;
+        counters.rcon_seconds += duration.as_secs_f64();
+        if !success {
+            counters.rcon_errors += 1;
+        }
+    }
+
+    pub fn set_tick(&self, tick: Option<TickSample>) {
+        let mut counters = self.counters.lock().unwrap_or_else(PoisonError::into_inner);
+        counters.tick = tick;
+    }
+
+    pub fn record_poll_failure(&self) {
+        let mut counters = self.counters.lock().unwrap_or_else(PoisonError::into_inner);
+        counters.poll_failures += 1;
+    }
+
+    pub fn render(&self, process: &ProcessInfo) -> String {
+        let mut out = String::new();
+        {
+            let counters = self.counters.lock().unwrap_or_else(PoisonError::into_inner);
+            gauge(
+                &mut out,
+                "minecraft_players_online",
+                "Number of players on the server",
+                counters.players_online,
+            );
+            gauge(
+                &mut out,
+                "minecraft_players_max",
+                "Maximum number of players on the server",
+                counters.max_players,
+            );
+            header(
+                &mut out,
+                "minecraft_server_state",
+                "gauge",
+                "Current state of the server",
+            );
+            for state in ServerState::ALL {
+                let value = u8::from(state == counters.state);
+                let _ = writeln!(
+                    out,
+                    "minecraft_server_state{{state=\"{}\"}} {}",
+                    state.label(),
+                    value
+                );
+            }
+            let idle_seconds = counters
+                .idle_since
+                .map(|time| time.elapsed().as_secs())
+                .unwrap_or(0);
+            gauge(
+                &mut out,
+                "minecraft_server_idle_seconds",
+                "For how long there have been no players on the server",
+                idle_seconds,
+            );
+            counter(
+                &mut out,
+                "minecraft_rcon_commands_total",
+                "Number of commands sent over RCON",
+                counters.rcon_commands,
+            );
+            counter(
+                &mut out,
+                "minecraft_rcon_errors_total",
+                "Number of RCON commands that failed",
+                counters.rcon_errors,
+            );
+            header(
+                &mut out,
+                "minecraft_rcon_command_duration_seconds",
+                "summary",
+                "Time it took to execute RCON commands",
+            );
+            let _ = writeln!(
+                out,
+                "minecraft_rcon_command_duration_seconds_sum {}",
+                counters.rcon_seconds
+            );
+            let _ = writeln!(
+                out,
+                "minecraft_rcon_command_duration_seconds_count {}",
+                counters.rcon_commands
+            );
+            counter(
+                &mut out,
+                "minecraft_status_poll_failures_total",
+                "Number of times the server didn't answer the status probe",
+                counters.poll_failures,
+            );
+            // Without samples there is nothing meaningful to report
+            if let Some(tick) = counters.tick {
+                gauge(
+                    &mut out,
+                    "minecraft_tps",
+                    "Ticks per second, according to the last sample",
+                    tick.tps,
+                );
+                gauge(
+                    &mut out,
+                    "minecraft_mspt",
+                    "Milliseconds per tick, according to the last sample",
+                    tick.mspt,
+                );
+            }
+        }
+
+        let usage = process.pid.and_then(resource_usage);
+        gauge(
+            &mut out,
+            "minecraft_process_resident_memory_bytes",
+            "Resident memory size of the server process",
+            usage.as_ref().map(|u| u.resident_memory_bytes).unwrap_or(0),
+        );
+        counter(
+            &mut out,
+            "minecraft_process_cpu_seconds_total",
+            "CPU time used by the server process",
+            usage.as_ref().map(|u| u.cpu_seconds).unwrap_or(0.0),
+        );
+        let uptime = process
+            .started
+            .map(|time| time.elapsed().as_secs())
+            .unwrap_or(0);
+        gauge(
+            &mut out,
+            "minecraft_server_uptime_seconds",
+            "For how long the server process has been running",
+            uptime,
+        );
+        counter(
+            &mut out,
+            "minecraft_server_restarts_total",
+            "Number of times the server was started again after a crash, hibernation or restore",
+            process.restarts,
+        );
+        counter(
+            &mut out,
+            "minecraft_server_crashes_total",
+            "Number of times the server exited with an error",
+            process.crashes,
+        );
+        gauge(
+            &mut out,
+            "minecraft_admin_panel_uptime_seconds",
+            "For how long the admin panel has been running",
+            self.created.elapsed().as_secs(),
+        );
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn gauge<T: std::fmt::Display>(out: &mut String, name: &str, help: &str, value: T) {
+    header(out, name, "gauge", help);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+fn counter<T: std::fmt::Display>(out: &mut String, name: &str, help: &str, value: T) {
+    header(out, name, "counter", help);
+    let _ = writeln!(out, "{} {}", name, value);
+}
@@ -1,2 +1,14 @@
+pub mod firewall;
+pub mod list_ping;
+pub mod log_buffer;
+pub mod log_parser;
+pub mod metrics;
 pub mod online_poller;
+pub mod process;
+pub mod protocol;
 pub mod proxy_service;
+pub mod rcon;
+pub mod status;
+pub mod temp_bans;
+pub mod tick_health;
+pub mod wake_listener;
@@ -1,11 +1,17 @@
-use std::{io, net::Ipv4Addr, num::ParseIntError, time::Duration};
+use std::{collections::HashMap, io, net::Ipv4Addr, num::ParseIntError, time::Duration};
 
+use axum::async_trait;
 use log::debug;
 use thiserror::Error;
 use tokio::{net::UdpSocket, time::timeout};
 
 use crate::error::OnlinePollerError;
 
+use super::{
+    firewall::QUERY_PORT,
+    status::{ProbeError, ServerSnapshot, StatusProber},
+};
+
 #[derive(Error, Debug)]
 pub enum InnerError {
     #[error("Generic IO error: {0}")]
@@ -26,6 +32,13 @@ pub enum InnerError {
     ReadTimeout,
 }
 
+/// Full stat response has constant padding before K/V section...
+const KV_PADDING: &[u8] = b"splitnum\x00\x80\x00";
+/// ...and before the player section.
+const PLAYERS_PADDING: &[u8] = b"\x01player_\x00\x00";
+const BUFFER_SIZE: usize = 8192;
+
+/// Polls the server over GameSpy4 query protocol. Requires `enable-query=true`.
 pub struct OnlinePoller {
     socket: UdpSocket,
     read_timeout: Duration,
@@ -38,31 +51,31 @@ struct Challenge {
 
 impl OnlinePoller {
     pub async fn new() -> Result<Self, OnlinePollerError> {
-        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
-        socket.connect((Ipv4Addr::LOCALHOST, 25566)).await?;
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+        socket.connect((Ipv4Addr::LOCALHOST, QUERY_PORT)).await?;
         Ok(Self {
             socket,
             read_timeout: Duration::from_secs(1),
         })
     }
 
-    pub async fn current_online(&self) -> Result<u32, InnerError> {
+    async fn query(&self) -> Result<ServerSnapshot, InnerError> {
         /*
         It's possible that under heavy load the server will be slow to respond,
         and instead we will catch its response on the next cycle.
         Try to catch this response first before sending new packets.
          */
-        if let Ok(online) = self.catch_old_packet().await {
-            Ok(online)
+        if let Ok(snapshot) = self.catch_old_packet().await {
+            Ok(snapshot)
         } else {
             let challenge = self.get_challenge().await?;
-            self.get_online(challenge).await
+            self.get_full_stat(challenge).await
         }
     }
 
-    async fn catch_old_packet(&self) -> Result<u32, InnerError> {
-        let mut buffer = [0u8; 512];
-        debug!("Reading the number of players online from the socket...");
+    async fn catch_old_packet(&self) -> Result<ServerSnapshot, InnerError> {
+        let mut buffer = [0u8; BUFFER_SIZE];
+        debug!("Reading server status from the socket...");
         let n = match timeout(self.read_timeout, self.socket.recv(&mut buffer)).await {
             Ok(r) => r?,
             Err(_e) => return Err(InnerError::ReadTimeout),
@@ -70,10 +83,10 @@ impl OnlinePoller {
 
         let packet_type = buffer[0];
         match packet_type {
-            0 => OnlinePoller::read_online_packet(buffer, n),
+            0 => OnlinePoller::read_full_stat_packet(&buffer[..n]),
             9 => {
-                let challenge = OnlinePoller::read_challenge_packet(buffer, n)?;
-                self.get_online(challenge).await
+                let challenge = OnlinePoller::read_challenge_packet(&buffer[..n])?;
+                self.get_full_stat(challenge).await
             }
             _ => Err(InnerError::UnknownType(packet_type)),
         }
@@ -93,17 +106,17 @@ impl OnlinePoller {
 
         debug!("Sending packet to the socket to get the challenge...");
         self.socket.send(&packet).await?;
-        let mut buffer = [0u8; 512];
+        let mut buffer = [0u8; BUFFER_SIZE];
         debug!("Reading challenge from the socket...");
         let n = match timeout(self.read_timeout, self.socket.recv(&mut buffer)).await {
             Ok(r) => r?,
             Err(_e) => return Err(InnerError::ReadTimeout),
         };
-        OnlinePoller::read_challenge_packet(buffer, n)
+        OnlinePoller::read_challenge_packet(&buffer[..n])
     }
 
-    async fn get_online(&self, challenge: Challenge) -> Result<u32, InnerError> {
-        debug!("Asking for current online");
+    async fn get_full_stat(&self, challenge: Challenge) -> Result<ServerSnapshot, InnerError> {
+        debug!("Asking for full stat");
         let mut packet = Vec::with_capacity(64);
         packet.push(0xFE);
         packet.push(0xFD);
@@ -119,28 +132,34 @@ impl OnlinePoller {
             packet.push(byte);
         }
 
-        debug!("Sending packet to the socket to get the number of players online...");
+        // Padding turns basic stat request into full stat request
+        for byte in [0x00, 0x00, 0x00, 0x00] {
+            packet.push(byte);
+        }
+
+        debug!("Sending packet to the socket to get server status...");
         self.socket.send(&packet).await?;
-        let mut buffer = [0u8; 512];
-        debug!("Reading the number of players online from the socket...");
+        let mut buffer = [0u8; BUFFER_SIZE];
+        debug!("Reading server status from the socket...");
         let n = match timeout(self.read_timeout, self.socket.recv(&mut buffer)).await {
             Ok(r) => r?,
             Err(_e) => return Err(InnerError::ReadTimeout),
         };
-        OnlinePoller::read_online_packet(buffer, n)
+        OnlinePoller::read_full_stat_packet(&buffer[..n])
     }
 
-    fn read_challenge_packet(buffer: [u8; 512], n: usize) -> Result<Challenge, InnerError> {
-        debug!("Received packet: {:?}", &buffer[..n]);
+    fn read_challenge_packet(buffer: &[u8]) -> Result<Challenge, InnerError> {
+        debug!("Received packet: {:?}", buffer);
+        let n = buffer.len();
+        if n < 5 {
+            return Err(InnerError::TooShort(n));
+        }
+
         let packet_type = buffer[0];
         if packet_type != 9 {
             return Err(InnerError::IncorrectType(9, packet_type));
         }
 
-        if n < 5 {
-            return Err(InnerError::TooShort(n));
-        }
-
         let mut session = [0u8; 4];
         session.clone_from_slice(&buffer[1..=4]);
         let session = u32::from_be_bytes(session);
@@ -154,39 +173,209 @@ impl OnlinePoller {
         Ok(Challenge { challenge, session })
     }
 
-    fn read_online_packet(buffer: [u8; 512], n: usize) -> Result<u32, InnerError> {
-        debug!("Received packet: {:?}", &buffer[..n]);
+    fn read_full_stat_packet(buffer: &[u8]) -> Result<ServerSnapshot, InnerError> {
+        debug!("Received packet: {:?}", buffer);
+        let n = buffer.len();
+        if n < 5 + KV_PADDING.len() {
+            return Err(InnerError::TooShort(n));
+        }
+
         let packet_type = buffer[0];
         if packet_type != 0 {
             return Err(InnerError::IncorrectType(0, packet_type));
         }
 
-        if n < 5 {
-            return Err(InnerError::TooShort(n));
+        let mut position = 5 + KV_PADDING.len();
+        let mut values = HashMap::new();
+        loop {
+            let key = match next_string(buffer, &mut position) {
+                Some(key) => key,
+                None => return Err(InnerError::NoField("End of K/V section".to_string())),
+            };
+            if key.is_empty() {
+                break;
+            }
+            let value = match next_string(buffer, &mut position) {
+                Some(value) => value,
+                None => return Err(InnerError::NoField(key)),
+            };
+            values.insert(key, value);
         }
 
-        let mut strings = buffer[5..].split(|byte| byte == &0);
+        position += PLAYERS_PADDING.len();
+        let mut players = Vec::new();
+        while let Some(player) = next_string(buffer, &mut position) {
+            if player.is_empty() {
+                break;
+            }
+            players.push(player);
+        }
 
-        match strings.next() {
-            Some(_) => {}
-            None => return Err(InnerError::NoField("MOTD".to_string())),
+        let mut field = |name: &str| match values.remove(name) {
+            Some(value) => Ok(value),
+            None => Err(InnerError::NoField(name.to_string())),
         };
+        let motd = field("hostname")?;
+        let version = field("version")?;
+        let map = field("map")?;
+        let online = field("numplayers")?.parse::<u32>()?;
+        let max_players = field("maxplayers")?.parse::<u32>()?;
+        let plugins = field("plugins").unwrap_or_default();
 
-        match strings.next() {
-            Some(_) => {}
-            None => return Err(InnerError::NoField("Game type".to_string())),
-        };
+        Ok(ServerSnapshot {
+            motd,
+            version,
+            map,
+            online,
+            max_players,
+            players,
+            plugins: parse_plugins(&plugins),
+            latency_ms: None,
+        })
+    }
+}
 
-        match strings.next() {
-            Some(_) => {}
-            None => return Err(InnerError::NoField("Map".to_string())),
-        };
+#[async_trait]
+impl StatusProber for OnlinePoller {
+    async fn current_status(&self) -> Result<ServerSnapshot, ProbeError> {
+        Ok(self.query().await?)
+    }
+}
 
-        let current_online = match strings.next() {
-            Some(bytes) => String::from_utf8_lossy(bytes).parse::<u32>()?,
-            None => return Err(InnerError::NoField("Current online".to_string())),
-        };
+/// Reads null-terminated string starting at `position`, and moves `position` past the terminator.
+fn next_string(buffer: &[u8], position: &mut usize) -> Option<String> {
+    let rest = buffer.get(*position..)?;
+    let length = rest.iter().position(|byte| byte == &0)?;
+    *position += length + 1;
+    Some(String::from_utf8_lossy(&rest[..length]).into_owned())
+}
+
+/// Plugins are reported as `<server software>: <plugin 1>; <plugin 2>`.
+/// Vanilla server reports only the server software, or nothing at all.
+fn parse_plugins(plugins: &str) -> Vec<String> {
+    match plugins.split_once(':') {
+        Some((_software, plugins)) => plugins
+            .split(';')
+            .map(|plugin| plugin.trim().to_string())
+            .filter(|plugin| !plugin.is_empty())
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VALUES: [(&str, &str); 9] = [
+        ("hostname", "A Minecraft Server"),
+        ("gametype", "SMP"),
+        ("game_id", "MINECRAFT"),
+        ("version", "1.19.2"),
+        ("plugins", "Fabric on 1.19.2: lithium 0.10.3; carpet 1.4.84"),
+        ("map", "world"),
+        ("numplayers", "2"),
+        ("maxplayers", "10"),
+        ("hostport", "25565"),
+    ];
+
+    fn full_stat(values: &[(&str, &str)], players: &[&str]) -> Vec<u8> {
+        let mut packet = vec![0, 0, 0, 0, 1];
+        packet.extend_from_slice(KV_PADDING);
+        for (key, value) in values {
+            packet.extend_from_slice(key.as_bytes());
+            packet.push(0);
+            packet.extend_from_slice(value.as_bytes());
+            packet.push(0);
+        }
+        packet.push(0);
+        packet.extend_from_slice(PLAYERS_PADDING);
+        for player in players {
+            packet.extend_from_slice(player.as_bytes());
+            packet.push(0);
+        }
+        packet.push(0);
+        packet
+    }
+
+    #[test]
+    fn reads_full_stat() {
+        let packet = full_stat(&VALUES, &["Steve", "Alex"]);
+        let snapshot = OnlinePoller::read_full_stat_packet(&packet).unwrap();
+        assert_eq!(snapshot.motd, "A Minecraft Server");
+        assert_eq!(snapshot.version, "1.19.2");
+        assert_eq!(snapshot.map, "world");
+        assert_eq!(snapshot.online, 2);
+        assert_eq!(snapshot.max_players, 10);
+        assert_eq!(snapshot.players, vec!["Steve", "Alex"]);
+        assert_eq!(snapshot.plugins, vec!["lithium 0.10.3", "carpet 1.4.84"]);
+    }
+
+    #[test]
+    fn ignores_trailing_padding() {
+        let mut packet = full_stat(&VALUES, &[]);
+        packet.extend_from_slice(&[0; 16]);
+        let snapshot = OnlinePoller::read_full_stat_packet(&packet).unwrap();
+        assert!(snapshot.players.is_empty());
+        assert_eq!(snapshot.online, 2);
+    }
+
+    #[test]
+    fn rejects_truncated_full_stat() {
+        let packet = full_stat(&VALUES, &["Steve", "Alex"]);
+        let result = OnlinePoller::read_full_stat_packet(&packet[..8]);
+        assert!(matches!(result, Err(InnerError::TooShort(8))));
+
+        // Cut in the middle of the K/V section
+        let result = OnlinePoller::read_full_stat_packet(&packet[..40]);
+        assert!(matches!(result, Err(InnerError::NoField(_))));
+
+        // Cut in the middle of the second player, the first one is still there
+        let cut = packet.len() - 3;
+        let snapshot = OnlinePoller::read_full_stat_packet(&packet[..cut]).unwrap();
+        assert_eq!(snapshot.players, vec!["Steve"]);
+    }
+
+    #[test]
+    fn rejects_incomplete_full_stat() {
+        let values: Vec<_> = VALUES
+            .iter()
+            .filter(|(key, _)| *key != "numplayers")
+            .cloned()
+            .collect();
+        let result = OnlinePoller::read_full_stat_packet(&full_stat(&values, &[]));
+        assert!(matches!(result, Err(InnerError::NoField(field)) if field == "numplayers"));
+
+        let mut packet = full_stat(&VALUES, &[]);
+        packet[0] = 9;
+        let result = OnlinePoller::read_full_stat_packet(&packet);
+        assert!(matches!(result, Err(InnerError::IncorrectType(0, 9))));
+    }
+
+    #[test]
+    fn reads_challenge() {
+        let packet = b"\x09\x00\x00\x00\x01nonce\x00";
+        assert!(matches!(
+            OnlinePoller::read_challenge_packet(packet),
+            Err(InnerError::Parsing(_))
+        ));
+        let packet = b"\x09\x00\x00\x00\x019513307\x00";
+        let challenge = OnlinePoller::read_challenge_packet(packet).unwrap();
+        assert_eq!(challenge.session, 1);
+        assert_eq!(challenge.challenge, 9513307);
+        assert!(matches!(
+            OnlinePoller::read_challenge_packet(&packet[..3]),
+            Err(InnerError::TooShort(3))
+        ));
+    }
 
-        Ok(current_online)
+    #[test]
+    fn parses_plugins() {
+        assert!(parse_plugins("").is_empty());
+        assert!(parse_plugins("Fabric on 1.19.2").is_empty());
+        assert_eq!(
+            parse_plugins("CraftBukkit on Bukkit 1.2.5-R4.0: WorldEdit 5.3;CommandBook 2.1; "),
+            vec!["WorldEdit 5.3", "CommandBook 2.1"]
+        );
     }
 }
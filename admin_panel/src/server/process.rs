@@ -0,0 +1,175 @@
+use std::{
+    fs,
+    process::ExitStatus,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+/// If the server has been running for that long, it's not considered to be in a crash loop anymore.
+const STABLE_UPTIME: Duration = Duration::from_secs(10 * 60);
+/// Linux always reports CPU time to user space in 1/100ths of a second.
+const CLOCK_TICKS_PER_SECOND: f64 = 100.0;
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ProcessInfo {
+    pub crashes: u32,
+    pub restarts: u32,
+    pub last_exit_status: Option<String>,
+    #[serde(skip)]
+    pub pid: Option<u32>,
+    #[serde(skip)]
+    pub started: Option<Instant>,
+    #[serde(skip)]
+    launched: bool,
+}
+
+/// Resources used by the MC server process, as reported by `/proc`.
+pub struct ResourceUsage {
+    pub resident_memory_bytes: u64,
+    pub cpu_seconds: f64,
+}
+
+/// Shared between the supervisor, which records how the MC server exited, and the proxy, which reports it.
+#[derive(Clone)]
+pub struct ProcessState(Arc<Mutex<ProcessInfo>>);
+
+impl ProcessState {
+    pub fn new() -> Self {
+        Self(Arc::new(Mutex::new(ProcessInfo::default())))
+    }
+
+    pub fn get(&self) -> ProcessInfo {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    pub fn record_start(&self, pid: Option<u32>) {
+        let mut info = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        if info.launched {
+            info.restarts += 1;
+        }
+        info.launched = true;
+        info.pid = pid;
+        info.started = Some(Instant::now());
+    }
+
+    /// Status is `None` if the server didn't exit in time and was killed.
+    /// Returns `true` if the server crashed.
+    pub fn record_exit(&self, status: Option<ExitStatus>) -> bool {
+        let mut info = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        info.pid = None;
+        info.started = None;
+        let status = match status {
+            Some(status) => status,
+            None => return false,
+        };
+        info.last_exit_status = Some(status.to_string());
+        if status.success() {
+            return false;
+        }
+        info.crashes += 1;
+        true
+    }
+}
+
+/// Returns `None` if the process doesn't exist, or `/proc` isn't available.
+pub fn resource_usage(pid: u32) -> Option<ResourceUsage> {
+    let status = fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+    let resident_memory_kb: u64 = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+
+    // The name of the process may contain spaces, so the fields are counted from its end
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    let (_name, fields) = stat.rsplit_once(')')?;
+    let mut fields = fields.split_whitespace().skip(11);
+    let user_time: u64 = fields.next()?.parse().ok()?;
+    let system_time: u64 = fields.next()?.parse().ok()?;
+
+    Some(ResourceUsage {
+        resident_memory_bytes: resident_memory_kb * 1024,
+        cpu_seconds: (user_time + system_time) as f64 / CLOCK_TICKS_PER_SECOND,
+    })
+}
+
+/// Decides whether the crashed server should be restarted, and how long to wait before that.
+pub struct CrashBackoff {
+    max_restarts: u8,
+    initial_delay: Duration,
+    restarts: u8,
+}
+
+impl CrashBackoff {
+    pub fn new(max_restarts: u8, initial_delay: Duration) -> Self {
+        Self {
+            max_restarts,
+            initial_delay,
+            restarts: 0,
+        }
+    }
+
+    /// Returns `None` if the server crashed too many times in a row.
+    pub fn next_delay(&mut self, uptime: Duration) -> Option<Duration> {
+        if uptime >= STABLE_UPTIME {
+            self.restarts = 0;
+        }
+        if self.restarts >= self.max_restarts {
+            return None;
+        }
+        let delay = self
+            .initial_delay
+            .saturating_mul(2u32.saturating_pow(self.restarts as u32));
+        self.restarts += 1;
+        Some(delay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CRASH: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn doubles_delay_until_giving_up() {
+        let mut backoff = CrashBackoff::new(3, Duration::from_secs(10));
+        assert_eq!(backoff.next_delay(CRASH), Some(Duration::from_secs(10)));
+        assert_eq!(backoff.next_delay(CRASH), Some(Duration::from_secs(20)));
+        assert_eq!(backoff.next_delay(CRASH), Some(Duration::from_secs(40)));
+        assert_eq!(backoff.next_delay(CRASH), None);
+        assert_eq!(backoff.next_delay(CRASH), None);
+
+        let mut disabled = CrashBackoff::new(0, Duration::from_secs(10));
+        assert_eq!(disabled.next_delay(CRASH), None);
+    }
+
+    #[test]
+    fn resets_after_stable_run() {
+        let mut backoff = CrashBackoff::new(2, Duration::from_secs(10));
+        backoff.next_delay(CRASH);
+        assert_eq!(backoff.next_delay(CRASH), Some(Duration::from_secs(20)));
+        assert_eq!(backoff.next_delay(STABLE_UPTIME - CRASH), None);
+        assert_eq!(
+            backoff.next_delay(STABLE_UPTIME),
+            Some(Duration::from_secs(10))
+        );
+        assert_eq!(backoff.next_delay(CRASH), Some(Duration::from_secs(20)));
+    }
+
+    #[test]
+    fn saturates_long_delays() {
+        let mut backoff = CrashBackoff::new(u8::MAX, Duration::from_secs(60));
+        let delays: Vec<_> = std::iter::from_fn(|| backoff.next_delay(CRASH)).collect();
+        assert_eq!(delays.len(), u8::MAX as usize);
+        assert!(delays.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+}
@@ -0,0 +1,225 @@
+use std::{io, string::FromUtf8Error};
+
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Packets bigger than this are never sent by a vanilla client before the login phase,
+/// so anything larger is treated as garbage.
+const MAX_PACKET_LENGTH: i32 = 32 * 1024;
+
+#[derive(Error, Debug)]
+pub enum PacketError {
+    #[error("Generic IO error: {0}")]
+    IO(#[from] io::Error),
+    #[error("VarInt is too big")]
+    VarIntTooBig,
+    #[error("Incorrect packet length: {0}")]
+    IncorrectLength(i32),
+    #[error("Packet is too short")]
+    TooShort,
+    #[error("Couldn't parse string: {0}")]
+    Utf8(#[from] FromUtf8Error),
+}
+
+pub async fn read_varint<R: AsyncRead + Unpin>(reader: &mut R) -> Result<i32, PacketError> {
+    let mut value = 0i32;
+    for position in 0..5 {
+        let byte = reader.read_u8().await?;
+        value |= ((byte & 0x7F) as i32) << (7 * position);
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(PacketError::VarIntTooBig)
+}
+
+pub fn write_varint(buffer: &mut Vec<u8>, value: i32) {
+    let mut value = value as u32;
+    loop {
+        if value & !0x7F == 0 {
+            buffer.push(value as u8);
+            return;
+        }
+        buffer.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+}
+
+pub fn write_string(buffer: &mut Vec<u8>, value: &str) {
+    write_varint(buffer, value.len() as i32);
+    buffer.extend_from_slice(value.as_bytes());
+}
+
+/// Reads a single length-prefixed packet and returns its id and payload.
+pub async fn read_packet<R: AsyncRead + Unpin>(
+    reader: &mut R,
+) -> Result<(i32, Vec<u8>), PacketError> {
+    let length = read_varint(reader).await?;
+    if length <= 0 || length > MAX_PACKET_LENGTH {
+        return Err(PacketError::IncorrectLength(length));
+    }
+    let mut data = vec![0u8; length as usize];
+    reader.read_exact(&mut data).await?;
+
+    let mut payload = PacketReader::new(&data);
+    let id = payload.varint()?;
+    let offset = payload.position;
+    Ok((id, data.split_off(offset)))
+}
+
+pub async fn write_packet<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    id: i32,
+    payload: &[u8],
+) -> Result<(), PacketError> {
+    let mut body = Vec::with_capacity(payload.len() + 5);
+    write_varint(&mut body, id);
+    body.extend_from_slice(payload);
+
+    let mut packet = Vec::with_capacity(body.len() + 5);
+    write_varint(&mut packet, body.len() as i32);
+    packet.extend_from_slice(&body);
+    writer.write_all(&packet).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Cursor over the payload of an already received packet.
+pub struct PacketReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> PacketReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], PacketError> {
+        if self.data.len() - self.position < n {
+            return Err(PacketError::TooShort);
+        }
+        let slice = &self.data[self.position..self.position + n];
+        self.position += n;
+        Ok(slice)
+    }
+
+    pub fn varint(&mut self) -> Result<i32, PacketError> {
+        let mut value = 0i32;
+        for position in 0..5 {
+            let byte = self.take(1)?[0];
+            value |= ((byte & 0x7F) as i32) << (7 * position);
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(PacketError::VarIntTooBig)
+    }
+
+    pub fn string(&mut self) -> Result<String, PacketError> {
+        let length = self.varint()?;
+        if !(0..=MAX_PACKET_LENGTH).contains(&length) {
+            return Err(PacketError::IncorrectLength(length));
+        }
+        let bytes = self.take(length as usize)?;
+        Ok(String::from_utf8(bytes.to_vec())?)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, PacketError> {
+        let mut bytes = [0u8; 2];
+        bytes.clone_from_slice(self.take(2)?);
+        Ok(u16::from_be_bytes(bytes))
+    }
+
+    pub fn i64(&mut self) -> Result<i64, PacketError> {
+        let mut bytes = [0u8; 8];
+        bytes.clone_from_slice(self.take(8)?);
+        Ok(i64::from_be_bytes(bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_varints() {
+        let cases: [(i32, &[u8]); 7] = [
+            (0, &[0x00]),
+            (1, &[0x01]),
+            (127, &[0x7F]),
+            (128, &[0x80, 0x01]),
+            (25565, &[0xDD, 0xC7, 0x01]),
+            (i32::MAX, &[0xFF, 0xFF, 0xFF, 0xFF, 0x07]),
+            (-1, &[0xFF, 0xFF, 0xFF, 0xFF, 0x0F]),
+        ];
+        for (value, bytes) in cases {
+            let mut buffer = Vec::new();
+            write_varint(&mut buffer, value);
+            assert_eq!(buffer, bytes, "{}", value);
+            assert_eq!(PacketReader::new(bytes).varint().unwrap(), value);
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_broken_varints() {
+        let too_big: &[u8] = &[0x80, 0x80, 0x80, 0x80, 0x80, 0x01];
+        assert!(matches!(
+            PacketReader::new(too_big).varint(),
+            Err(PacketError::VarIntTooBig)
+        ));
+        assert!(matches!(
+            read_varint(&mut &too_big[..]).await,
+            Err(PacketError::VarIntTooBig)
+        ));
+
+        let truncated: &[u8] = &[0x80, 0x80];
+        assert!(matches!(
+            PacketReader::new(truncated).varint(),
+            Err(PacketError::TooShort)
+        ));
+        assert!(matches!(
+            read_varint(&mut &truncated[..]).await,
+            Err(PacketError::IO(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn round_trips_packets() {
+        let mut payload = Vec::new();
+        write_string(&mut payload, "héllo");
+        payload.extend_from_slice(&25565u16.to_be_bytes());
+        let mut packet = Vec::new();
+        write_packet(&mut packet, 0x42, &payload).await.unwrap();
+
+        let (id, payload) = read_packet(&mut &packet[..]).await.unwrap();
+        assert_eq!(id, 0x42);
+        let mut reader = PacketReader::new(&payload);
+        assert_eq!(reader.string().unwrap(), "héllo");
+        assert_eq!(reader.u16().unwrap(), 25565);
+        assert!(matches!(reader.u16(), Err(PacketError::TooShort)));
+    }
+
+    #[tokio::test]
+    async fn rejects_incorrect_lengths() {
+        let empty: &[u8] = &[0x00];
+        assert!(matches!(
+            read_packet(&mut &empty[..]).await,
+            Err(PacketError::IncorrectLength(0))
+        ));
+        let mut huge = Vec::new();
+        write_varint(&mut huge, MAX_PACKET_LENGTH + 1);
+        assert!(matches!(
+            read_packet(&mut &huge[..]).await,
+            Err(PacketError::IncorrectLength(_))
+        ));
+
+        let mut string = Vec::new();
+        write_varint(&mut string, 10);
+        string.extend_from_slice(b"short");
+        assert!(matches!(
+            PacketReader::new(&string).string(),
+            Err(PacketError::TooShort)
+        ));
+    }
+}
@@ -1,25 +1,42 @@
-use std::time::Duration;
+use std::{io, path::PathBuf, process::ExitStatus, sync::Arc, time::Duration};
 
 use log::{debug, error, info, warn};
-use minecraft_client_rs::Client;
 use serde::Serialize;
 use tokio::{
     sync::{
         mpsc::{channel, Receiver, Sender},
         oneshot,
     },
+    task::{spawn_blocking, JoinHandle},
     time::{sleep, timeout_at, Instant},
 };
 
-use crate::error::ProxyResponseError;
+use crate::{
+    backup::{archive::create_archive, restore::RestoreStage, BackupSettings},
+    error::{BackupError, ProxyResponseError},
+};
 
-use super::online_poller::OnlinePoller;
+use super::{
+    log_buffer::LogBuffer,
+    log_parser::{parse_line, GameEvent},
+    metrics::{Metrics, ServerState},
+    process::{ProcessInfo, ProcessState},
+    rcon::RconClient,
+    status::{ServerSnapshot, StatusProber},
+    temp_bans::{console_targets, TempBans},
+    tick_health::{TickMonitor, TickSample, TickSettings},
+    wake_listener::WakeListener,
+};
 
-#[derive(Debug)]
+/// Serialized as `{"action": "Ban", "params": {...}}` for the audit log.
+#[derive(Debug, Serialize)]
+#[serde(tag = "action", content = "params")]
 pub enum ProxyMessage {
     Ban {
         nickname: String,
         reason: Option<String>,
+        /// Seconds, the ban is permanent without it.
+        duration: Option<u64>,
     },
     Pardon {
         nickname: String,
@@ -40,14 +57,38 @@ pub enum ProxyMessage {
     DeOp {
         nickname: String,
     },
+    Raw {
+        command: String,
+    },
+    Backup,
+    Restore {
+        name: String,
+    },
     Ping,
 }
 
 #[derive(Debug, Serialize)]
 pub enum ProxyResponse {
     NotReady,
-    Ok { response: String },
-    Err { error: String },
+    Sleeping,
+    Ok {
+        response: String,
+    },
+    Status {
+        snapshot: ServerSnapshot,
+        process: ProcessInfo,
+        ticks: Vec<TickSample>,
+    },
+    Err {
+        error: String,
+    },
+}
+
+pub struct ProxySettings {
+    pub idle_timeout: Duration,
+    pub backups: BackupSettings,
+    pub tick_health: TickSettings,
+    pub temp_bans: Arc<TempBans>,
 }
 
 enum ServerStatus {
@@ -57,40 +98,98 @@ enum ServerStatus {
 }
 
 pub struct ProxyService {
-    online_poller: OnlinePoller,
+    status_prober: Box<dyn StatusProber>,
+    rcon: RconClient,
     status: ServerStatus,
     idle_timeout: Duration,
     rx: Receiver<(ProxyMessage, oneshot::Sender<ProxyResponse>)>,
-    current_online: u32,
+    snapshot: ServerSnapshot,
     last_request_time: Instant,
+    backups: BackupSettings,
+    backup_task: Option<JoinHandle<Result<PathBuf, io::Error>>>,
+    last_schedule_check: i64,
+    restore: Option<String>,
+    process: ProcessState,
+    logs: LogBuffer,
+    metrics: Metrics,
+    ticks: TickMonitor,
+    temp_bans: Arc<TempBans>,
 }
 
 impl ProxyService {
     pub fn new(
-        online_poller: OnlinePoller,
-        idle_timeout: Duration,
+        status_prober: Box<dyn StatusProber>,
+        rcon: RconClient,
+        settings: ProxySettings,
+        process: ProcessState,
+        logs: LogBuffer,
+        metrics: Metrics,
     ) -> (Self, Sender<(ProxyMessage, oneshot::Sender<ProxyResponse>)>) {
         let start_time = Instant::now();
         let status = ServerStatus::Starting(start_time);
         let (tx, rx) = channel(16);
         (
             Self {
-                online_poller,
+                status_prober,
+                rcon,
                 status,
-                idle_timeout,
+                idle_timeout: settings.idle_timeout,
                 rx,
-                current_online: 0,
+                snapshot: ServerSnapshot::default(),
                 last_request_time: start_time,
+                backups: settings.backups,
+                backup_task: None,
+                last_schedule_check: 0,
+                restore: None,
+                process,
+                logs,
+                metrics,
+                ticks: TickMonitor::new(settings.tick_health),
+                temp_bans: settings.temp_bans,
             },
             tx,
         )
     }
 
-    pub async fn run(mut self) {
-        match self.do_run().await {
-            Ok(_r) => {}
-            Err(e) => error!("Proxy service encountered an error: {}", &e),
+    /// Serves the web layer until the MC server should be stopped, or until it exits by itself.
+    pub async fn run(mut self, mut exited: oneshot::Receiver<ExitStatus>) -> Self {
+        self.status = ServerStatus::Starting(Instant::now());
+        self.snapshot = ServerSnapshot::default();
+        self.ticks.reset();
+        self.metrics.set_tick(None);
+        let exited = tokio::select! {
+            result = self.do_run() => {
+                match result {
+                    Ok(_r) => {}
+                    Err(e) => error!("Proxy service encountered an error: {}", &e),
+                };
+                false
+            }
+            status = &mut exited => {
+                match status {
+                    Ok(status) => warn!("MC server exited unexpectedly: {}", &status),
+                    Err(_e) => warn!("Lost track of MC server process"),
+                }
+                true
+            }
         };
+        if matches!(&self.status, ServerStatus::Starting(_)) {
+            let progress = &self.backups.restore_progress;
+            if let RestoreStage::Starting { name } = progress.get() {
+                let error = "MC server stopped before it was ready".to_owned();
+                progress.set(RestoreStage::Failed { name, error });
+            }
+        }
+        // Don't let the server write into the world while it's being archived
+        if let Some(task) = self.backup_task.take() {
+            info!("Waiting for the backup to finish...");
+            self.finish_backup(task).await;
+        }
+        self.metrics
+            .set_state(ServerState::Stopped, 0, self.snapshot.max_players, None);
+        if exited {
+            return self;
+        }
         info!("Sending shut down command to MC server...");
         for _ in 0..3 {
             if (self.shutdown().await).is_ok() {
@@ -99,37 +198,121 @@ impl ProxyService {
             error!("Error while shutting down MC server, attempting again in 5 seconds...");
             sleep(Duration::from_secs(5)).await;
         }
+        self
+    }
+
+    /// Keeps answering the web layer while the MC server is down,
+    /// until some player tries to join or a restore is requested.
+    pub async fn hibernate(
+        &mut self,
+        wake_listener: &WakeListener,
+    ) -> Result<(), ProxyResponseError> {
+        info!("Hibernating...");
+        self.snapshot = ServerSnapshot::default();
+        self.metrics.set_state(ServerState::Sleeping, 0, 0, None);
+        let wake_up = wake_listener.wait_for_player();
+        tokio::pin!(wake_up);
+        loop {
+            tokio::select! {
+                result = &mut wake_up => {
+                    let nickname = result?;
+                    info!("Waking up, because <{}> wants to play", &nickname);
+                    return Ok(());
+                }
+                val = self.rx.recv() => {
+                    let (message, rx) = match val {
+                        Some((message, rx)) => (message, rx),
+                        None => return Err(ProxyResponseError::IncomingChannelClosed),
+                    };
+                    debug!("Received new message while hibernating: {:?}", &message);
+                    match message {
+                        ProxyMessage::Restore { name } => match self.request_restore(name) {
+                            Ok(response) => {
+                                rx.send(ProxyResponse::Ok { response })?;
+                                return Ok(());
+                            }
+                            Err(err) => rx.send(ProxyResponse::Err {
+                                error: err.to_string(),
+                            })?,
+                        },
+                        _ => rx.send(ProxyResponse::Sleeping)?,
+                    }
+                }
+            }
+        }
     }
 
     async fn do_run(&mut self) -> Result<(), ProxyResponseError> {
         info!("Start polling...");
         let frequency = Duration::from_secs(5);
         let mut deadline = Instant::now() + frequency;
+        let mut output = self.logs.subscribe();
         loop {
+            self.update_metrics();
             debug!("Polling new message for 5 seconds...");
-            match timeout_at(deadline, self.rx.recv()).await {
+            let received = tokio::select! {
+                received = timeout_at(deadline, self.rx.recv()) => received,
+                line = output.recv() => {
+                    if let Ok(line) = line {
+                        self.handle_output(&line);
+                    }
+                    continue;
+                }
+            };
+            match received {
                 Ok(val) => {
                     let (message, rx) = match val {
                         Some((message, rx)) => (message, rx),
                         None => return Err(ProxyResponseError::IncomingChannelClosed),
                     };
                     info!("Received new message: {:?}", &message);
-                    if matches!(&self.status, ServerStatus::Starting(_)) {
+                    // Restore is allowed while the server is starting, in case the world is broken
+                    if matches!(&self.status, ServerStatus::Starting(_))
+                        && !matches!(&message, ProxyMessage::Restore { .. })
+                    {
                         warn!("Server is not ready yet...");
                         rx.send(ProxyResponse::NotReady)?;
                         continue;
                     }
                     let response = match message {
-                        ProxyMessage::Ban { nickname, reason } => self.ban(nickname, reason),
-                        ProxyMessage::Pardon { nickname } => self.pardon(nickname),
-                        ProxyMessage::Kick { nickname, reason } => self.kick(nickname, reason),
-                        ProxyMessage::WhitelistAdd { nickname } => self.whitelist_add(nickname),
+                        ProxyMessage::Ban {
+                            nickname,
+                            reason,
+                            duration,
+                        } => self.ban(nickname, reason, duration).await,
+                        ProxyMessage::Pardon { nickname } => self.pardon(nickname).await,
+                        ProxyMessage::Kick { nickname, reason } => {
+                            self.kick(nickname, reason).await
+                        }
+                        ProxyMessage::WhitelistAdd { nickname } => {
+                            self.whitelist_add(nickname).await
+                        }
                         ProxyMessage::WhitelistRemove { nickname } => {
-                            self.whitelist_remove(nickname)
+                            self.whitelist_remove(nickname).await
+                        }
+                        ProxyMessage::OpAdd { nickname } => self.op_add(nickname).await,
+                        ProxyMessage::DeOp { nickname } => self.de_op(nickname).await,
+                        ProxyMessage::Raw { command } => self.raw(command).await,
+                        ProxyMessage::Backup => self.backup().await,
+                        ProxyMessage::Restore { name } => match self.request_restore(name) {
+                            Ok(response) => {
+                                // Stop the server, the world will be restored by the supervisor
+                                rx.send(ProxyResponse::Ok { response })?;
+                                return Ok(());
+                            }
+                            Err(err) => Err(err),
+                        },
+                        ProxyMessage::Ping => {
+                            let snapshot = self.snapshot.clone();
+                            let process = self.process.get();
+                            let ticks = self.ticks.samples();
+                            rx.send(ProxyResponse::Status {
+                                snapshot,
+                                process,
+                                ticks,
+                            })?;
+                            continue;
                         }
-                        ProxyMessage::OpAdd { nickname } => self.op_add(nickname),
-                        ProxyMessage::DeOp { nickname } => self.de_op(nickname),
-                        ProxyMessage::Ping => Ok(self.current_online.to_string()),
                     };
 
                     match response {
@@ -138,30 +321,29 @@ impl ProxyService {
                             ProxyResponseError::Spam => rx.send(ProxyResponse::Err {
                                 error: err.to_string(),
                             })?,
-                            ProxyResponseError::McServerConnect(_) => {
-                                rx.send(ProxyResponse::Err {
-                                    error: err.to_string(),
-                                })?
-                            }
-                            ProxyResponseError::McServerAuth(_) => rx.send(ProxyResponse::Err {
+                            ProxyResponseError::Rcon(_) => rx.send(ProxyResponse::Err {
+                                error: err.to_string(),
+                            })?,
+                            ProxyResponseError::Backup(_) => rx.send(ProxyResponse::Err {
                                 error: err.to_string(),
                             })?,
-                            ProxyResponseError::McServerCommand(_) => {
-                                rx.send(ProxyResponse::Err {
-                                    error: err.to_string(),
-                                })?
-                            }
                             _ => return Err(err),
                         },
                     }
                 }
                 Err(_e) => {
                     deadline = Instant::now() + frequency;
+                    if !matches!(&self.status, ServerStatus::Starting(_)) {
+                        self.check_bans().await;
+                        self.check_backup().await;
+                        self.check_tick_health().await;
+                    }
                     debug!("Timed out, polling MC server...");
-                    let polling_result = self.online_poller.current_online().await;
-                    self.current_online = match polling_result {
-                        Ok(number_of_players) => number_of_players,
+                    let polling_result = self.status_prober.current_status().await;
+                    self.snapshot = match polling_result {
+                        Ok(snapshot) => snapshot,
                         Err(_) => {
+                            self.metrics.record_poll_failure();
                             match &self.status {
                                 ServerStatus::Starting(time) => {
                                     if time.elapsed() > self.idle_timeout {
@@ -183,17 +365,15 @@ impl ProxyService {
                         }
                     };
 
-                    debug!("Current online: {}", &self.current_online);
-                    match (&self.status, self.current_online) {
+                    debug!("Current online: {}", &self.snapshot.online);
+                    match (&self.status, self.snapshot.online) {
                         (ServerStatus::Starting(_), number) => {
-                            self.status = {
-                                info!("MC server is ready...");
-                                if number == 0 {
-                                    ServerStatus::Idle(Instant::now())
-                                } else {
-                                    ServerStatus::Busy
-                                }
-                            }
+                            info!("MC server is ready...");
+                            self.mark_ready(if number == 0 {
+                                ServerStatus::Idle(Instant::now())
+                            } else {
+                                ServerStatus::Busy
+                            });
                         }
                         (ServerStatus::Busy, 0) => self.status = ServerStatus::Idle(Instant::now()),
                         (ServerStatus::Idle(time), 0) => {
@@ -215,30 +395,75 @@ impl ProxyService {
         }
     }
 
+    fn update_metrics(&self) {
+        let (state, idle_since) = match &self.status {
+            ServerStatus::Starting(_) => (ServerState::Starting, None),
+            ServerStatus::Idle(time) => (ServerState::Idle, Some(time.into_std())),
+            ServerStatus::Busy => (ServerState::Busy, None),
+        };
+        self.metrics.set_state(
+            state,
+            self.snapshot.online,
+            self.snapshot.max_players,
+            idle_since,
+        );
+    }
+
+    fn handle_output(&mut self, line: &str) {
+        let event = match parse_line(line) {
+            Some(event) => event,
+            None => return,
+        };
+        debug!("Game event: {:?}", &event);
+        if let (ServerStatus::Starting(_), GameEvent::ServerStarted { seconds }) =
+            (&self.status, &event)
+        {
+            info!("MC server is ready, started in {} seconds...", seconds);
+            self.mark_ready(ServerStatus::Idle(Instant::now()));
+        }
+    }
+
+    /// A restore is done only when the server has started with the restored world.
+    fn mark_ready(&mut self, status: ServerStatus) {
+        self.status = status;
+        let progress = &self.backups.restore_progress;
+        if let RestoreStage::Starting { name } = progress.get() {
+            progress.set(RestoreStage::Done { name });
+        }
+    }
+
     async fn shutdown(&mut self) -> Result<(), ProxyResponseError> {
         let command = "/stop".to_string();
-        let _ = self.send_command(command, false)?;
+        let _ = self.send_command(command, false).await?;
         Ok(())
     }
 
-    fn ban(
+    async fn ban(
         &mut self,
         nickname: String,
         reason: Option<String>,
+        duration: Option<u64>,
     ) -> Result<String, ProxyResponseError> {
         let command = match reason {
             Some(reason) => format!("/ban {} {}", nickname, reason),
             None => format!("/ban {}", nickname),
         };
-        self.send_command(command, true)
+        let response = self.send_command(command, true).await?;
+        let now = time::OffsetDateTime::now_utc().unix_timestamp();
+        let expires =
+            duration.map(|seconds| now.saturating_add(i64::try_from(seconds).unwrap_or(i64::MAX)));
+        self.temp_bans.set(&nickname, expires);
+        Ok(response)
     }
 
-    fn pardon(&mut self, nickname: String) -> Result<String, ProxyResponseError> {
+    async fn pardon(&mut self, nickname: String) -> Result<String, ProxyResponseError> {
         let command = format!("/pardon {}", nickname);
-        self.send_command(command, true)
+        let response = self.send_command(command, true).await?;
+        self.temp_bans.remove(&nickname);
+        Ok(response)
     }
 
-    fn kick(
+    async fn kick(
         &mut self,
         nickname: String,
         reason: Option<String>,
@@ -247,54 +472,191 @@ impl ProxyService {
             Some(reason) => format!("/kick {} {}", nickname, reason),
             None => format!("/kick {}", nickname),
         };
-        self.send_command(command, true)
+        self.send_command(command, true).await
     }
 
-    fn whitelist_add(&mut self, nickname: String) -> Result<String, ProxyResponseError> {
+    async fn whitelist_add(&mut self, nickname: String) -> Result<String, ProxyResponseError> {
         let command = format!("/whitelist add {}", nickname);
-        self.send_command(command, true)
+        self.send_command(command, true).await
     }
 
-    fn whitelist_remove(&mut self, nickname: String) -> Result<String, ProxyResponseError> {
+    async fn whitelist_remove(&mut self, nickname: String) -> Result<String, ProxyResponseError> {
         let command = format!("/whitelist remove {}", nickname);
-        self.send_command(command, true)
+        self.send_command(command, true).await
     }
 
-    fn op_add(&mut self, nickname: String) -> Result<String, ProxyResponseError> {
+    async fn op_add(&mut self, nickname: String) -> Result<String, ProxyResponseError> {
         let command = format!("/op {}", nickname);
-        self.send_command(command, true)
+        self.send_command(command, true).await
     }
 
-    fn de_op(&mut self, nickname: String) -> Result<String, ProxyResponseError> {
+    async fn de_op(&mut self, nickname: String) -> Result<String, ProxyResponseError> {
         let command = format!("/deop {}", nickname);
-        self.send_command(command, true)
+        self.send_command(command, true).await
     }
 
-    fn send_command(
-        &mut self,
-        command: String,
-        protect_from_spam: bool,
-    ) -> Result<String, ProxyResponseError> {
-        if protect_from_spam && self.last_request_time.elapsed() < Duration::from_secs(5) {
-            return Err(ProxyResponseError::Spam);
+    async fn raw(&mut self, command: String) -> Result<String, ProxyResponseError> {
+        // Console is interactive, so commands can follow each other quickly
+        let response = self.send_command(command.clone(), false).await?;
+        // Same as with the ban buttons: bans without a duration are permanent
+        for nickname in console_targets(&command) {
+            self.temp_bans.remove(&nickname);
+        }
+        Ok(response)
+    }
+
+    async fn backup(&mut self) -> Result<String, ProxyResponseError> {
+        if self.backup_task.is_some() {
+            return Err(BackupError::InProgress.into());
+        }
+
+        info!("Starting backup...");
+        self.send_command("/save-off".to_string(), false).await?;
+        if let Err(e) = self
+            .send_command("/save-all flush".to_string(), false)
+            .await
+        {
+            // Never leave the server with saving turned off
+            let _ = self.send_command("/save-on".to_string(), false).await;
+            return Err(e);
+        }
+
+        let world = self.backups.world.clone();
+        let directory = self.backups.directory.clone();
+        self.backup_task = Some(spawn_blocking(move || create_archive(&world, &directory)));
+        Ok("Backup started".to_string())
+    }
+
+    pub fn restore_requested(&self) -> bool {
+        self.restore.is_some()
+    }
+
+    pub fn take_restore(&mut self) -> Option<String> {
+        self.restore.take()
+    }
+
+    fn request_restore(&mut self, name: String) -> Result<String, ProxyResponseError> {
+        if self.backup_task.is_some() {
+            return Err(BackupError::InProgress.into());
+        }
+        if self.backups.restore_progress.get().in_progress() {
+            return Err(BackupError::RestoreInProgress.into());
+        }
+        info!("Restoring <{}>, stopping the server...", &name);
+        self.backups
+            .restore_progress
+            .set(RestoreStage::Stopping { name: name.clone() });
+        self.restore = Some(name);
+        Ok("Restore started".to_string())
+    }
+
+    /// Pardons players whose temporary bans have run out.
+    /// Expiry is persisted, so bans that ran out while the server was down are lifted once it's up.
+    async fn check_bans(&mut self) {
+        let now = time::OffsetDateTime::now_utc().unix_timestamp();
+        for nickname in self.temp_bans.expired(now) {
+            let command = format!("/pardon {}", &nickname);
+            match self.send_command(command, false).await {
+                Ok(_r) => {
+                    info!("Temporary ban of <{}> has run out", &nickname);
+                    self.temp_bans.remove(&nickname);
+                }
+                Err(e) => {
+                    // Attempted again on the next poll
+                    warn!("Couldn't pardon <{}>: {}", &nickname, &e);
+                    return;
+                }
+            }
         }
-        let mut client = match Client::new("127.0.0.1:25567".to_string()) {
-            Ok(r) => r,
-            Err(e) => return Err(ProxyResponseError::McServerConnect(e)),
+    }
+
+    /// Finishes the backup if archiving is done, and starts a new one if it's time to.
+    async fn check_backup(&mut self) {
+        if matches!(&self.backup_task, Some(task) if task.is_finished()) {
+            if let Some(task) = self.backup_task.take() {
+                self.finish_backup(task).await;
+            }
+        }
+
+        let now = time::OffsetDateTime::now_utc();
+        let minute = now.unix_timestamp() / 60;
+        if minute == self.last_schedule_check {
+            return;
+        }
+        self.last_schedule_check = minute;
+        let scheduled = match &self.backups.schedule {
+            Some(schedule) => schedule.matches(now),
+            None => false,
         };
+        if scheduled && self.backup_task.is_none() {
+            match self.backup().await {
+                Ok(_r) => {}
+                Err(e) => error!("Couldn't start scheduled backup: {}", &e),
+            }
+        }
+    }
 
-        let message = match client.authenticate("M1n3cr@ft".to_string()) {
-            Ok(r) => r,
-            Err(e) => return Err(ProxyResponseError::McServerAuth(e)),
+    async fn check_tick_health(&mut self) {
+        let command = match self.ticks.due() {
+            Some(command) => command,
+            None => return,
         };
-        info!("Message: {:?}", message);
+        match self.send_command(command, false).await {
+            Ok(response) => {
+                if let Some(sample) = self.ticks.record(&response) {
+                    debug!(
+                        "Tick health: {:.1} TPS, {:.1} MSPT",
+                        sample.tps, sample.mspt
+                    );
+                    self.metrics.set_tick(Some(sample));
+                }
+            }
+            Err(e) => debug!("Couldn't check tick health: {}", &e),
+        }
+    }
 
-        let message = match client.send_command(command) {
-            Ok(r) => r,
-            Err(e) => return Err(ProxyResponseError::McServerCommand(e)),
+    async fn finish_backup(&mut self, task: JoinHandle<Result<PathBuf, io::Error>>) {
+        let result = task.await;
+        match self.send_command("/save-on".to_string(), false).await {
+            Ok(_r) => {}
+            Err(e) => error!("Couldn't turn saving back on: {}", &e),
+        }
+
+        let path = match result {
+            Ok(Ok(path)) => path,
+            Ok(Err(e)) => {
+                error!("Couldn't back up the world: {}", BackupError::from(e));
+                return;
+            }
+            Err(e) => {
+                error!("Couldn't back up the world: {}", BackupError::from(e));
+                return;
+            }
         };
+        info!("World is saved into <{}>", path.to_string_lossy());
+        match self.backups.retention.prune(&self.backups.directory) {
+            Ok(_r) => {}
+            Err(e) => error!("Couldn't delete old backups: {}", &e),
+        }
+    }
 
-        self.last_request_time = Instant::now();
-        Ok(message.body)
+    async fn send_command(
+        &mut self,
+        command: String,
+        protect_from_spam: bool,
+    ) -> Result<String, ProxyResponseError> {
+        if protect_from_spam && self.last_request_time.elapsed() < Duration::from_secs(5) {
+            return Err(ProxyResponseError::Spam);
+        }
+        let started = Instant::now();
+        let response = self.rcon.send_command(&command).await;
+        self.metrics
+            .record_rcon(started.elapsed(), response.is_ok());
+        let response = response?;
+        // Internal commands, like status polls and backups, shouldn't lock users out
+        if protect_from_spam {
+            self.last_request_time = Instant::now();
+        }
+        Ok(response)
     }
 }
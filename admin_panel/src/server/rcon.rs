@@ -0,0 +1,449 @@
+use std::{io, net::SocketAddr, time::Duration};
+
+use log::{debug, info, warn};
+use thiserror::Error;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time::{sleep, timeout},
+};
+
+const SERVERDATA_AUTH: i32 = 3;
+const SERVERDATA_EXECCOMMAND: i32 = 2;
+const SERVERDATA_RESPONSE_VALUE: i32 = 0;
+/// Minecraft splits responses into several packets, each of them carrying at most that many bytes.
+const MAX_RESPONSE_CHUNK: usize = 4096;
+/// Request id + packet type + two null bytes.
+const PACKET_OVERHEAD: i32 = 10;
+const MAX_PACKET_LENGTH: i32 = MAX_RESPONSE_CHUNK as i32 * 4 + PACKET_OVERHEAD;
+
+#[derive(Error, Debug)]
+pub enum RconError {
+    #[error("Generic IO error: {0}")]
+    IO(#[from] io::Error),
+    #[error("Read timeout")]
+    ReadTimeout,
+    #[error("Wrong RCON password")]
+    Auth,
+    #[error("Incorrect packet length: {0}")]
+    IncorrectLength(i32),
+}
+
+struct Packet {
+    id: i32,
+    packet_type: i32,
+    body: Vec<u8>,
+}
+
+enum ExecuteError {
+    /// The command didn't reach the server, so it's safe to send it again.
+    NotSent(RconError),
+    /// The server may have already run the command.
+    MaybeRun(RconError),
+}
+
+/// RCON client that keeps a single authenticated connection to the MC server,
+/// and transparently reconnects when the connection is lost.
+pub struct RconClient {
+    address: SocketAddr,
+    password: String,
+    connection: Option<TcpStream>,
+    next_id: i32,
+    read_timeout: Duration,
+    max_attempts: u32,
+    initial_backoff: Duration,
+}
+
+impl RconClient {
+    pub fn new(address: SocketAddr, password: String) -> Self {
+        Self {
+            address,
+            password,
+            connection: None,
+            next_id: 1,
+            read_timeout: Duration::from_secs(5),
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(500),
+        }
+    }
+
+    /// A command is sent again only if it didn't reach the server, so that e.g. a slow `/ban` doesn't run twice.
+    pub async fn send_command(&mut self, command: &str) -> Result<String, RconError> {
+        if let Some(stream) = &self.connection {
+            if is_closed(stream).await {
+                // Most likely the server was restarted since the last command
+                warn!("RCON connection is closed. Reconnecting...");
+                self.connection = None;
+            }
+        }
+        if self.connection.is_some() {
+            match self.execute(command).await {
+                Ok(response) => return Ok(response),
+                Err(ExecuteError::NotSent(e)) => {
+                    warn!("RCON connection is lost: {}. Reconnecting...", &e);
+                    self.connection = None;
+                }
+                Err(ExecuteError::MaybeRun(e)) => {
+                    self.connection = None;
+                    return Err(e);
+                }
+            }
+        }
+
+        self.connect().await?;
+        match self.execute(command).await {
+            Ok(response) => Ok(response),
+            Err(ExecuteError::NotSent(e)) | Err(ExecuteError::MaybeRun(e)) => {
+                self.connection = None;
+                Err(e)
+            }
+        }
+    }
+
+    async fn connect(&mut self) -> Result<(), RconError> {
+        let mut backoff = self.initial_backoff;
+        let mut attempt = 1;
+        loop {
+            match self.try_connect().await {
+                Ok(stream) => {
+                    info!("Connected to RCON at {}", &self.address);
+                    self.connection = Some(stream);
+                    return Ok(());
+                }
+                // Retrying won't fix the password
+                Err(RconError::Auth) => return Err(RconError::Auth),
+                Err(e) if attempt >= self.max_attempts => return Err(e),
+                Err(e) => {
+                    warn!(
+                        "Couldn't connect to RCON: {}. Retrying in {} ms...",
+                        &e,
+                        backoff.as_millis()
+                    );
+                    sleep(backoff).await;
+                    backoff *= 2;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    async fn try_connect(&mut self) -> Result<TcpStream, RconError> {
+        debug!("Connecting to RCON at {}...", &self.address);
+        let mut stream = match timeout(self.read_timeout, TcpStream::connect(self.address)).await {
+            Ok(r) => r?,
+            Err(_e) => return Err(RconError::ReadTimeout),
+        };
+
+        let id = self.next_id();
+        write_packet(&mut stream, id, SERVERDATA_AUTH, self.password.as_bytes()).await?;
+        loop {
+            let packet = read_packet(&mut stream, self.read_timeout).await?;
+            // Some implementations send an empty response value before the auth response
+            if packet.packet_type == SERVERDATA_RESPONSE_VALUE {
+                continue;
+            }
+            if packet.id == -1 {
+                return Err(RconError::Auth);
+            }
+            if packet.id == id {
+                return Ok(stream);
+            }
+        }
+    }
+
+    async fn execute(&mut self, command: &str) -> Result<String, ExecuteError> {
+        let id = self.next_id();
+        let terminator_id = self.next_id();
+        let read_timeout = self.read_timeout;
+        let stream = match self.connection.as_mut() {
+            Some(stream) => stream,
+            None => {
+                return Err(ExecuteError::NotSent(RconError::IO(io::Error::new(
+                    io::ErrorKind::NotConnected,
+                    "RCON is not connected",
+                ))))
+            }
+        };
+
+        debug!("Sending RCON command #{}: {}", id, command);
+        write_packet(stream, id, SERVERDATA_EXECCOMMAND, command.as_bytes())
+            .await
+            .map_err(ExecuteError::NotSent)?;
+        let response = read_full_response(stream, id, terminator_id, read_timeout)
+            .await
+            .map_err(ExecuteError::MaybeRun)?;
+        // Multibyte characters can be split between packets, so the response is decoded as a whole
+        Ok(String::from_utf8_lossy(&response).into_owned())
+    }
+
+    fn next_id(&mut self) -> i32 {
+        let id = self.next_id;
+        self.next_id = if self.next_id == i32::MAX {
+            1
+        } else {
+            self.next_id + 1
+        };
+        id
+    }
+}
+
+/// Checks without waiting whether the server has closed the connection.
+async fn is_closed(stream: &TcpStream) -> bool {
+    let mut buffer = [0u8; 1];
+    tokio::select! {
+        biased;
+        result = stream.peek(&mut buffer) => matches!(result, Ok(0) | Err(_)),
+        _ = std::future::ready(()) => false,
+    }
+}
+
+async fn read_full_response(
+    stream: &mut TcpStream,
+    id: i32,
+    terminator_id: i32,
+    read_timeout: Duration,
+) -> Result<Vec<u8>, RconError> {
+    let mut response = read_response(stream, id, read_timeout).await?;
+    if response.len() < MAX_RESPONSE_CHUNK {
+        return Ok(response);
+    }
+
+    /*
+    The response didn't fit into a single packet. The server handles packets in order,
+    so by the time it answers to an empty packet, all the other parts of the response
+    will have been sent.
+     */
+    write_packet(stream, terminator_id, SERVERDATA_RESPONSE_VALUE, b"").await?;
+    loop {
+        let packet = read_packet(stream, read_timeout).await?;
+        if packet.id == terminator_id {
+            return Ok(response);
+        }
+        if packet.id == id {
+            response.extend_from_slice(&packet.body);
+        }
+    }
+}
+
+async fn read_response(
+    stream: &mut TcpStream,
+    id: i32,
+    read_timeout: Duration,
+) -> Result<Vec<u8>, RconError> {
+    loop {
+        let packet = read_packet(stream, read_timeout).await?;
+        if packet.id == id {
+            return Ok(packet.body);
+        }
+        // Leftovers from a command that timed out earlier
+        debug!("Skipping RCON packet #{}", packet.id);
+    }
+}
+
+async fn write_packet(
+    stream: &mut TcpStream,
+    id: i32,
+    packet_type: i32,
+    body: &[u8],
+) -> Result<(), RconError> {
+    let length = body.len() as i32 + PACKET_OVERHEAD;
+    let mut packet = Vec::with_capacity(length as usize + 4);
+    packet.extend_from_slice(&length.to_le_bytes());
+    packet.extend_from_slice(&id.to_le_bytes());
+    packet.extend_from_slice(&packet_type.to_le_bytes());
+    packet.extend_from_slice(body);
+    packet.extend_from_slice(&[0, 0]);
+    stream.write_all(&packet).await?;
+    Ok(())
+}
+
+async fn read_packet(stream: &mut TcpStream, read_timeout: Duration) -> Result<Packet, RconError> {
+    let length = match timeout(read_timeout, stream.read_i32_le()).await {
+        Ok(r) => r?,
+        Err(_e) => return Err(RconError::ReadTimeout),
+    };
+    if !(PACKET_OVERHEAD..=MAX_PACKET_LENGTH).contains(&length) {
+        return Err(RconError::IncorrectLength(length));
+    }
+
+    let mut buffer = vec![0u8; length as usize];
+    match timeout(read_timeout, stream.read_exact(&mut buffer)).await {
+        Ok(r) => r?,
+        Err(_e) => return Err(RconError::ReadTimeout),
+    };
+
+    let mut id = [0u8; 4];
+    id.clone_from_slice(&buffer[0..4]);
+    let mut packet_type = [0u8; 4];
+    packet_type.clone_from_slice(&buffer[4..8]);
+    Ok(Packet {
+        id: i32::from_le_bytes(id),
+        packet_type: i32::from_le_bytes(packet_type),
+        body: buffer[8..buffer.len() - 2].to_vec(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::Ipv4Addr,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// Minimal RCON server that mimics Minecraft's behaviour.
+    /// Closes the connection after `commands_per_connection` commands.
+    async fn fake_server(
+        password: &'static str,
+        commands_per_connection: usize,
+    ) -> (SocketAddr, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let counter = connections.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(async move {
+                    let mut commands = 0;
+                    while let Ok(packet) = read_packet(&mut stream, Duration::from_secs(5)).await {
+                        match packet.packet_type {
+                            SERVERDATA_AUTH => {
+                                let id = if packet.body == password.as_bytes() {
+                                    packet.id
+                                } else {
+                                    -1
+                                };
+                                write_packet(&mut stream, id, 2, b"").await.unwrap();
+                            }
+                            SERVERDATA_EXECCOMMAND => {
+                                let body = String::from_utf8(packet.body).unwrap();
+                                let response = match body.as_str() {
+                                    // Never answers, as if the server was busy
+                                    "sleep" => continue,
+                                    // Characters of two bytes, one of them is split between packets
+                                    "accents" => "a".to_string() + &"é".repeat(3000),
+                                    _ => match body.strip_prefix("echo ") {
+                                        Some(text) => text.to_string(),
+                                        None => "a".repeat(body.len() * 1000),
+                                    },
+                                };
+                                let bytes = response.as_bytes();
+                                for chunk in bytes.chunks(MAX_RESPONSE_CHUNK) {
+                                    write_packet(&mut stream, packet.id, 0, chunk)
+                                        .await
+                                        .unwrap();
+                                }
+                                commands += 1;
+                                if commands == commands_per_connection {
+                                    return;
+                                }
+                            }
+                            other => {
+                                let response = format!("Unknown request {:x}", other);
+                                write_packet(&mut stream, packet.id, 0, response.as_bytes())
+                                    .await
+                                    .unwrap();
+                            }
+                        }
+                    }
+                });
+            }
+        });
+        (address, connections)
+    }
+
+    #[tokio::test]
+    async fn executes_command() {
+        let (address, _) = fake_server("secret", usize::MAX).await;
+        let mut client = RconClient::new(address, "secret".to_string());
+        let response = client.send_command("echo hello").await.unwrap();
+        assert_eq!(response, "hello");
+    }
+
+    #[tokio::test]
+    async fn reassembles_multi_packet_response() {
+        let (address, _) = fake_server("secret", usize::MAX).await;
+        let mut client = RconClient::new(address, "secret".to_string());
+        // 9 000 bytes, split into 3 packets
+        let response = client.send_command("list user").await.unwrap();
+        assert_eq!(response.len(), 9 * 1000);
+        let response = client.send_command("list players").await.unwrap();
+        assert_eq!(response.len(), 12 * 1000);
+        let response = client.send_command("echo done").await.unwrap();
+        assert_eq!(response, "done");
+    }
+
+    #[tokio::test]
+    async fn decodes_characters_split_between_packets() {
+        let (address, _) = fake_server("secret", usize::MAX).await;
+        let mut client = RconClient::new(address, "secret".to_string());
+        let response = client.send_command("accents").await.unwrap();
+        assert_eq!(response, "a".to_string() + &"é".repeat(3000));
+    }
+
+    #[tokio::test]
+    async fn doesnt_resend_command_after_timeout() {
+        let (address, connections) = fake_server("secret", usize::MAX).await;
+        let mut client = RconClient::new(address, "secret".to_string());
+        client.read_timeout = Duration::from_millis(100);
+        let result = client.send_command("sleep").await;
+        assert!(matches!(result, Err(RconError::ReadTimeout)));
+        // Sending the command again would take a new connection
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+
+        let response = client.send_command("echo awake").await.unwrap();
+        assert_eq!(response, "awake");
+        assert_eq!(connections.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn reuses_connection() {
+        let (address, connections) = fake_server("secret", usize::MAX).await;
+        let mut client = RconClient::new(address, "secret".to_string());
+        for i in 0..5 {
+            let response = client.send_command(&format!("echo {}", i)).await.unwrap();
+            assert_eq!(response, i.to_string());
+        }
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn reconnects_after_connection_loss() {
+        let (address, connections) = fake_server("secret", 1).await;
+        let mut client = RconClient::new(address, "secret".to_string());
+        for i in 0..3 {
+            let response = client.send_command(&format!("echo {}", i)).await.unwrap();
+            assert_eq!(response, i.to_string());
+        }
+        assert_eq!(connections.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn rejects_wrong_password() {
+        let (address, connections) = fake_server("secret", usize::MAX).await;
+        let mut client = RconClient::new(address, "wrong".to_string());
+        let result = client.send_command("echo hello").await;
+        assert!(matches!(result, Err(RconError::Auth)));
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_attempts() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let address = listener.local_addr().unwrap();
+        drop(listener);
+
+        let mut client = RconClient::new(address, "secret".to_string());
+        client.initial_backoff = Duration::from_millis(10);
+        let result = client.send_command("echo hello").await;
+        assert!(matches!(result, Err(RconError::IO(_))));
+    }
+}
@@ -0,0 +1,31 @@
+use axum::async_trait;
+use serde::Serialize;
+use thiserror::Error;
+
+use super::{list_ping::PingError, online_poller::InnerError};
+
+#[derive(Error, Debug)]
+pub enum ProbeError {
+    #[error("Query error: {0}")]
+    Query(#[from] InnerError),
+    #[error("Server List Ping error: {0}")]
+    Ping(#[from] PingError),
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ServerSnapshot {
+    pub motd: String,
+    pub version: String,
+    pub map: String,
+    pub online: u32,
+    pub max_players: u32,
+    pub players: Vec<String>,
+    pub plugins: Vec<String>,
+    pub latency_ms: Option<u32>,
+}
+
+/// Checks whether the MC server is alive, and how many players are online.
+#[async_trait]
+pub trait StatusProber: Send + Sync {
+    async fn current_status(&self) -> Result<ServerSnapshot, ProbeError>;
+}
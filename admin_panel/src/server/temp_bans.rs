@@ -0,0 +1,177 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    str::FromStr,
+    sync::{Mutex, PoisonError},
+};
+
+use crate::{
+    env::commands,
+    error::{BanDurationParserError, StoreError},
+    store,
+};
+
+/// Length of a temporary ban, written as `30m`, `12h`, `7d` or combined like `1d12h`.
+/// Weeks (`w`) and seconds (`s`) are accepted as well.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BanDuration(u64);
+
+impl BanDuration {
+    pub fn seconds(&self) -> u64 {
+        self.0
+    }
+}
+
+impl FromStr for BanDuration {
+    type Err = BanDurationParserError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_lowercase();
+        let mut seconds: u64 = 0;
+        let mut number = String::new();
+        for c in s.chars() {
+            if c.is_ascii_digit() {
+                number.push(c);
+                continue;
+            }
+            let unit = match c {
+                's' => 1,
+                'm' => 60,
+                'h' => 60 * 60,
+                'd' => 24 * 60 * 60,
+                'w' => 7 * 24 * 60 * 60,
+                _ => return Err(BanDurationParserError::Parse(s)),
+            };
+            let value: u64 = number
+                .parse()
+                .map_err(|_| BanDurationParserError::Parse(s.clone()))?;
+            seconds = value
+                .checked_mul(unit)
+                .and_then(|value| seconds.checked_add(value))
+                .ok_or_else(|| BanDurationParserError::Parse(s.clone()))?;
+            number.clear();
+        }
+        // A trailing number without a unit is ambiguous
+        if !number.is_empty() || seconds == 0 {
+            return Err(BanDurationParserError::Parse(s));
+        }
+        Ok(Self(seconds))
+    }
+}
+
+/// Expiry of temporary bans, by lowercase nickname.
+/// The server itself only knows permanent bans, so the panel pardons players when the time is up.
+pub struct TempBans {
+    path: PathBuf,
+    /// Unix timestamp after which the player is pardoned.
+    bans: Mutex<HashMap<String, i64>>,
+}
+
+impl TempBans {
+    pub fn load(path: PathBuf) -> Result<Self, StoreError> {
+        let bans = store::load(&path)?;
+        Ok(Self {
+            path,
+            bans: Mutex::new(bans),
+        })
+    }
+
+    pub fn expires(&self, nickname: &str) -> Option<i64> {
+        let bans = self.bans.lock().unwrap_or_else(PoisonError::into_inner);
+        bans.get(&nickname.to_lowercase()).copied()
+    }
+
+    /// `None` makes the ban permanent, e.g. when a temporary ban is replaced by a permanent one.
+    pub fn set(&self, nickname: &str, expires: Option<i64>) {
+        let mut bans = self.bans.lock().unwrap_or_else(PoisonError::into_inner);
+        let nickname = nickname.to_lowercase();
+        let changed = match expires {
+            Some(expires) => bans.insert(nickname, expires) != Some(expires),
+            None => bans.remove(&nickname).is_some(),
+        };
+        if changed {
+            store::save_or_warn(&self.path, &*bans);
+        }
+    }
+
+    pub fn remove(&self, nickname: &str) {
+        self.set(nickname, None);
+    }
+
+    /// Nicknames whose bans have run out by `now`.
+    pub fn expired(&self, now: i64) -> Vec<String> {
+        let bans = self.bans.lock().unwrap_or_else(PoisonError::into_inner);
+        bans.iter()
+            .filter(|(_nickname, expires)| **expires <= now)
+            .map(|(nickname, _expires)| nickname.clone())
+            .collect()
+    }
+}
+
+/// Nicknames that the console command bans or pardons, also through `execute ... run`.
+pub fn console_targets(command: &str) -> Vec<String> {
+    commands(command)
+        .iter()
+        .filter_map(|command| {
+            let mut words = command.split_whitespace();
+            match (words.next(), words.next()) {
+                (Some("ban" | "pardon"), Some(nickname)) => Some(nickname.to_string()),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations() {
+        let seconds = |s: &str| s.parse::<BanDuration>().map(|d| d.seconds()).ok();
+        assert_eq!(seconds("30m"), Some(30 * 60));
+        assert_eq!(seconds("12H"), Some(12 * 60 * 60));
+        assert_eq!(seconds(" 1d12h "), Some(36 * 60 * 60));
+        assert_eq!(seconds("2w"), Some(14 * 24 * 60 * 60));
+        assert_eq!(seconds("90s"), Some(90));
+        assert_eq!(seconds(""), None);
+        assert_eq!(seconds("15"), None);
+        assert_eq!(seconds("0d"), None);
+        assert_eq!(seconds("h"), None);
+        assert_eq!(seconds("1y"), None);
+        assert_eq!(seconds("1d 2h"), None);
+        assert_eq!(seconds("99999999999999999999w"), None);
+    }
+
+    #[test]
+    fn finds_console_targets() {
+        assert_eq!(console_targets("/ban Steve griefing"), ["steve"]);
+        assert_eq!(console_targets("minecraft:pardon Alex"), ["alex"]);
+        assert_eq!(
+            console_targets("execute as @a run minecraft:ban Herobrine"),
+            ["herobrine"]
+        );
+        assert!(console_targets("/ban").is_empty());
+        assert!(console_targets("/ban-ip 192.0.2.1").is_empty());
+        assert!(console_targets("/kick Steve").is_empty());
+    }
+
+    #[test]
+    fn tracks_expiry() {
+        let path = std::env::temp_dir().join(format!("temp-bans-{}.json", std::process::id()));
+        let bans = TempBans::load(path.clone()).unwrap();
+        bans.set("Steve", Some(100));
+        bans.set("Alex", Some(300));
+        bans.set("Herobrine", Some(200));
+        bans.remove("herobrine");
+        assert_eq!(bans.expires("steve"), Some(100));
+        assert_eq!(bans.expires("Herobrine"), None);
+        assert_eq!(bans.expired(200), vec!["steve".to_string()]);
+
+        // Survives restarts
+        let reloaded = TempBans::load(path.clone()).unwrap();
+        assert_eq!(reloaded.expires("alex"), Some(300));
+        assert_eq!(reloaded.expires("herobrine"), None);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,248 @@
+use std::{collections::VecDeque, time::Duration};
+
+use log::{info, warn};
+use serde::Serialize;
+use tokio::time::Instant;
+
+/// Samples older than that are dropped.
+const MAX_SAMPLES: usize = 120;
+
+#[derive(Clone)]
+pub struct TickSettings {
+    /// Command that reports tick health, `None` disables sampling.
+    pub command: Option<String>,
+    pub interval: Duration,
+    /// TPS below this value is considered lag.
+    pub threshold: f32,
+    /// Number of consecutive samples below the threshold, before the alert is raised.
+    pub alert_after: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct TickSample {
+    pub time: i64,
+    pub tps: f32,
+    pub mspt: f32,
+}
+
+/// Keeps the recent tick health samples, and warns when the server keeps lagging.
+pub struct TickMonitor {
+    settings: TickSettings,
+    samples: VecDeque<TickSample>,
+    last_sample: Option<Instant>,
+    below_threshold: u8,
+    lagging: bool,
+    reported_parse_error: bool,
+}
+
+impl TickMonitor {
+    pub fn new(settings: TickSettings) -> Self {
+        Self {
+            settings,
+            samples: VecDeque::with_capacity(MAX_SAMPLES),
+            last_sample: None,
+            below_threshold: 0,
+            lagging: false,
+            reported_parse_error: false,
+        }
+    }
+
+    /// Returns the command to send, if it's time to take a new sample.
+    pub fn due(&mut self) -> Option<String> {
+        let command = self.settings.command.clone()?;
+        if matches!(self.last_sample, Some(time) if time.elapsed() < self.settings.interval) {
+            return None;
+        }
+        self.last_sample = Some(Instant::now());
+        Some(command)
+    }
+
+    pub fn samples(&self) -> Vec<TickSample> {
+        self.samples.iter().copied().collect()
+    }
+
+    /// Samples are kept only while the server is running.
+    pub fn reset(&mut self) {
+        self.samples.clear();
+        self.last_sample = None;
+        self.below_threshold = 0;
+        self.lagging = false;
+    }
+
+    pub fn record(&mut self, response: &str) -> Option<TickSample> {
+        let (tps, mspt) = match parse_tick_health(response) {
+            Some(r) => r,
+            None => {
+                if !self.reported_parse_error {
+                    warn!(
+                        "Couldn't get TPS from the response to <{}>: {}",
+                        self.settings.command.as_deref().unwrap_or_default(),
+                        response
+                    );
+                    self.reported_parse_error = true;
+                }
+                return None;
+            }
+        };
+        let sample = TickSample {
+            time: time::OffsetDateTime::now_utc().unix_timestamp(),
+            tps,
+            mspt,
+        };
+        if self.samples.len() == MAX_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+
+        if tps >= self.settings.threshold {
+            if self.lagging {
+                info!("TPS is back to {:.1}", tps);
+            }
+            self.below_threshold = 0;
+            self.lagging = false;
+            return Some(sample);
+        }
+        self.below_threshold = self.below_threshold.saturating_add(1);
+        if !self.lagging && self.below_threshold >= self.settings.alert_after {
+            warn!(
+                "Server is lagging: TPS has been below {:.1} for the last {} samples, now {:.1} TPS, {:.1} MSPT",
+                self.settings.threshold, self.below_threshold, tps, mspt
+            );
+            self.lagging = true;
+        }
+        Some(sample)
+    }
+}
+
+/// Understands the output of vanilla `/tick query`, `/forge tps` and `/spark tps`.
+/// Returns TPS and MSPT.
+pub fn parse_tick_health(response: &str) -> Option<(f32, f32)> {
+    let response = strip_formatting(response);
+
+    // Vanilla reports the target tick rate and the average time per tick
+    if let Some(mspt) = number_after(&response, "Average time per tick:") {
+        let target = number_after(&response, "Target tick rate:").unwrap_or(20.0);
+        return Some((tps_from_mspt(mspt, target), mspt));
+    }
+
+    // Forge lists every dimension first, and then `Overall: Mean tick time: 1.234 ms. Mean TPS: 20.000`
+    let overall = match response.split_once("Overall:") {
+        Some((_dimensions, overall)) => overall,
+        None => &response,
+    };
+    if let Some(mspt) = number_after(overall, "Mean tick time:") {
+        let tps = number_after(overall, "Mean TPS:").unwrap_or_else(|| tps_from_mspt(mspt, 20.0));
+        return Some((tps, mspt));
+    }
+
+    // Spark lists TPS and tick durations on the lines following their headers
+    if let Some(tps_line) = line_after(&response, "TPS from last") {
+        let tps = leading_number(tps_line.trim_start_matches(|c: char| !c.is_ascii_digit()))?;
+        let mspt = match line_after(&response, "Tick durations") {
+            // min/median/95%ile/max, median is the closest one to what the others report
+            Some(durations) => durations
+                .trim()
+                .split('/')
+                .nth(1)
+                .and_then(leading_number)
+                .unwrap_or_else(|| 1000.0 / tps),
+            None => 1000.0 / tps,
+        };
+        return Some((tps, mspt));
+    }
+    None
+}
+
+/// The server can't tick faster than its target rate, even if ticks take less time than that.
+fn tps_from_mspt(mspt: f32, target: f32) -> f32 {
+    if mspt <= 0.0 {
+        return target;
+    }
+    (1000.0 / mspt).min(target)
+}
+
+/// Removes `§` color codes.
+fn strip_formatting(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '§' {
+            chars.next();
+            continue;
+        }
+        result.push(c);
+    }
+    result
+}
+
+fn number_after(text: &str, label: &str) -> Option<f32> {
+    let (_before, rest) = text.split_once(label)?;
+    leading_number(rest.trim_start())
+}
+
+fn line_after<'a>(text: &'a str, header: &str) -> Option<&'a str> {
+    let mut lines = text.lines();
+    lines.find(|line| line.contains(header))?;
+    lines.find(|line| !line.trim().is_empty())
+}
+
+fn leading_number(text: &str) -> Option<f32> {
+    let text = text.trim_start_matches('*');
+    let end = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(text.len());
+    text[..end].parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_vanilla_tick_query() {
+        let response = "The game is running normally\nTarget tick rate: 20.0 per second.\nAverage time per tick: 12.5ms (Target: 50.0ms)\nPercentiles: P50: 11.0ms P95: 20.1ms P99: 31.7ms, sample: 100";
+        assert_eq!(parse_tick_health(response), Some((20.0, 12.5)));
+
+        let response =
+            "Target tick rate: 20.0 per second.\nAverage time per tick: 80.0ms (Target: 50.0ms)";
+        assert_eq!(parse_tick_health(response), Some((12.5, 80.0)));
+    }
+
+    #[test]
+    fn parses_forge_tps() {
+        let response = "Dim minecraft:overworld (minecraft:overworld): Mean tick time: 3.105 ms. Mean TPS: 20.000\nOverall: Mean tick time: 62.500 ms. Mean TPS: 16.000";
+        assert_eq!(parse_tick_health(response), Some((16.0, 62.5)));
+    }
+
+    #[test]
+    fn parses_spark_tps() {
+        let response = "§8[§e⚡§8] §7TPS from last 5s, 10s, 1m, 5m, 15m:\n§8[§e⚡§8]  §a*19.6§7, §a19.8§7, §a20.0§7, §a20.0§7, §a20.0\n§8[§e⚡§8] \n§8[§e⚡§8] §7Tick durations (min/med/95%ile/max ms) for last 10s, 1m:\n§8[§e⚡§8]  §a0.4§7/§a8.6§7/§a23.1§7/§a61.0§7;  §a0.3§7/§a7.9§7/§a20.4§7/§a98.2";
+        assert_eq!(parse_tick_health(response), Some((19.6, 8.6)));
+    }
+
+    #[test]
+    fn rejects_unknown_command() {
+        let response = "Unknown or incomplete command, see below for error\ntick query<--[HERE]";
+        assert_eq!(parse_tick_health(response), None);
+    }
+
+    #[test]
+    fn alerts_after_consecutive_samples() {
+        let mut monitor = TickMonitor::new(TickSettings {
+            command: Some("tick query".to_string()),
+            interval: Duration::from_secs(30),
+            threshold: 15.0,
+            alert_after: 2,
+        });
+        let lagging = "Target tick rate: 20.0 per second.\nAverage time per tick: 100.0ms";
+        let normal = "Target tick rate: 20.0 per second.\nAverage time per tick: 10.0ms";
+        monitor.record(lagging);
+        assert!(!monitor.lagging);
+        monitor.record(lagging);
+        assert!(monitor.lagging);
+        monitor.record(normal);
+        assert!(!monitor.lagging);
+        assert_eq!(monitor.samples().len(), 3);
+        assert_eq!(monitor.samples().last().map(|s| s.tps), Some(20.0));
+    }
+}
@@ -0,0 +1,132 @@
+use std::{net::Ipv4Addr, time::Duration};
+
+use log::{debug, info, warn};
+use serde_json::json;
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::mpsc::{channel, Sender},
+    time::timeout,
+};
+
+use crate::error::WakeListenerError;
+
+use super::protocol::{read_packet, write_packet, write_string, PacketError, PacketReader};
+
+/// Listens on the game port while the Minecraft server is down.
+/// Answers Server List Ping on behalf of the server, and reports
+/// the first player that tries to log in.
+pub struct WakeListener {
+    motd: String,
+    max_players: u8,
+    read_timeout: Duration,
+}
+
+impl WakeListener {
+    pub fn new(motd: String, max_players: u8) -> Self {
+        Self {
+            motd,
+            max_players,
+            read_timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// Returns the nickname of the player who woke up the server.
+    /// The port is released as soon as this function returns.
+    pub async fn wait_for_player(&self) -> Result<String, WakeListenerError> {
+        info!("Waiting for players on port 25565...");
+        let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, 25565)).await?;
+        let (tx, mut rx) = channel(1);
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => {
+                    let (stream, address) = match accepted {
+                        Ok(r) => r,
+                        Err(e) => {
+                            warn!("Couldn't accept connection: {}", &e);
+                            continue;
+                        }
+                    };
+                    debug!("New connection from {}", &address);
+                    let motd = self.motd.clone();
+                    let max_players = self.max_players;
+                    let read_timeout = self.read_timeout;
+                    let tx = tx.clone();
+                    tokio::spawn(async move {
+                        let connection = handle_connection(stream, motd, max_players, tx);
+                        match timeout(read_timeout, connection).await {
+                            Ok(Ok(_r)) => {}
+                            Ok(Err(e)) => debug!("Error while talking to {}: {}", &address, &e),
+                            Err(_) => debug!("Connection from {} timed out", &address),
+                        }
+                    });
+                }
+                Some(nickname) = rx.recv() => return Ok(nickname),
+            }
+        }
+    }
+}
+
+async fn handle_connection(
+    mut stream: TcpStream,
+    motd: String,
+    max_players: u8,
+    tx: Sender<String>,
+) -> Result<(), PacketError> {
+    let (id, payload) = read_packet(&mut stream).await?;
+    if id != 0x00 {
+        return Ok(());
+    }
+
+    let mut handshake = PacketReader::new(&payload);
+    let protocol = handshake.varint()?;
+    let _address = handshake.string()?;
+    let _port = handshake.u16()?;
+    let next_state = handshake.varint()?;
+
+    match next_state {
+        1 => send_status(&mut stream, protocol, &motd, max_players).await,
+        2 | 3 => {
+            let (_id, payload) = read_packet(&mut stream).await?;
+            let nickname = PacketReader::new(&payload).string()?;
+            info!("<{}> is trying to join, waking up the server...", &nickname);
+
+            let reason = json!({ "text": "Server is starting, please reconnect in a minute" });
+            let mut disconnect = Vec::new();
+            write_string(&mut disconnect, &reason.to_string());
+            write_packet(&mut stream, 0x00, &disconnect).await?;
+
+            let _ = tx.send(nickname).await;
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+async fn send_status(
+    stream: &mut TcpStream,
+    protocol: i32,
+    motd: &str,
+    max_players: u8,
+) -> Result<(), PacketError> {
+    let (id, _payload) = read_packet(stream).await?;
+    if id != 0x00 {
+        return Ok(());
+    }
+
+    // Echo client's protocol version, so that the client doesn't show the server as incompatible
+    let status = json!({
+        "version": { "name": "Sleeping", "protocol": protocol },
+        "players": { "max": max_players, "online": 0 },
+        "description": { "text": motd },
+    });
+    let mut response = Vec::new();
+    write_string(&mut response, &status.to_string());
+    write_packet(stream, 0x00, &response).await?;
+
+    let (id, payload) = read_packet(stream).await?;
+    if id != 0x01 {
+        return Ok(());
+    }
+    let payload = PacketReader::new(&payload).i64()?;
+    write_packet(stream, 0x01, &payload.to_be_bytes()).await
+}
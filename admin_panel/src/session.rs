@@ -0,0 +1,38 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Mutex, PoisonError},
+};
+
+use crate::{error::StoreError, store};
+
+/// Sessions that were closed before their tokens expired, by `jti`.
+/// Persisted, so that a restart doesn't bring them back when the signing secret is persistent.
+pub struct RevokedSessions {
+    path: PathBuf,
+    /// Unix timestamp after which the session can be forgotten.
+    sessions: Mutex<HashMap<String, i64>>,
+}
+
+impl RevokedSessions {
+    pub fn load(path: PathBuf) -> Result<Self, StoreError> {
+        let sessions = store::load(&path)?;
+        Ok(Self {
+            path,
+            sessions: Mutex::new(sessions),
+        })
+    }
+
+    pub fn is_revoked(&self, jti: &str) -> bool {
+        let sessions = self.sessions.lock().unwrap_or_else(PoisonError::into_inner);
+        sessions.contains_key(jti)
+    }
+
+    pub fn revoke(&self, jti: String, until: i64) {
+        let now = time::OffsetDateTime::now_utc().unix_timestamp();
+        let mut sessions = self.sessions.lock().unwrap_or_else(PoisonError::into_inner);
+        sessions.retain(|_jti, expires| *expires > now);
+        sessions.insert(jti, until);
+        store::save_or_warn(&self.path, &*sessions);
+    }
+}
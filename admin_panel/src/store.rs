@@ -0,0 +1,59 @@
+use std::{fs, io::ErrorKind, path::Path};
+
+use log::warn;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::error::StoreError;
+
+/// Reads the JSON file, or returns the default value if it wasn't saved yet.
+pub fn load<T: DeserializeOwned + Default>(path: &Path) -> Result<T, StoreError> {
+    let name = || path.display().to_string();
+    match fs::read_to_string(path) {
+        Ok(content) => serde_json::from_str(&content).map_err(|e| StoreError::Parse(name(), e)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(T::default()),
+        Err(e) => Err(StoreError::Read(name(), e)),
+    }
+}
+
+/// Writes the whole file at once, so that a crash doesn't leave it half written.
+pub fn save<T: Serialize + ?Sized>(path: &Path, value: &T) -> Result<(), StoreError> {
+    let name = || path.display().to_string();
+    let content =
+        serde_json::to_string_pretty(value).map_err(|e| StoreError::Write(name(), e.into()))?;
+    let temporary = path.with_extension("json.partial");
+    fs::write(&temporary, content).map_err(|e| StoreError::Write(name(), e))?;
+    fs::rename(&temporary, path).map_err(|e| StoreError::Write(name(), e))
+}
+
+/// For data that keeps working from memory, so that it's enough to warn if it can't be saved.
+pub fn save_or_warn<T: Serialize + ?Sized>(path: &Path, value: &T) {
+    if let Err(e) = save(path, value) {
+        warn!("{}", &e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        let path = std::env::temp_dir().join(format!("store-{}.json", std::process::id()));
+        let empty: HashMap<String, i64> = load(&path).unwrap();
+        assert!(empty.is_empty());
+
+        let values = HashMap::from([("steve".to_string(), 100)]);
+        save(&path, &values).unwrap();
+        assert!(!path.with_extension("json.partial").exists());
+        assert_eq!(load::<HashMap<String, i64>>(&path).unwrap(), values);
+
+        fs::write(&path, "{").unwrap();
+        assert!(matches!(
+            load::<HashMap<String, i64>>(&path),
+            Err(StoreError::Parse(..))
+        ));
+        fs::remove_file(&path).unwrap();
+    }
+}
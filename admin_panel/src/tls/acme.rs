@@ -0,0 +1,323 @@
+use std::{sync::Arc, thread::sleep, time::Duration};
+
+use log::{debug, info};
+use ring::{
+    digest,
+    rand::SystemRandom,
+    signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING},
+};
+use rustls::{Certificate, ClientConfig, OwnedTrustAnchor, RootCertStore};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+
+use super::{certificate::CertificatePair, AcmeSettings, Challenges};
+use crate::error::AcmeError;
+
+/// How many times the status of an authorization or an order is checked, before giving up.
+const POLL_ATTEMPTS: u32 = 30;
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Directory {
+    new_nonce: String,
+    new_account: String,
+    new_order: String,
+}
+
+#[derive(Deserialize)]
+struct Order {
+    status: String,
+    authorizations: Vec<String>,
+    finalize: String,
+    certificate: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Authorization {
+    status: String,
+    challenges: Vec<Challenge>,
+}
+
+#[derive(Deserialize)]
+struct Challenge {
+    #[serde(rename = "type")]
+    kind: String,
+    url: String,
+    token: String,
+}
+
+/// Minimal RFC 8555 client, that can only order a certificate for a single domain
+/// with the HTTP-01 challenge. It's blocking, so it must not be called on the async runtime.
+pub struct AcmeClient {
+    agent: ureq::Agent,
+    directory: Directory,
+    key: EcdsaKeyPair,
+    rng: SystemRandom,
+    /// URL of the account, known after the account is registered.
+    kid: Option<String>,
+    nonce: Option<String>,
+}
+
+impl AcmeClient {
+    /// `account_key` is a PKCS#8 document of a P-256 key.
+    pub fn new(settings: &AcmeSettings, account_key: &[u8]) -> Result<Self, AcmeError> {
+        let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, account_key)
+            .map_err(|e| AcmeError::Key(e.to_string()))?;
+        let agent = ureq::AgentBuilder::new()
+            .tls_config(Arc::new(client_config(settings.ca_cert.as_deref())?))
+            .timeout(Duration::from_secs(30))
+            .build();
+        let directory = agent
+            .get(&settings.directory)
+            .call()
+            .map_err(AcmeError::from)?
+            .into_json()
+            .map_err(AcmeError::Read)?;
+        Ok(Self {
+            agent,
+            directory,
+            key,
+            rng: SystemRandom::new(),
+            kid: None,
+            nonce: None,
+        })
+    }
+
+    pub fn generate_account_key() -> Result<Vec<u8>, AcmeError> {
+        let document =
+            EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &SystemRandom::new())
+                .map_err(|e| AcmeError::Key(e.to_string()))?;
+        Ok(document.as_ref().to_vec())
+    }
+
+    /// Registers the account, or finds the existing one for the same key.
+    pub fn register(&mut self, email: Option<&str>) -> Result<(), AcmeError> {
+        let mut payload = json!({ "termsOfServiceAgreed": true });
+        if let Some(email) = email {
+            payload["contact"] = json!([format!("mailto:{}", email)]);
+        }
+        let url = self.directory.new_account.clone();
+        let response = self.post(&url, Some(&payload))?;
+        let kid = response
+            .header("Location")
+            .ok_or(AcmeError::MissingHeader("Location"))?;
+        self.kid = Some(kid.to_string());
+        Ok(())
+    }
+
+    /// Answers the challenges through `challenges`, which are served on port 80.
+    pub fn order(
+        &mut self,
+        domain: &str,
+        challenges: &Challenges,
+    ) -> Result<CertificatePair, AcmeError> {
+        let url = self.directory.new_order.clone();
+        let payload = json!({ "identifiers": [{ "type": "dns", "value": domain }] });
+        let response = self.post(&url, Some(&payload))?;
+        let order_url = response
+            .header("Location")
+            .ok_or(AcmeError::MissingHeader("Location"))?
+            .to_string();
+        let order: Order = response.into_json().map_err(AcmeError::Read)?;
+
+        for authorization in order.authorizations.iter() {
+            self.authorize(authorization, challenges)?;
+        }
+
+        let mut params = rcgen::CertificateParams::new(vec![domain.to_string()]);
+        params.distinguished_name = rcgen::DistinguishedName::new();
+        let certificate = rcgen::Certificate::from_params(params)?;
+        let csr = base64::encode_config(
+            certificate.serialize_request_der()?,
+            base64::URL_SAFE_NO_PAD,
+        );
+        if order.status != "valid" {
+            self.post(&order.finalize, Some(&json!({ "csr": csr })))?;
+        }
+
+        let order: Order = self.poll(&order_url, |order: &Order| match order.status.as_str() {
+            "valid" => Ok(true),
+            "pending" | "ready" | "processing" => Ok(false),
+            status => Err(AcmeError::Status(format!("order is {}", status))),
+        })?;
+        let certificate_url = order
+            .certificate
+            .ok_or_else(|| AcmeError::Status("order doesn't have a certificate".to_string()))?;
+        let chain = self
+            .post(&certificate_url, None)?
+            .into_string()
+            .map_err(AcmeError::Read)?;
+        Ok(CertificatePair {
+            cert: chain.into_bytes(),
+            key: certificate.serialize_private_key_pem().into_bytes(),
+        })
+    }
+
+    fn authorize(&mut self, url: &str, challenges: &Challenges) -> Result<(), AcmeError> {
+        let authorization: Authorization =
+            self.post(url, None)?.into_json().map_err(AcmeError::Read)?;
+        if authorization.status == "valid" {
+            return Ok(());
+        }
+        let challenge = authorization
+            .challenges
+            .into_iter()
+            .find(|challenge| challenge.kind == "http-01")
+            .ok_or(AcmeError::NoHttpChallenge)?;
+
+        let key_authorization = format!("{}.{}", &challenge.token, self.thumbprint());
+        challenges.insert(challenge.token.clone(), key_authorization);
+        debug!("Answering ACME challenge {}", &challenge.token);
+        let result = self.post(&challenge.url, Some(&json!({}))).and_then(|_r| {
+            self.poll(url, |authorization: &Authorization| {
+                match authorization.status.as_str() {
+                    "valid" => Ok(true),
+                    "pending" | "processing" => Ok(false),
+                    status => Err(AcmeError::Status(format!("authorization is {}", status))),
+                }
+            })
+        });
+        challenges.remove(&challenge.token);
+        result.map(|_authorization| ())
+    }
+
+    /// Fetches the object until `done` says it's ready.
+    fn poll<T: DeserializeOwned>(
+        &mut self,
+        url: &str,
+        done: impl Fn(&T) -> Result<bool, AcmeError>,
+    ) -> Result<T, AcmeError> {
+        for _ in 0..POLL_ATTEMPTS {
+            let object: T = self.post(url, None)?.into_json().map_err(AcmeError::Read)?;
+            if done(&object)? {
+                return Ok(object);
+            }
+            sleep(POLL_INTERVAL);
+        }
+        Err(AcmeError::Status(format!("{} timed out", url)))
+    }
+
+    /// Sends a JWS signed request, `None` payload makes a POST-as-GET request.
+    fn post(&mut self, url: &str, payload: Option<&Value>) -> Result<ureq::Response, AcmeError> {
+        let mut retried = false;
+        loop {
+            let body = self.sign(url, payload)?;
+            let result = self
+                .agent
+                .post(url)
+                .set("Content-Type", "application/jose+json")
+                .send_string(&body.to_string());
+            let response = match result {
+                Ok(response) => response,
+                Err(ureq::Error::Status(_code, response)) => response,
+                Err(e) => return Err(e.into()),
+            };
+            if let Some(nonce) = response.header("Replay-Nonce") {
+                self.nonce = Some(nonce.to_string());
+            }
+            if response.status() < 400 {
+                return Ok(response);
+            }
+
+            let status = response.status();
+            let problem = response.into_string().unwrap_or_default();
+            // Nonces expire, the server sends a fresh one along with the error
+            if !retried && problem.contains("urn:ietf:params:acme:error:badNonce") {
+                retried = true;
+                continue;
+            }
+            return Err(AcmeError::Problem(status, problem));
+        }
+    }
+
+    fn sign(&mut self, url: &str, payload: Option<&Value>) -> Result<Value, AcmeError> {
+        let nonce = match self.nonce.take() {
+            Some(nonce) => nonce,
+            None => self.new_nonce()?,
+        };
+        let mut protected = json!({ "alg": "ES256", "nonce": nonce, "url": url });
+        match &self.kid {
+            Some(kid) => protected["kid"] = json!(kid),
+            None => protected["jwk"] = self.jwk(),
+        }
+        let protected = encode(protected.to_string().as_bytes());
+        let payload = match payload {
+            Some(payload) => encode(payload.to_string().as_bytes()),
+            None => String::new(),
+        };
+        let signature = self
+            .key
+            .sign(&self.rng, format!("{}.{}", &protected, &payload).as_bytes())
+            .map_err(|e| AcmeError::Key(e.to_string()))?;
+        Ok(json!({
+            "protected": protected,
+            "payload": payload,
+            "signature": encode(signature.as_ref()),
+        }))
+    }
+
+    fn new_nonce(&self) -> Result<String, AcmeError> {
+        let response = self
+            .agent
+            .head(&self.directory.new_nonce)
+            .call()
+            .map_err(AcmeError::from)?;
+        response
+            .header("Replay-Nonce")
+            .map(|nonce| nonce.to_string())
+            .ok_or(AcmeError::MissingHeader("Replay-Nonce"))
+    }
+
+    fn jwk(&self) -> Value {
+        // Public key is 0x04 followed by the coordinates
+        let public = self.key.public_key().as_ref();
+        json!({
+            "crv": "P-256",
+            "kty": "EC",
+            "x": encode(&public[1..33]),
+            "y": encode(&public[33..65]),
+        })
+    }
+
+    /// RFC 7638 thumbprint of the account key, the members must be sorted.
+    fn thumbprint(&self) -> String {
+        let jwk = self.jwk();
+        let canonical = format!(
+            r#"{{"crv":"P-256","kty":"EC","x":"{}","y":"{}"}}"#,
+            jwk["x"].as_str().unwrap_or_default(),
+            jwk["y"].as_str().unwrap_or_default()
+        );
+        encode(digest::digest(&digest::SHA256, canonical.as_bytes()).as_ref())
+    }
+}
+
+/// Trusts the usual public CAs, and the one from `ACME_CA_CERT`, e.g. the root of a test server.
+fn client_config(ca_cert: Option<&std::path::Path>) -> Result<ClientConfig, AcmeError> {
+    let mut roots = RootCertStore::empty();
+    roots.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|anchor| {
+        OwnedTrustAnchor::from_subject_spki_name_constraints(
+            anchor.subject,
+            anchor.spki,
+            anchor.name_constraints,
+        )
+    }));
+    if let Some(path) = ca_cert {
+        let pem = std::fs::read(path).map_err(AcmeError::Read)?;
+        let certs = rustls_pemfile::certs(&mut pem.as_slice()).map_err(AcmeError::Read)?;
+        for cert in certs {
+            roots
+                .add(&Certificate(cert))
+                .map_err(|e| AcmeError::Key(e.to_string()))?;
+        }
+        info!("Trusting ACME server certificates from {}", path.display());
+    }
+    Ok(ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth())
+}
+
+fn encode(data: &[u8]) -> String {
+    base64::encode_config(data, base64::URL_SAFE_NO_PAD)
+}
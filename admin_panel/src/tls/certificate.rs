@@ -0,0 +1,150 @@
+use std::{io, path::Path};
+
+use time::{Date, Month};
+
+use crate::error::TlsError;
+
+/// PEM encoded certificate chain and its private key.
+pub struct CertificatePair {
+    pub cert: Vec<u8>,
+    pub key: Vec<u8>,
+}
+
+impl CertificatePair {
+    /// Returns `None` if either of the files doesn't exist.
+    pub async fn read(cert: &Path, key: &Path) -> Result<Option<Self>, TlsError> {
+        let cert = match tokio::fs::read(cert).await {
+            Ok(cert) => cert,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(TlsError::Read(cert.display().to_string(), e)),
+        };
+        let key = match tokio::fs::read(key).await {
+            Ok(key) => key,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(TlsError::Read(key.display().to_string(), e)),
+        };
+        Ok(Some(Self { cert, key }))
+    }
+
+    pub async fn write(&self, cert: &Path, key: &Path) -> Result<(), TlsError> {
+        if let Some(parent) = cert.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(|e| TlsError::Write(parent.display().to_string(), e))?;
+        }
+        tokio::fs::write(cert, &self.cert)
+            .await
+            .map_err(|e| TlsError::Write(cert.display().to_string(), e))?;
+        tokio::fs::write(key, &self.key)
+            .await
+            .map_err(|e| TlsError::Write(key.display().to_string(), e))
+    }
+
+    /// Expiration time of the first certificate in the chain, as a unix timestamp.
+    pub fn not_after(&self) -> Option<i64> {
+        let certs = rustls_pemfile::certs(&mut self.cert.as_slice()).ok()?;
+        not_after(certs.first()?)
+    }
+}
+
+pub fn self_signed(domain: &str) -> Result<CertificatePair, TlsError> {
+    let cert = rcgen::generate_simple_self_signed(vec![domain.to_string()])?;
+    Ok(CertificatePair {
+        cert: cert.serialize_pem()?.into_bytes(),
+        key: cert.serialize_private_key_pem().into_bytes(),
+    })
+}
+
+/// Reads `notAfter` from a DER encoded X.509 certificate.
+pub fn not_after(der: &[u8]) -> Option<i64> {
+    let (_tag, certificate, _rest) = read_element(der)?;
+    let (_tag, mut tbs, _rest) = read_element(certificate)?;
+    // Version is optional and explicitly tagged
+    if tbs.first() == Some(&0xa0) {
+        tbs = read_element(tbs)?.2;
+    }
+    // Serial number, signature algorithm and issuer come before validity
+    for _ in 0..3 {
+        tbs = read_element(tbs)?.2;
+    }
+    let (_tag, validity, _rest) = read_element(tbs)?;
+    let (_tag, _not_before, rest) = read_element(validity)?;
+    let (tag, not_after, _rest) = read_element(rest)?;
+    let text = std::str::from_utf8(not_after).ok()?;
+    match tag {
+        0x17 => parse_time(text, true),
+        0x18 => parse_time(text, false),
+        _ => None,
+    }
+}
+
+/// Returns the tag, the content and the rest of the data.
+fn read_element(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let tag = *data.first()?;
+    let first = *data.get(1)?;
+    let (length, header) = if first < 0x80 {
+        (first as usize, 2)
+    } else {
+        let count = (first & 0x7f) as usize;
+        if count == 0 || count > 4 {
+            return None;
+        }
+        let bytes = data.get(2..2 + count)?;
+        let length = bytes
+            .iter()
+            .fold(0usize, |acc, byte| (acc << 8) | *byte as usize);
+        (length, 2 + count)
+    };
+    let content = data.get(header..header + length)?;
+    Some((tag, content, &data[header + length..]))
+}
+
+/// `UTCTime` is `YYMMDDHHMMSSZ`, `GeneralizedTime` is `YYYYMMDDHHMMSSZ`.
+fn parse_time(text: &str, short_year: bool) -> Option<i64> {
+    let text = text.strip_suffix('Z')?;
+    let (year, rest) = if short_year {
+        let year: i32 = text.get(..2)?.parse().ok()?;
+        (
+            if year >= 50 { 1900 + year } else { 2000 + year },
+            &text[2..],
+        )
+    } else {
+        (text.get(..4)?.parse().ok()?, &text[4..])
+    };
+    let field = |index: usize| -> Option<u8> { rest.get(index * 2..index * 2 + 2)?.parse().ok() };
+    let month = Month::try_from(field(0)?).ok()?;
+    let date = Date::from_calendar_date(year, month, field(1)?).ok()?;
+    let time = date.with_hms(field(2)?, field(3)?, field(4)?).ok()?;
+    Some(time.assume_utc().unix_timestamp())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn certificate(year: i32) -> Vec<u8> {
+        let mut params = rcgen::CertificateParams::new(vec!["localhost".to_string()]);
+        params.not_after = rcgen::date_time_ymd(year, 5, 17);
+        let cert = rcgen::Certificate::from_params(params).unwrap();
+        cert.serialize_der().unwrap()
+    }
+
+    #[test]
+    fn reads_utc_time() {
+        let expected = rcgen::date_time_ymd(2031, 5, 17).unix_timestamp();
+        assert_eq!(not_after(&certificate(2031)), Some(expected));
+    }
+
+    #[test]
+    fn reads_generalized_time() {
+        let expected = rcgen::date_time_ymd(2055, 5, 17).unix_timestamp();
+        assert_eq!(not_after(&certificate(2055)), Some(expected));
+    }
+
+    #[test]
+    fn reads_pem_chain() {
+        let pair = self_signed("localhost").unwrap();
+        assert!(pair.not_after().is_some());
+        assert_eq!(not_after(b"\x30\x05\x30"), None);
+    }
+}
@@ -0,0 +1,223 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
+
+use axum_server::tls_rustls::RustlsConfig;
+use log::{error, info, warn};
+use tokio::{task::spawn_blocking, time::sleep};
+
+use crate::error::TlsError;
+
+use self::{
+    acme::AcmeClient,
+    certificate::{self_signed, CertificatePair},
+};
+
+pub mod acme;
+pub mod certificate;
+
+static TLS_DIRECTORY: &str = "/data/tls";
+/// Certificates are renewed when they expire in less than that.
+const RENEW_BEFORE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+const CHECK_INTERVAL: Duration = Duration::from_secs(12 * 60 * 60);
+const RETRY_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Clone)]
+pub struct AcmeSettings {
+    pub domain: String,
+    pub email: Option<String>,
+    /// URL of the directory of the ACME server.
+    pub directory: String,
+    /// Additional root certificate to trust, when talking to the ACME server.
+    pub ca_cert: Option<PathBuf>,
+}
+
+#[derive(Clone)]
+pub struct TlsSettings {
+    /// User supplied certificate chain and key, take precedence over ACME.
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+    pub acme: Option<AcmeSettings>,
+}
+
+/// Key authorizations of the pending HTTP-01 challenges, by token.
+#[derive(Clone, Default)]
+pub struct Challenges(Arc<Mutex<HashMap<String, String>>>);
+
+impl Challenges {
+    pub fn insert(&self, token: String, key_authorization: String) {
+        let mut challenges = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        challenges.insert(token, key_authorization);
+    }
+
+    pub fn remove(&self, token: &str) {
+        let mut challenges = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        challenges.remove(token);
+    }
+
+    pub fn get(&self, token: &str) -> Option<String> {
+        let challenges = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        challenges.get(token).cloned()
+    }
+}
+
+/// Uses the user supplied certificate, then the one issued by ACME earlier,
+/// and falls back to a self-signed certificate until ACME issues a new one.
+pub async fn load(settings: &TlsSettings) -> Result<RustlsConfig, TlsError> {
+    match (&settings.cert, &settings.key) {
+        (Some(cert), Some(key)) => {
+            info!("Using TLS certificate from {}", cert.display());
+            let pair = CertificatePair::read(cert, key)
+                .await?
+                .ok_or_else(|| TlsError::NotFound(cert.display().to_string()))?;
+            return config(pair).await;
+        }
+        (None, None) => {}
+        _ => return Err(TlsError::IncompletePair),
+    }
+    if settings.acme.is_some() {
+        let (cert, key) = acme_paths();
+        if let Some(pair) = CertificatePair::read(&cert, &key).await? {
+            info!("Using TLS certificate issued by ACME");
+            return config(pair).await;
+        }
+    }
+
+    let (cert, key) = paths("self-signed");
+    let pair = match CertificatePair::read(&cert, &key).await? {
+        Some(pair) => pair,
+        None => {
+            let domain = match &settings.acme {
+                Some(acme) => acme.domain.as_str(),
+                None => "localhost",
+            };
+            info!("Generating self-signed TLS certificate for {}...", domain);
+            let pair = self_signed(domain)?;
+            pair.write(&cert, &key).await?;
+            pair
+        }
+    };
+    warn!("Using self-signed TLS certificate, browsers will show a warning");
+    config(pair).await
+}
+
+/// Issues the certificate if there is none yet, and renews it before it expires.
+pub async fn keep_renewed(settings: AcmeSettings, config: RustlsConfig, challenges: Challenges) {
+    let (cert, key) = acme_paths();
+    loop {
+        let expires = match CertificatePair::read(&cert, &key).await {
+            Ok(pair) => pair.and_then(|pair| pair.not_after()),
+            Err(e) => {
+                error!("Couldn't read ACME certificate: {}", &e);
+                None
+            }
+        };
+        let now = time::OffsetDateTime::now_utc().unix_timestamp();
+        let due = match expires {
+            Some(expires) => expires - now < RENEW_BEFORE.as_secs() as i64,
+            None => true,
+        };
+        if !due {
+            sleep(CHECK_INTERVAL).await;
+            continue;
+        }
+
+        info!("Requesting TLS certificate for {}...", &settings.domain);
+        let installed = match issue(settings.clone(), challenges.clone()).await {
+            Ok(pair) => match pair.write(&cert, &key).await {
+                Ok(_r) => match config.reload_from_pem(pair.cert, pair.key).await {
+                    Ok(_r) => {
+                        info!("Installed new TLS certificate");
+                        true
+                    }
+                    Err(e) => {
+                        error!("Couldn't install new TLS certificate: {}", &e);
+                        false
+                    }
+                },
+                Err(e) => {
+                    error!("Couldn't save new TLS certificate: {}", &e);
+                    false
+                }
+            },
+            Err(e) => {
+                error!("Couldn't get TLS certificate: {}", &e);
+                false
+            }
+        };
+        // Otherwise a persistent error would hit the rate limits of the ACME server
+        if !installed {
+            sleep(RETRY_INTERVAL).await;
+        }
+    }
+}
+
+async fn issue(
+    settings: AcmeSettings,
+    challenges: Challenges,
+) -> Result<CertificatePair, TlsError> {
+    let account_path = Path::new(TLS_DIRECTORY).join("acme-account.key");
+    let account_key = match tokio::fs::read(&account_path).await {
+        Ok(key) => key,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let key = AcmeClient::generate_account_key()?;
+            tokio::fs::create_dir_all(TLS_DIRECTORY)
+                .await
+                .map_err(|e| TlsError::Write(TLS_DIRECTORY.to_string(), e))?;
+            tokio::fs::write(&account_path, &key)
+                .await
+                .map_err(|e| TlsError::Write(account_path.display().to_string(), e))?;
+            key
+        }
+        Err(e) => return Err(TlsError::Read(account_path.display().to_string(), e)),
+    };
+
+    let pair = spawn_blocking(move || {
+        let mut client = AcmeClient::new(&settings, &account_key)?;
+        client.register(settings.email.as_deref())?;
+        client.order(&settings.domain, &challenges)
+    })
+    .await??;
+    Ok(pair)
+}
+
+async fn config(pair: CertificatePair) -> Result<RustlsConfig, TlsError> {
+    RustlsConfig::from_pem(pair.cert, pair.key)
+        .await
+        .map_err(TlsError::Invalid)
+}
+
+fn acme_paths() -> (PathBuf, PathBuf) {
+    paths("acme")
+}
+
+fn paths(name: &str) -> (PathBuf, PathBuf) {
+    let directory = Path::new(TLS_DIRECTORY);
+    (
+        directory.join(format!("{}.crt", name)),
+        directory.join(format!("{}.key", name)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn rejects_incomplete_pair() {
+        for (cert, key) in [(Some("cert.pem"), None), (None, Some("key.pem"))] {
+            let settings = TlsSettings {
+                cert: cert.map(PathBuf::from),
+                key: key.map(PathBuf::from),
+                acme: None,
+            };
+            assert!(matches!(
+                load(&settings).await,
+                Err(TlsError::IncompletePair)
+            ));
+        }
+    }
+}
@@ -0,0 +1,345 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Mutex, PoisonError},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::info;
+use rand::RngCore;
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::TotpError,
+    password::{constant_time_eq, random_string, sha256_hex},
+    store,
+};
+
+const STEP_SECONDS: u64 = 30;
+const DIGITS: u32 = 6;
+const SECRET_LENGTH: usize = 20;
+const RECOVERY_CODES: usize = 10;
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+#[derive(Serialize, Deserialize)]
+struct TotpEntry {
+    /// Base32 encoded secret.
+    secret: String,
+    /// SHA-256 of unused recovery codes.
+    recovery_codes: Vec<String>,
+    /// The last time step that was used to log in, so that the same code can't be used twice,
+    /// even after a restart.
+    #[serde(default)]
+    last_step: u64,
+}
+
+#[derive(Serialize)]
+pub struct Enrollment {
+    /// `otpauth://` URI for the authenticator apps.
+    pub uri: String,
+    /// Base32 encoded secret, for the apps that can't read the URI.
+    pub secret: String,
+}
+
+/// RFC 6238 second factor of the users, persisted in a JSON file.
+pub struct TotpStore {
+    path: PathBuf,
+    entries: Mutex<HashMap<String, TotpEntry>>,
+    /// Secrets that were shown to the users, but weren't confirmed yet.
+    pending: Mutex<HashMap<String, Vec<u8>>>,
+}
+
+impl TotpStore {
+    pub fn load(path: PathBuf) -> Result<Self, TotpError> {
+        let entries = store::load(&path)?;
+        Ok(Self {
+            path,
+            entries: Mutex::new(entries),
+            pending: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn is_enabled(&self, username: &str) -> bool {
+        let entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        entries.contains_key(&username.to_lowercase())
+    }
+
+    /// Generates a new secret, which is used only after it's confirmed with a code.
+    pub fn start_enrollment(&self, username: &str) -> Result<Enrollment, TotpError> {
+        if self.is_enabled(username) {
+            return Err(TotpError::AlreadyEnabled);
+        }
+        let mut secret = vec![0u8; SECRET_LENGTH];
+        rand::thread_rng().fill_bytes(&mut secret);
+        let encoded = base32_encode(&secret);
+        let uri = format!(
+            "otpauth://totp/Minecraft%20admin%20panel:{}?secret={}&issuer=Minecraft%20admin%20panel&algorithm=SHA1&digits={}&period={}",
+            encode_uri_component(username),
+            &encoded,
+            DIGITS,
+            STEP_SECONDS
+        );
+        let mut pending = self.pending.lock().unwrap_or_else(PoisonError::into_inner);
+        pending.insert(username.to_lowercase(), secret);
+        Ok(Enrollment {
+            uri,
+            secret: encoded,
+        })
+    }
+
+    /// Enables the second factor, if the code matches the pending secret. Returns recovery codes,
+    /// which are never shown again.
+    pub fn finish_enrollment(&self, username: &str, code: &str) -> Result<Vec<String>, TotpError> {
+        let key = username.to_lowercase();
+        let secret = {
+            let pending = self.pending.lock().unwrap_or_else(PoisonError::into_inner);
+            pending.get(&key).cloned().ok_or(TotpError::NotEnrolled)?
+        };
+        let step = match verify_code(&secret, code, current_step()) {
+            Some(step) => step,
+            None => return Err(TotpError::InvalidCode),
+        };
+
+        let recovery_codes: Vec<String> = (0..RECOVERY_CODES).map(|_| recovery_code()).collect();
+        let entry = TotpEntry {
+            secret: base32_encode(&secret),
+            recovery_codes: recovery_codes
+                .iter()
+                .map(|code| hash_recovery_code(code))
+                .collect(),
+            last_step: step,
+        };
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        entries.insert(key.clone(), entry);
+        self.save(&entries)?;
+        self.pending
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&key);
+        info!("<{}> enabled two-factor authentication", username);
+        Ok(recovery_codes)
+    }
+
+    /// Accepts either a code from the authenticator app, or one of the recovery codes.
+    pub fn verify(&self, username: &str, code: &str) -> Result<bool, TotpError> {
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        let entry = match entries.get_mut(&username.to_lowercase()) {
+            Some(entry) => entry,
+            None => return Ok(false),
+        };
+
+        let secret = base32_decode(&entry.secret).ok_or(TotpError::CorruptedSecret)?;
+        if let Some(step) = verify_code(&secret, code, current_step()) {
+            if step <= entry.last_step {
+                return Ok(false);
+            }
+            entry.last_step = step;
+            self.save(&entries)?;
+            return Ok(true);
+        }
+
+        let hash = hash_recovery_code(code);
+        let position = entry
+            .recovery_codes
+            .iter()
+            .position(|stored| constant_time_eq(stored.as_bytes(), hash.as_bytes()));
+        match position {
+            Some(position) => {
+                entry.recovery_codes.remove(position);
+                info!(
+                    "<{}> used a recovery code, {} left",
+                    username,
+                    entry.recovery_codes.len()
+                );
+                self.save(&entries)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    pub fn disable(&self, username: &str, code: &str) -> Result<(), TotpError> {
+        if !self.verify(username, code)? {
+            return Err(TotpError::InvalidCode);
+        }
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        entries.remove(&username.to_lowercase());
+        self.save(&entries)?;
+        info!("<{}> disabled two-factor authentication", username);
+        Ok(())
+    }
+
+    fn save(&self, entries: &HashMap<String, TotpEntry>) -> Result<(), TotpError> {
+        Ok(store::save(&self.path, entries)?)
+    }
+}
+
+fn current_step() -> u64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    now / STEP_SECONDS
+}
+
+/// Codes from the previous and the next time steps are accepted too, in case the clocks are off.
+/// Returns the step that matched.
+fn verify_code(secret: &[u8], code: &str, step: u64) -> Option<u64> {
+    let code = code.trim();
+    if code.len() != DIGITS as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let mut matched = None;
+    for candidate in [step.saturating_sub(1), step, step + 1] {
+        let expected = format!(
+            "{:0width$}",
+            code_at(secret, candidate),
+            width = DIGITS as usize
+        );
+        // Check all steps, so that the time doesn't depend on which one matched
+        if constant_time_eq(expected.as_bytes(), code.as_bytes()) {
+            matched = Some(candidate);
+        }
+    }
+    matched
+}
+
+/// HOTP value for the counter, as described in RFC 4226.
+fn code_at(secret: &[u8], counter: u64) -> u32 {
+    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, secret);
+    let tag = hmac::sign(&key, &counter.to_be_bytes());
+    let hash = tag.as_ref();
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let mut bytes = [0u8; 4];
+    bytes.clone_from_slice(&hash[offset..offset + 4]);
+    (u32::from_be_bytes(bytes) & 0x7fff_ffff) % 10u32.pow(DIGITS)
+}
+
+fn recovery_code() -> String {
+    let code = random_string(10).to_ascii_lowercase();
+    format!("{}-{}", &code[..5], &code[5..])
+}
+
+fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .trim()
+        .chars()
+        .filter(|c| *c != '-')
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    sha256_hex(&normalized)
+}
+
+fn base32_encode(data: &[u8]) -> String {
+    let mut result = String::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for byte in data {
+        buffer = (buffer << 8) | *byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            result.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        result.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    result
+}
+
+fn base32_decode(text: &str) -> Option<Vec<u8>> {
+    let mut result = Vec::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for c in text.trim_end_matches('=').bytes() {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|a| *a == c.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            result.push((buffer >> bits) as u8);
+        }
+    }
+    Some(result)
+}
+
+fn encode_uri_component(text: &str) -> String {
+    text.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_rfc_6238_test_vectors() {
+        let secret = b"12345678901234567890";
+        // RFC lists 8 digit codes, these are their last 6 digits
+        assert_eq!(code_at(secret, 59 / STEP_SECONDS), 287082);
+        assert_eq!(code_at(secret, 1111111109 / STEP_SECONDS), 81804);
+        assert_eq!(code_at(secret, 1234567890 / STEP_SECONDS), 5924);
+        assert_eq!(code_at(secret, 20000000000 / STEP_SECONDS), 353130);
+    }
+
+    #[test]
+    fn accepts_adjacent_steps() {
+        let secret = b"12345678901234567890";
+        let step = 1111111109 / STEP_SECONDS;
+        assert_eq!(verify_code(secret, "081804", step), Some(step));
+        assert_eq!(verify_code(secret, "081804", step + 1), Some(step));
+        assert_eq!(verify_code(secret, "081804", step + 2), None);
+        assert_eq!(verify_code(secret, "81804", step), None);
+    }
+
+    #[test]
+    fn base32_round_trip() {
+        assert_eq!(
+            base32_encode(b"12345678901234567890"),
+            "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ"
+        );
+        let data = [0u8, 1, 2, 250, 251, 252, 253];
+        assert_eq!(base32_decode(&base32_encode(&data)), Some(data.to_vec()));
+    }
+
+    #[test]
+    fn rejects_replayed_codes_after_restart() {
+        let path = std::env::temp_dir().join(format!("totp-{}.json", std::process::id()));
+        let secret = b"12345678901234567890";
+        let store = TotpStore::load(path.clone()).unwrap();
+        {
+            let mut entries = store.entries.lock().unwrap();
+            let entry = TotpEntry {
+                secret: base32_encode(secret),
+                recovery_codes: Vec::new(),
+                last_step: 0,
+            };
+            entries.insert("steve".to_string(), entry);
+        }
+        let code = format!("{:06}", code_at(secret, current_step()));
+        assert!(store.verify("Steve", &code).unwrap());
+        assert!(!store.verify("Steve", &code).unwrap());
+
+        let reloaded = TotpStore::load(path.clone()).unwrap();
+        assert!(!reloaded.verify("steve", &code).unwrap());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn normalizes_recovery_codes() {
+        assert_eq!(
+            hash_recovery_code("abcde-12345"),
+            hash_recovery_code(" ABCDE12345 ")
+        );
+    }
+}
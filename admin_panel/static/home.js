@@ -0,0 +1,640 @@
+function successToast(msg) {
+    $.toast({
+        heading: 'Success',
+        text: msg,
+        icon: 'success',
+        showHideTransition: 'slide',
+        stack: 5,
+        position: 'top-right',
+        textAlign: 'left',
+    });
+}
+
+function warningToast(msg) {
+    $.toast({
+        heading: 'Warning',
+        text: msg,
+        icon: 'warning',
+        showHideTransition: 'slide',
+        stack: 5,
+        position: 'top-right',
+        textAlign: 'left',
+    });
+}
+
+function errorToast(msg) {
+    $.toast({
+        heading: 'Error',
+        text: msg,
+        icon: 'error',
+        showHideTransition: 'slide',
+        stack: 5,
+        position: 'top-right',
+        textAlign: 'left',
+    });
+}
+
+function send(url, type, data, ping) {
+    $.ajax({
+        type: type,
+        url: url,
+        data: data,
+        contentType: "application/json",
+        dataType: "json",
+        timeout: 2000,
+        success: function(data) {
+            if (data.success === true) {
+                $("#status").removeClass();
+                if (data.response.Status !== undefined && data.response.Status != null) {
+                    let snapshot = data.response.Status.snapshot;
+                    $("#status").addClass('online');
+                    $("#online").text(snapshot.online + "/" + snapshot.max_players);
+                    let process = data.response.Status.process;
+                    let title = snapshot.version;
+                    if (process.crashes > 0) {
+                        title += ", crashes: " + process.crashes + ", last exit: " + process.last_exit_status;
+                    }
+                    $("#online").attr("title", title);
+                    $("#players").text(snapshot.players.join(", "));
+                    let ticks = data.response.Status.ticks;
+                    if (ticks.length > 0) {
+                        let last = ticks[ticks.length - 1];
+                        let lowest = Math.min(...ticks.map(function(tick) { return tick.tps; }));
+                        $("#tps").text("TPS: " + last.tps.toFixed(1) + ", MSPT: " + last.mspt.toFixed(1));
+                        $("#tps").attr("title", "Lowest TPS in the last " + ticks.length + " samples: " + lowest.toFixed(1));
+                    } else {
+                        $("#tps").text("");
+                    }
+                } else if (data.response.Ok !== undefined && data.response.Ok != null) {
+                    $("#status").addClass('online');
+                    if (ping) {
+                        $("#online").text(data.response.Ok.response);
+                    } else {
+                        successToast(data.response.Ok.response);
+                        loadRosters($("nav a.current").data("form"));
+                    }
+                } else if (data.response === "NotReady") {
+                    $("#status").addClass('preparing');
+                    $("#online").text("preparing");
+                    $("#players").text("");
+                    $("#tps").text("");
+                    if (!ping) {
+                        warningToast("Server is not ready yet");
+                    }
+                } else if (data.response === "Sleeping") {
+                    $("#status").addClass('preparing');
+                    $("#online").text("sleeping");
+                    $("#players").text("");
+                    $("#tps").text("");
+                    if (!ping) {
+                        warningToast("Server is sleeping, join the game to wake it up");
+                    }
+                } else if (data.response.Err !== undefined && data.response.Err != null) {
+                    $("#status").addClass('unknown');
+                    if (ping) {
+                        $("#online").text("unknown");
+                    } else {
+                        errorToast(data.response.Err.error);
+                    }
+                } else {
+                    warningToast("Unknown response code: " + data.response);
+                }
+            } else {
+                errorToast(["Couldn't perform the action:", data.error]);
+            }
+        },
+        error: errorHandler
+    });
+}
+
+const rosters = {
+    banForm: [
+        ["/ban", "banList", [["Banned player", "name"], ["Reason", "reason"], ["Created", "created"], ["Expires", "expires"], ["Remaining", "remaining", formatRemaining]]],
+        ["/ban-ip", "banIpList", [["Banned IP", "ip"], ["Reason", "reason"], ["Created", "created"], ["Expires", "expires"]]],
+    ],
+    whitelistForm: [
+        ["/whitelist", "whitelistList", [["Whitelisted player", "name"], ["UUID", "uuid"]]],
+    ],
+    operatorForm: [
+        ["/op", "opList", [["Operator", "name"], ["Level", "level"], ["UUID", "uuid"]]],
+    ],
+};
+
+// Only temporary bans have the remaining time
+function formatRemaining(seconds) {
+    if (seconds === null || seconds === undefined) {
+        return "";
+    }
+    let days = Math.floor(seconds / 86400);
+    let hours = Math.floor(seconds % 86400 / 3600);
+    let minutes = Math.ceil(seconds % 3600 / 60);
+    if (minutes === 60) {
+        hours += 1;
+        minutes = 0;
+    }
+    let parts = [];
+    if (days > 0) {
+        parts.push(days + "d");
+    }
+    if (hours > 0) {
+        parts.push(hours + "h");
+    }
+    if (minutes > 0 || parts.length === 0) {
+        parts.push(minutes + "m");
+    }
+    return parts.join(" ");
+}
+
+function loadRoster(url, tableId, columns) {
+    $.ajax({
+        type: "GET",
+        url: url,
+        dataType: "json",
+        timeout: 2000,
+        success: function(data) {
+            let table = $("#" + tableId);
+            table.empty();
+            if (data.success !== true) {
+                errorToast(["Couldn't load the list:", data.error]);
+                return;
+            }
+            let header = $("<tr>");
+            columns.forEach(function(column) {
+                header.append($("<th>").text(column[0]));
+            });
+            table.append(header);
+            data.response.forEach(function(entry) {
+                let row = $("<tr>");
+                columns.forEach(function(column) {
+                    let value = entry[column[1]];
+                    row.append($("<td>").text(column[2] ? column[2](value) : value));
+                });
+                table.append(row);
+            });
+        },
+        error: function(jqXHR) {
+            errorHandler(jqXHR);
+        }
+    });
+}
+
+function loadRosters(formId) {
+    (rosters[formId] || []).forEach(function(roster) {
+        loadRoster(roster[0], roster[1], roster[2]);
+    });
+}
+
+let restoreTimer = null;
+
+function loadBackups() {
+    $.ajax({
+        type: "GET",
+        url: "/backups",
+        dataType: "json",
+        timeout: 2000,
+        success: function(data) {
+            let table = $("#backupList");
+            table.empty();
+            if (data.success !== true) {
+                errorToast(["Couldn't load the list:", data.error]);
+                return;
+            }
+            table.append($("<tr>")
+                .append($("<th>").text("Archive"))
+                .append($("<th>").text("Size, MB"))
+                .append($("<th>")));
+            data.response.forEach(function(backup) {
+                let button = $("<button>").text("Restore").on("click", function(event) {
+                    event.preventDefault();
+                    if (!confirm("The server will be stopped and the world will be replaced with " + backup.name + ". Continue?")) {
+                        return;
+                    }
+                    send("/restore", "POST", JSON.stringify({ "name": backup.name }), false);
+                    watchRestore();
+                });
+                table.append($("<tr>")
+                    .append($("<td>").text(backup.name))
+                    .append($("<td>").text((backup.size / 1048576).toFixed(1)))
+                    .append($("<td>").append(button)));
+            });
+        },
+        error: errorHandler
+    });
+}
+
+function loadRestoreStatus() {
+    $.ajax({
+        type: "GET",
+        url: "/restore",
+        dataType: "json",
+        timeout: 2000,
+        success: function(data) {
+            if (data.success !== true) {
+                return;
+            }
+            let stage = data.response;
+            let text = "";
+            let finished = true;
+            if (stage.Stopping !== undefined) {
+                text = "Stopping the server to restore " + stage.Stopping.name + "...";
+                finished = false;
+            } else if (stage.Unpacking !== undefined) {
+                text = "Unpacking " + stage.Unpacking.name + "...";
+                finished = false;
+            } else if (stage.Starting !== undefined) {
+                text = "Starting the server with " + stage.Starting.name + "...";
+                finished = false;
+            } else if (stage.Done !== undefined) {
+                text = "Restored " + stage.Done.name;
+            } else if (stage.Failed !== undefined) {
+                text = "Couldn't restore " + stage.Failed.name + ": " + stage.Failed.error;
+            }
+            $("#restoreStatus").text(text);
+            if (finished && restoreTimer !== null) {
+                clearInterval(restoreTimer);
+                restoreTimer = null;
+            }
+        },
+        error: errorHandler
+    });
+}
+
+function watchRestore() {
+    loadRestoreStatus();
+    if (restoreTimer === null) {
+        restoreTimer = setInterval(loadRestoreStatus, 2000);
+    }
+}
+
+function loadTotpStatus() {
+    $.ajax({
+        type: "GET",
+        url: "/totp",
+        dataType: "json",
+        timeout: 2000,
+        success: function(data) {
+            if (data.success !== true) {
+                return;
+            }
+            let enabled = data.response.enabled;
+            $("#totpStatus").text(enabled
+                ? "Two-factor authentication is enabled. Enter a code to disable it."
+                : "Two-factor authentication is disabled.");
+            $("#totpEnroll").toggle(!enabled);
+            $("#totpDisable").toggle(enabled);
+            $("#totpConfirm").toggle(!enabled && $("#totpSetup").is(":visible"));
+        },
+        error: errorHandler
+    });
+}
+
+function sendTotp(type, code, success) {
+    sendJson("/totp", type, code === null ? null : JSON.stringify({ "code": code }), success);
+}
+
+function sendJson(url, type, data, success) {
+    $.ajax({
+        type: type,
+        url: url,
+        data: data,
+        contentType: "application/json",
+        dataType: "json",
+        timeout: 2000,
+        success: success,
+        error: function(jqXHR) {
+            if (jqXHR.responseJSON && jqXHR.responseJSON.error) {
+                errorToast(jqXHR.responseJSON.error);
+            } else {
+                errorHandler(jqXHR);
+            }
+        }
+    });
+}
+
+function loadTokens() {
+    sendJson("/tokens", "GET", null, function(data) {
+        let table = $("#tokenList");
+        table.empty();
+        table.append($("<tr>")
+            .append($("<th>").text("Name"))
+            .append($("<th>").text("Owner"))
+            .append($("<th>").text("Scopes"))
+            .append($("<th>").text("Expires"))
+            .append($("<th>")));
+        data.response.forEach(function(token) {
+            let button = $("<button>").text("Revoke").on("click", function(event) {
+                event.preventDefault();
+                if (!confirm("Scripts using " + token.name + " will stop working. Continue?")) {
+                    return;
+                }
+                sendJson("/tokens", "DELETE", JSON.stringify({ "id": token.id }), function() {
+                    successToast("Token is revoked");
+                    loadTokens();
+                });
+            });
+            let expires = token.expires === null ? "Never" : new Date(token.expires * 1000).toLocaleString();
+            table.append($("<tr>")
+                .append($("<td>").text(token.name))
+                .append($("<td>").text(token.owner))
+                .append($("<td>").text(token.scopes.join(", ")))
+                .append($("<td>").text(expires))
+                .append($("<td>").append(button)));
+        });
+    });
+}
+
+let consoleSocket = null;
+
+function appendConsole(line) {
+    let output = $("#consoleOutput");
+    output.append(document.createTextNode(line + "\n"));
+    output.scrollTop(output[0].scrollHeight);
+}
+
+function openConsole() {
+    if (consoleSocket !== null && consoleSocket.readyState <= WebSocket.OPEN) {
+        return;
+    }
+    let protocol = window.location.protocol === "https:" ? "wss://" : "ws://";
+    consoleSocket = new WebSocket(protocol + window.location.host + "/console");
+    consoleSocket.onmessage = function(event) {
+        let data = JSON.parse(event.data);
+        if (data.success !== true) {
+            appendConsole(data.error);
+        } else if (data.response.Ok !== undefined && data.response.Ok != null) {
+            appendConsole(data.response.Ok.response);
+        } else if (data.response.Err !== undefined && data.response.Err != null) {
+            appendConsole(data.response.Err.error);
+        } else if (data.response === "NotReady") {
+            appendConsole("Server is not ready yet");
+        } else if (data.response === "Sleeping") {
+            appendConsole("Server is sleeping, join the game to wake it up");
+        } else {
+            appendConsole("Unknown response code: " + data.response);
+        }
+    };
+    consoleSocket.onclose = function() {
+        appendConsole("Console disconnected");
+    };
+}
+
+let logStream = null;
+
+function openLogStream() {
+    if (logStream !== null) {
+        return;
+    }
+    logStream = new EventSource("/logs/stream");
+    logStream.onmessage = function(event) {
+        appendConsole(event.data);
+    };
+    logStream.addEventListener("lagged", function(event) {
+        appendConsole("... " + event.data + " lines skipped");
+    });
+    $.ajax({
+        type: "GET",
+        url: "/logs?lines=200",
+        dataType: "json",
+        timeout: 2000,
+        success: function(data) {
+            if (data.success === true) {
+                $("#consoleOutput").prepend(document.createTextNode(data.response.map(function(line) {
+                    return line + "\n";
+                }).join("")));
+                let output = $("#consoleOutput");
+                output.scrollTop(output[0].scrollHeight);
+            }
+        },
+        error: errorHandler
+    });
+}
+
+let timer = setInterval(function() { send("/ping", "GET", null, true) }, 7000);
+
+$("nav a").on("click", function() {
+    $("nav a").each(function() {
+        $(this).removeClass("current");
+    })
+
+    $("main form").each(function() {
+        $(this).hide();
+    })
+
+    $(this).addClass("current");
+    let formId = $(this).data("form");
+    $("#" + formId).show();
+    if (formId === "consoleForm") {
+        openConsole();
+        openLogStream();
+    }
+    if (formId === "backupForm") {
+        loadBackups();
+        watchRestore();
+    }
+    if (formId === "totpForm") {
+        loadTotpStatus();
+    }
+    if (formId === "tokenForm") {
+        loadTokens();
+    }
+    loadRosters(formId);
+});
+
+$("nav a").first().click();
+
+let errorHandler = function(jqXHR) {
+    $("#status").removeClass();
+    $("#status").addClass('unknown');
+    $("#online").text("unknown");
+    if (jqXHR.responseText) {
+        errorToast(["Error while sending the request to the server:", jqXHR.responseText]);
+    } else {
+        errorToast("Error while sending the request to the server");
+    }
+    if (jqXHR.status === 401) {
+        window.location = '/';
+    }
+};
+
+$("#ban").on("click", function(event) {
+    if (!$("#banForm")[0].checkValidity()) {
+        return;
+    }
+    event.preventDefault();
+
+    let nickname = $("#banNickname").val();
+    let reason = $("#banReason").val();
+    let duration = $("#banDuration").val();
+    let data = JSON.stringify({
+        "nickname": nickname,
+        "reason": reason,
+        "duration": duration
+    });
+    send("/ban", "POST", data, false);
+});
+
+$("#unban").on("click", function(event) {
+    if (!$("#banForm")[0].checkValidity()) {
+        return;
+    }
+    event.preventDefault();
+
+    let nickname = $("#banNickname").val();
+    let data = JSON.stringify({
+        "nickname": nickname,
+    });
+    send("/ban", "DELETE", data, false);
+});
+
+$("#kick").on("click", function(event) {
+    if (!$("#kickForm")[0].checkValidity()) {
+        return;
+    }
+    event.preventDefault();
+
+    let nickname = $("#kickNickname").val();
+    let reason = $("#kickReason").val();
+    let data = JSON.stringify({
+        "nickname": nickname,
+        "reason": reason
+    });
+    send("/kick", "POST", data, false);
+});
+
+$("#add").on("click", function(event) {
+    if (!$("#whitelistForm")[0].checkValidity()) {
+        return;
+    }
+    event.preventDefault();
+
+    let nickname = $("#wlNickname").val();
+    let data = JSON.stringify({
+        "nickname": nickname
+    });
+    send("/whitelist", "POST", data, false);
+});
+
+$("#remove").on("click", function(event) {
+    if (!$("#whitelistForm")[0].checkValidity()) {
+        return;
+    }
+    event.preventDefault();
+
+    let nickname = $("#wlNickname").val();
+    let data = JSON.stringify({
+        "nickname": nickname,
+    });
+    send("/whitelist", "DELETE", data, false);
+});
+
+$("#op_add").on("click", function(event) {
+    if (!$("#operatorForm")[0].checkValidity()) {
+        return;
+    }
+    event.preventDefault();
+
+    let nickname = $("#opNickname").val();
+    let data = JSON.stringify({
+        "nickname": nickname
+    });
+    send("/op", "POST", data, false);
+});
+
+$("#de_op").on("click", function(event) {
+    if (!$("#operatorForm")[0].checkValidity()) {
+        return;
+    }
+    event.preventDefault();
+
+    let nickname = $("#opNickname").val();
+    let data = JSON.stringify({
+        "nickname": nickname,
+    });
+    send("/op", "DELETE", data, false);
+});
+
+$("#backupNow").on("click", function(event) {
+    event.preventDefault();
+    send("/backup", "POST", null, false);
+});
+
+$("#totpEnroll").on("click", function(event) {
+    event.preventDefault();
+    sendTotp("POST", null, function(data) {
+        $("#totpSecret").text(data.response.secret);
+        $("#totpUri").attr("href", data.response.uri);
+        $("#totpSetup").show();
+        $("#totpRecovery").hide();
+        loadTotpStatus();
+    });
+});
+
+$("#totpConfirm").on("click", function(event) {
+    if (!$("#totpForm")[0].checkValidity()) {
+        return;
+    }
+    event.preventDefault();
+    sendTotp("PUT", $("#totpCode").val(), function(data) {
+        $("#totpSetup").hide();
+        $("#totpCode").val("");
+        $("#totpRecovery")
+            .text("Recovery codes, each can be used once instead of a code. They won't be shown again:\n\n" + data.response.recovery_codes.join("\n"))
+            .show();
+        successToast("Two-factor authentication is enabled");
+        loadTotpStatus();
+    });
+});
+
+$("#totpDisable").on("click", function(event) {
+    if (!$("#totpForm")[0].checkValidity()) {
+        return;
+    }
+    event.preventDefault();
+    sendTotp("DELETE", $("#totpCode").val(), function() {
+        $("#totpCode").val("");
+        $("#totpRecovery").hide();
+        successToast("Two-factor authentication is disabled");
+        loadTotpStatus();
+    });
+});
+
+$("#tokenCreate").on("click", function(event) {
+    if (!$("#tokenForm")[0].checkValidity()) {
+        return;
+    }
+    event.preventDefault();
+
+    let scopes = $("input[name=tokenScope]:checked").map(function() {
+        return $(this).val();
+    }).get();
+    let days = $("#tokenExpires").val();
+    let data = JSON.stringify({
+        "name": $("#tokenName").val(),
+        "scopes": scopes,
+        "expires_in_days": days === "" ? null : parseInt(days, 10),
+    });
+    sendJson("/tokens", "POST", data, function(data) {
+        $("#tokenName").val("");
+        $("#tokenSecret")
+            .text("Copy the token now, it won't be shown again:\n\n" + data.response.secret)
+            .show();
+        loadTokens();
+    });
+});
+
+$("#consoleSend").on("click", function(event) {
+    if (!$("#consoleForm")[0].checkValidity()) {
+        return;
+    }
+    event.preventDefault();
+
+    if (consoleSocket === null || consoleSocket.readyState !== WebSocket.OPEN) {
+        errorToast("Console is not connected");
+        openConsole();
+        return;
+    }
+    let command = $("#consoleCommand").val();
+    appendConsole("> " + command);
+    consoleSocket.send(command);
+    $("#consoleCommand").val("");
+});